@@ -49,6 +49,10 @@ class AppDelegate: NSObject, NSApplicationDelegate {
                 // TODO: dispatch to appropriate editView based on obj["tab"]
                 self.appWindowController?.editView.updateSafe(update)
             }
+        } else if method == "def_style" {
+            if let style = params as? [String : AnyObject] {
+                self.appWindowController?.editView.defStyleSafe(style)
+            }
         }
     }
 
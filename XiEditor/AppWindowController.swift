@@ -66,7 +66,12 @@ class AppWindowController: NSWindowController {
         if filename == nil {
             saveDocumentAs(sender)
         } else {
-            editView.sendRpcAsync("save", params: ["filename": filename!])
+            let result = editView.sendRpc("save", params: ["filename": filename!]) as? [String: AnyObject]
+            if let error = result?["error"] as? String {
+                let alert = NSAlert()
+                alert.messageText = error
+                alert.runModal()
+            }
         }
     }
     
@@ -68,6 +68,8 @@ class EditView: NSView {
 
     // basically a cache of lines, indexed by line number
     var lineMap: [Int: [AnyObject]] = [:]
+    // lines requested from the core but not yet received
+    var requestedLines = Set<Int>()
     var height: Int = 0
 
     var widthConstraint: NSLayoutConstraint?
@@ -81,7 +83,8 @@ class EditView: NSView {
     var linespace: CGFloat
     var fontWidth: CGFloat
 
-    let selcolor: NSColor
+    // the styles defined by the core with def_style, by id
+    var styles: [Int: [String: AnyObject]] = [:]
 
     // visible scroll region, exclusive of lastLine
     var firstLine: Int = 0
@@ -93,7 +96,8 @@ class EditView: NSView {
 
     // magic for accepting updates from other threads
     var updateQueue: dispatch_queue_t
-    var pendingUpdate: [String: AnyObject]? = nil
+    // each update is relative to the previous one, so none may be dropped
+    var pendingUpdates: [[String: AnyObject]] = []
 
     var currentEvent: NSEvent?
 
@@ -106,7 +110,6 @@ class EditView: NSView {
         baseline = ceil(ascent)
         attributes = [String(kCTFontAttributeName): font]
         fontWidth = getFontWidth(font)
-        selcolor = NSColor(colorLiteralRed: 0.7, green: 0.85, blue: 0.99, alpha: 1.0)
         updateQueue = dispatch_queue_create("com.levien.xi.update", DISPATCH_QUEUE_SERIAL)
         super.init(frame: frameRect)
         widthConstraint = NSLayoutConstraint(item: self, attribute: .Width, relatedBy: .GreaterThanOrEqual, toItem: nil, attribute: .Width, multiplier: 1, constant: 400)
@@ -166,26 +169,20 @@ class EditView: NSView {
             let randcolor = NSColor(colorLiteralRed: Float(drand48()), green: Float(drand48()), blue: Float(drand48()), alpha: 1.0)
             attrString.addAttribute(NSForegroundColorAttributeName, value: randcolor, range: NSMakeRange(0, s.utf16.count))
             */
-            var cursor: Int? = nil;
+            var cursors: [Int] = []
             for attr in line!.dropFirst() {
                 let attr = attr as! [AnyObject]
                 let type = attr[0] as! String
                 if type == "cursor" {
-                    cursor = attr[1] as? Int
-                } else if type == "sel" {
+                    cursors.append(attr[1] as! Int)
+                } else if type == "style" {
                     let start = attr[1] as! Int
                     let u16_start = utf8_offset_to_utf16(s, start)
                     let end = attr[2] as! Int
                     let u16_end = utf8_offset_to_utf16(s, end)
-                    attrString.addAttribute(NSBackgroundColorAttributeName, value: selcolor, range: NSMakeRange(u16_start, u16_end - u16_start))
-                } else if type == "fg" {
-                    let start = attr[1] as! Int
-                    let u16_start = utf8_offset_to_utf16(s, start)
-                    let end = attr[2] as! Int
-                    let u16_end = utf8_offset_to_utf16(s, end)
-                    let fgcolor = colorFromArgb(UInt32(attr[3] as! Int))
-                    //let fgcolor = colorFromArgb(0xff800000)
-                    attrString.addAttribute(NSForegroundColorAttributeName, value: fgcolor, range: NSMakeRange(u16_start, u16_end - u16_start))
+                    if let styleAttributes = styles[attr[3] as! Int] {
+                        attrString.addAttributes(styleAttributes, range: NSMakeRange(u16_start, u16_end - u16_start))
+                    }
                 }
             }
             // TODO: I don't understand where the 13 comes from (it's what aligns with baseline. We
@@ -194,22 +191,24 @@ class EditView: NSView {
             //attrString.drawAtPoint(NSPoint(x: x0, y: y - 13))
             let y = linespace * CGFloat(lineIx + 1);
             attrString.drawWithRect(NSRect(x: x0, y: y, width: dirtyRect.origin.x + dirtyRect.width - x0, height: 14), options: [])
-            if let cursor = cursor {
+            if !cursors.isEmpty {
                 let ctline = CTLineCreateWithAttributedString(attrString)
                 /*
                 CGContextSetTextMatrix(context, CGAffineTransform(a: 1, b: 0, c: 0, d: -1, tx: x0, ty: y))
                 CTLineDraw(ctline, context)
                 */
-                var pos = CGFloat(0)
-                // special case because measurement is so expensive; might have to rethink in rtl
-                if cursor != 0 {
-                    let utf16_ix = utf8_offset_to_utf16(s, cursor)
-                    pos = CTLineGetOffsetForStringIndex(ctline, CFIndex(utf16_ix), nil)
+                for cursor in cursors {
+                    var pos = CGFloat(0)
+                    // special case because measurement is so expensive; might have to rethink in rtl
+                    if cursor != 0 {
+                        let utf16_ix = utf8_offset_to_utf16(s, cursor)
+                        pos = CTLineGetOffsetForStringIndex(ctline, CFIndex(utf16_ix), nil)
+                    }
+                    CGContextSetStrokeColorWithColor(context, CGColorCreateGenericGray(0, 1))
+                    CGContextMoveToPoint(context, x0 + pos, y + descent)
+                    CGContextAddLineToPoint(context, x0 + pos, y - ascent)
+                    CGContextStrokePath(context)
                 }
-                CGContextSetStrokeColorWithColor(context, CGColorCreateGenericGray(0, 1))
-                CGContextMoveToPoint(context, x0 + pos, y + descent)
-                CGContextAddLineToPoint(context, x0 + pos, y - ascent)
-                CGContextStrokePath(context)
             }
         }
     }
@@ -342,13 +341,41 @@ class EditView: NSView {
     }
 
     func updateText(text: [String: AnyObject]) {
-        self.lineMap = [:]
-        let firstLine = text["first_line"] as! Int
-        self.height = text["height"] as! Int
-        let lines = text["lines"]! as! [[AnyObject]]
-        for lineNum in firstLine..<(firstLine + lines.count) {
-            self.lineMap[lineNum] = lines[lineNum - firstLine]
+        // apply the ops, which transform the old cache of lines into the new one
+        var newLineMap: [Int: [AnyObject]] = [:]
+        var copies: [(Int, Int, Int)] = []  // (old line, new line, count)
+        var oldIx = 0
+        var newIx = 0
+        for op in text["ops"] as! [[String: AnyObject]] {
+            let n = op["n"] as! Int
+            switch op["op"] as! String {
+            case "copy":
+                copies.append((oldIx, newIx, n))
+                oldIx += n
+                newIx += n
+            case "skip":
+                oldIx += n
+            case "invalidate":
+                newIx += n
+            case "ins":
+                let lines = op["lines"] as! [[AnyObject]]
+                for i in 0..<n {
+                    newLineMap[newIx + i] = lines[i]
+                }
+                newIx += n
+            default:
+                Swift.print("unknown update op", op)
+            }
+        }
+        for (lineNum, line) in self.lineMap {
+            for (oldStart, newStart, n) in copies where lineNum >= oldStart && lineNum < oldStart + n {
+                newLineMap[newStart + lineNum - oldStart] = line
+            }
         }
+        self.lineMap = newLineMap
+        // line numbers may have shifted, so outstanding requests are made again if needed
+        self.requestedLines.removeAll()
+        self.height = newIx
         heightConstraint?.constant = CGFloat(self.height) * linespace + 2 * descent
         if let cursor = text["scrollto"] as? [Int] {
             let line = cursor[0]
@@ -365,19 +392,48 @@ class EditView: NSView {
     }
 
     func tryUpdate() {
-        var pendingUpdate: [String: AnyObject]?
+        var pendingUpdates: [[String: AnyObject]] = []
         dispatch_sync(updateQueue) {
-            pendingUpdate = self.pendingUpdate
-            self.pendingUpdate = nil
+            pendingUpdates = self.pendingUpdates
+            self.pendingUpdates = []
         }
-        if let text = pendingUpdate {
+        for text in pendingUpdates {
             updateText(text)
         }
     }
 
+    // define a style, turning it into text attributes; this goes through the main queue so that
+    // it's in place before any update using it is applied
+    func defStyleSafe(style: [String: AnyObject]) {
+        dispatch_async(dispatch_get_main_queue()) {
+            var styleAttributes: [String: AnyObject] = [:]
+            if let fg = style["fg"] as? Int {
+                styleAttributes[NSForegroundColorAttributeName] = colorFromArgb(UInt32(fg))
+            }
+            if let bg = style["bg"] as? Int {
+                styleAttributes[NSBackgroundColorAttributeName] = colorFromArgb(UInt32(bg))
+            }
+            var traits = NSFontTraitMask()
+            if let weight = style["weight"] as? Int where weight >= 600 {
+                traits.insert(.BoldFontMask)
+            }
+            if let italic = style["italic"] as? Bool where italic {
+                traits.insert(.ItalicFontMask)
+            }
+            if !traits.isEmpty {
+                let font = self.attributes[String(kCTFontAttributeName)] as! NSFont
+                styleAttributes[NSFontAttributeName] = NSFontManager.sharedFontManager().convertFont(font, toHaveTrait: traits)
+            }
+            if let underline = style["underline"] as? Bool where underline {
+                styleAttributes[NSUnderlineStyleAttributeName] = NSUnderlineStyle.StyleSingle.rawValue
+            }
+            self.styles[style["id"] as! Int] = styleAttributes
+        }
+    }
+
     func updateSafe(text: [String: AnyObject]) {
         dispatch_sync(updateQueue) {
-            self.pendingUpdate = text
+            self.pendingUpdates.append(text)
         }
         dispatch_async(dispatch_get_main_queue()) {
             self.tryUpdate()
@@ -398,50 +454,39 @@ class EditView: NSView {
     let MAX_CACHE_LINES = 1000
     let CACHE_FETCH_CHUNK = 100
 
-    // get a line, trying to hit the cache
+    // get a line from the cache; if it's missing, ask the core for it and return nil,
+    // the line will arrive in an update and be drawn then
     func getLine(lineNum: Int) -> [AnyObject]? {
-        // TODO: maybe core should take care to set height >= 1
-        if lineNum < 0 || lineNum >= max(1, self.height) {
+        if lineNum < 0 || lineNum >= self.height {
             return nil
         }
         if let line = self.lineMap[lineNum] {
             return line
         }
-        // speculatively prefetch a bigger chunk from RPC, but don't get anything we already have
+        if requestedLines.contains(lineNum) {
+            return nil
+        }
+        // speculatively request a bigger chunk, but don't get anything we already have
         var first = lineNum
-        while first > max(0, lineNum - CACHE_FETCH_CHUNK) && lineMap.indexForKey(first - 1) == nil {
+        while first > max(0, lineNum - CACHE_FETCH_CHUNK) && lineMap.indexForKey(first - 1) == nil
+                && !requestedLines.contains(first - 1) {
             first -= 1
         }
         var last = lineNum + 1
-        while last < min(lineNum, height) + CACHE_FETCH_CHUNK && lineMap.indexForKey(last + 1) == nil {
+        while last < min(lineNum + CACHE_FETCH_CHUNK, height) && lineMap.indexForKey(last) == nil
+                && !requestedLines.contains(last) {
             last += 1
         }
         if lineMap.count + (last - first) > MAX_CACHE_LINES {
-            // a more sophisticated approach would be LRU replacement, but simple is probably good enough
+            // a more sophisticated approach would be LRU replacement, but simple is probably good enough;
+            // the core resends requested lines, so it doesn't need to know they were dropped
             lineMap = [:]
         }
-        if let lines = fetchLineRange(first, last) {
-            for lineNum in first..<last {
-                if lineNum - first < lines.count {
-                    lineMap[lineNum] = lines[lineNum - first]
-                } else {
-                    lineMap[lineNum] = [""]  // TODO: maybe core should always supply
-                }
-            }
-        }
-        return lineMap[lineNum]
-    }
-    
-    func fetchLineRange(first: Int, _ last: Int) -> [[AnyObject]]? {
-        let start = NSDate()
-        if let result = sendRpc("render_lines", params: ["first_line": first, "last_line": last]) as? [[AnyObject]] {
-            let interval = NSDate().timeIntervalSinceDate(start)
-            Swift.print(String(format: "RPC latency = %3.2fms", interval as Double * 1e3))
-            return result
-        } else {
-            Swift.print("rpc error")
-            return nil
+        for ix in first..<last {
+            requestedLines.insert(ix)
         }
+        sendRpcAsync("request_lines", params: ["first_line": first, "last_line": last])
+        return nil
     }
 
     // MARK: - Debug Methods
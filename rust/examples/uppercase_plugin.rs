@@ -0,0 +1,89 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A toy plugin, which uppercases the word "hello" wherever it turns up in
+//! the document. It follows the protocol in doc/plugin.md, and the tests of
+//! xi-core run it to exercise plugins as real subprocesses.
+
+extern crate serde_json;
+extern crate xi_rope;
+
+use std::io;
+use std::io::{BufRead, Write};
+
+use serde_json::Value;
+use serde_json::builder::ObjectBuilder;
+
+use xi_rope::delta::Delta;
+use xi_rope::interval::Interval;
+use xi_rope::rope::Rope;
+
+const WORD: &str = "hello";
+
+// Bring `text` up to date with the params of an `update`, returning the
+// revision it's now at.
+fn update(text: &mut Rope, params: &Value) -> Option<u64> {
+    let params = params.as_object()?;
+    if let Some(new_text) = params.get("text").and_then(|v| v.as_string()) {
+        *text = Rope::from(new_text);
+    } else if let Some(delta) = params.get("delta") {
+        match Delta::from_json(delta, text.len()) {
+            Ok(delta) => *text = delta.apply(text),
+            Err(_) => return None,
+        }
+    }
+    params.get("rev").and_then(|v| v.as_u64())
+}
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut text = Rope::from("");
+    // the core closes stdin when the buffer is closed
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let msg = match serde_json::from_str::<Value>(&line) {
+            Ok(msg) => msg,
+            Err(_) => continue,
+        };
+        let params = match msg.as_object().and_then(|msg| msg.get("params")) {
+            Some(params) => params,
+            None => continue,
+        };
+        let rev = match update(&mut text, params) {
+            Some(rev) => rev,
+            None => continue,
+        };
+        let s = String::from(text.clone());
+        if let Some(start) = s.find(WORD) {
+            let iv = Interval::new_closed_open(start, start + WORD.len());
+            let delta = Delta::simple_edit(iv, Rope::from(WORD.to_uppercase()), s.len());
+            let edit = ObjectBuilder::new()
+                .insert("method", "edit")
+                .insert_object("params", |builder| builder
+                    .insert("base_rev", rev)
+                    .insert("priority", 1)
+                    .insert("delta", &delta))
+                .unwrap();
+            let mut out = serde_json::to_string(&edit).unwrap();
+            out.push('\n');
+            if stdout.lock().write_all(out.as_bytes()).is_err() {
+                break;
+            }
+        }
+    }
+}
@@ -19,8 +19,11 @@
 use interval::Interval;
 use tree::{Node, NodeInfo, TreeBuilder};
 use subset::{Subset, SubsetBuilder};
+use rope::{Rope, RopeInfo};
 use std::cmp::min;
 use std;
+use serde::ser::{Serialize, Serializer};
+use serde_json::Value;
 
 pub enum DeltaElement<N: NodeInfo> {
     Copy(usize, usize),  // note: for now, we lose open/closed info at interval endpoints
@@ -211,6 +214,87 @@ impl<N: NodeInfo> Delta<N> {
         Delta { els: els, base_len: l }
     }
 
+    /// Returns the smallest interval of the base that contains all of the
+    /// changes made by the delta, along with the length of the text that
+    /// replaces it. This is useful for clients (such as line wrapping) that
+    /// can only do incremental updates of a single region.
+    pub fn summary(&self) -> (Interval, usize) {
+        let mut els = self.els.as_slice();
+        let mut iv_start = 0;
+        if let Some((&DeltaElement::Copy(0, end), rest)) = els.split_first() {
+            iv_start = end;
+            els = rest;
+        }
+        let mut iv_end = self.base_len;
+        if let Some((&DeltaElement::Copy(beg, end), init)) = els.split_last() {
+            if end == iv_end {
+                iv_end = beg;
+                els = init;
+            }
+        }
+        let new_len = els.iter().fold(0, |acc, elem| acc + match *elem {
+            DeltaElement::Copy(beg, end) => end - beg,
+            DeltaElement::Insert(ref n) => n.len(),
+        });
+        (Interval::new_closed_open(iv_start, iv_end), new_len)
+    }
+
+    /// Returns each of the intervals of the base changed by the delta, in
+    /// order, along with the length of the text that replaces it. Unlike
+    /// `summary`, this leaves out the text kept between changes, so clients
+    /// can update just the regions changed by an edit at several places.
+    pub fn edits(&self) -> Vec<(Interval, usize)> {
+        let mut result = Vec::new();
+        let mut base = 0;  // position in the base reached so far
+        let mut new_len = 0;  // of the text inserted since then
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    if beg > base || new_len > 0 {
+                        result.push((Interval::new_closed_open(base, beg), new_len));
+                    }
+                    base = end;
+                    new_len = 0;
+                }
+                DeltaElement::Insert(ref n) => new_len += n.len(),
+            }
+        }
+        if self.base_len > base || new_len > 0 {
+            result.push((Interval::new_closed_open(base, self.base_len), new_len));
+        }
+        result
+    }
+
+    /// Returns the offset in the result of applying the delta that corresponds
+    /// to `offset` in the base. The `after` parameter controls whether text
+    /// inserted exactly at `offset` ends up before the transformed offset.
+    /// Offsets inside deleted regions map to the location of the deletion.
+    pub fn transform_offset(&self, offset: usize, after: bool) -> usize {
+        let mut result = 0;
+        let mut base = 0;  // position in the base reached so far
+        for elem in &self.els {
+            match *elem {
+                DeltaElement::Copy(beg, end) => {
+                    if offset < beg {
+                        return result;
+                    }
+                    if offset < end || (offset == end && !after) {
+                        return result + offset - beg;
+                    }
+                    result += end - beg;
+                    base = end;
+                }
+                DeltaElement::Insert(ref n) => {
+                    if offset == base && !after {
+                        return result;
+                    }
+                    result += n.len();
+                }
+            }
+        }
+        result
+    }
+
     /// Return a subset that inverts the insert-only delta:
     ///
     /// `d.invert_insert().apply_to_string(d.apply_to_string(s)) == s`
@@ -232,6 +316,111 @@ impl<N: NodeInfo> Delta<N> {
     }
 }
 
+/// A builder for deltas that replace several regions of the base at once.
+pub struct DeltaBuilder<N: NodeInfo> {
+    delta: Delta<N>,
+    last_offset: usize,
+}
+
+impl<N: NodeInfo> DeltaBuilder<N> {
+    /// Create a builder for a delta with a base of length `base_len`, which
+    /// leaves the base unchanged until edits are added.
+    pub fn new(base_len: usize) -> DeltaBuilder<N> {
+        DeltaBuilder {
+            delta: Delta { els: Vec::new(), base_len: base_len },
+            last_offset: 0,
+        }
+    }
+
+    /// Replace the interval `iv` of the base with `rope`. Intervals must be
+    /// added in increasing order and must not overlap.
+    pub fn replace(&mut self, iv: Interval, rope: Node<N>) {
+        let (start, end) = iv.start_end();
+        assert!(start >= self.last_offset, "DeltaBuilder intervals must be in order");
+        if start > self.last_offset {
+            self.delta.els.push(DeltaElement::Copy(self.last_offset, start));
+        }
+        if rope.len() > 0 {
+            self.delta.els.push(DeltaElement::Insert(rope));
+        }
+        self.last_offset = end;
+    }
+
+    /// Whether no edits have been added.
+    pub fn is_empty(&self) -> bool {
+        self.last_offset == 0 && self.delta.els.is_empty()
+    }
+
+    pub fn build(mut self) -> Delta<N> {
+        if self.last_offset < self.delta.base_len {
+            self.delta.els.push(DeltaElement::Copy(self.last_offset, self.delta.base_len));
+        }
+        self.delta
+    }
+}
+
+/// Reasons for rejecting the JSON encoding of a delta.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeltaJsonError {
+    /// The value does not have the shape of an encoded delta.
+    Malformed,
+    /// A copy element is empty, out of order, or extends past the end of the base.
+    BadCopy(usize, usize),
+}
+
+impl Delta<RopeInfo> {
+    /// Encode the delta as JSON. The encoding is an array of elements, in which
+    /// a copy from the base is a `[beg, end]` pair of offsets, and an insertion
+    /// is a string. For example, the edit from "hello world" to "herald" is
+    /// `[[0, 1], "era", [9, 11]]`. The base length is not included.
+    pub fn to_json(&self) -> Value {
+        Value::Array(self.els.iter().map(|elem| match *elem {
+            DeltaElement::Copy(beg, end) =>
+                Value::Array(vec![Value::U64(beg as u64), Value::U64(end as u64)]),
+            DeltaElement::Insert(ref n) => Value::String(String::from(n.clone())),
+        }).collect())
+    }
+
+    /// Decode a delta in the format produced by `to_json`, to be applied to a
+    /// base of length `base_len`. Copies must be nonempty, in increasing order,
+    /// and within the base. Empty insertions are dropped.
+    pub fn from_json(v: &Value, base_len: usize) -> Result<Delta<RopeInfo>, DeltaJsonError> {
+        let arr = try!(v.as_array().ok_or(DeltaJsonError::Malformed));
+        let mut els = Vec::with_capacity(arr.len());
+        let mut last_end = 0;
+        for elem in arr {
+            if let Some(s) = elem.as_string() {
+                if !s.is_empty() {
+                    els.push(DeltaElement::Insert(Rope::from(s)));
+                }
+            } else if let Some(pair) = elem.as_array() {
+                if pair.len() != 2 {
+                    return Err(DeltaJsonError::Malformed);
+                }
+                let (beg, end) = match (pair[0].as_u64(), pair[1].as_u64()) {
+                    (Some(beg), Some(end)) => (beg as usize, end as usize),
+                    _ => return Err(DeltaJsonError::Malformed),
+                };
+                if beg < last_end || beg >= end || end > base_len {
+                    return Err(DeltaJsonError::BadCopy(beg, end));
+                }
+                last_end = end;
+                els.push(DeltaElement::Copy(beg, end));
+            } else {
+                return Err(DeltaJsonError::Malformed);
+            }
+        }
+        Ok(Delta { els: els, base_len: base_len })
+    }
+}
+
+impl Serialize for Delta<RopeInfo> {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+            where S: Serializer {
+        self.to_json().serialize(serializer)
+    }
+}
+
 // This version of the Delta data structure will be replaced by the new one,
 // as it's not as suitable for async updates and undo. We keep it until the
 // new one is ready to use.
@@ -281,8 +470,10 @@ impl<N: NodeInfo> OldDelta<N> {
 
 #[cfg(test)]
 mod tests {
+    use serde_json;
+    use serde_json::Value;
     use rope::{Rope, RopeInfo};
-    use delta::{Delta};
+    use delta::{Delta, DeltaBuilder, DeltaJsonError};
     use interval::Interval;
     use subset::{Subset, SubsetBuilder};
 
@@ -314,6 +505,19 @@ mod tests {
         assert_eq!("herald", d.apply_to_string("hello world"));
     }
 
+    #[test]
+    fn builder() {
+        let mut b = DeltaBuilder::new(11);
+        b.replace(Interval::new_closed_open(0, 1), Rope::from("j"));
+        b.replace(Interval::new_closed_open(5, 5), Rope::from(","));
+        b.replace(Interval::new_closed_open(6, 11), Rope::from(""));
+        assert!(!b.is_empty());
+        let d = b.build();
+        assert_eq!("jello, ", d.apply_to_string("hello world"));
+        assert_eq!(6, d.transform_offset(5, true));
+        assert_eq!("hello world", DeltaBuilder::new(11).build().apply_to_string("hello world"));
+    }
+
     #[test]
     fn factor() {
         let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
@@ -341,6 +545,82 @@ mod tests {
         assert_eq!("hello world", d1.invert_insert().apply_to_string("heraello world"));
     }
 
+    #[test]
+    fn summary() {
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
+        let (iv, new_len) = d.summary();
+        assert_eq!((1, 9), iv.start_end());
+        assert_eq!(3, new_len);
+        let d = Delta::simple_edit(Interval::new_closed_open(11, 11), Rope::from("!"), 11);
+        let (iv, new_len) = d.summary();
+        assert_eq!((11, 11), iv.start_end());
+        assert_eq!(1, new_len);
+    }
+
+    #[test]
+    fn edits() {
+        let mut b = DeltaBuilder::new(11);
+        b.replace(Interval::new_closed_open(0, 1), Rope::from("j"));
+        b.replace(Interval::new_closed_open(5, 5), Rope::from(","));
+        b.replace(Interval::new_closed_open(6, 11), Rope::from(""));
+        let edits = b.build().edits().into_iter()
+            .map(|(iv, new_len)| (iv.start(), iv.end(), new_len))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(0, 1, 1), (5, 5, 1), (6, 11, 0)], edits);
+        assert!(DeltaBuilder::<RopeInfo>::new(11).build().edits().is_empty());
+        let d = Delta::simple_edit(Interval::new_closed_open(11, 11), Rope::from("!"), 11);
+        assert_eq!(vec![(11, 11, 1)], d.edits().into_iter()
+            .map(|(iv, new_len)| (iv.start(), iv.end(), new_len))
+            .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn transform_offset() {
+        // "hello world" -> "herald"
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
+        assert_eq!(0, d.transform_offset(0, false));
+        assert_eq!(1, d.transform_offset(1, false));
+        assert_eq!(4, d.transform_offset(1, true));
+        assert_eq!(4, d.transform_offset(5, false));
+        assert_eq!(4, d.transform_offset(9, false));
+        assert_eq!(6, d.transform_offset(11, false));
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("> "), 5);
+        assert_eq!(0, d.transform_offset(0, false));
+        assert_eq!(2, d.transform_offset(0, true));
+        assert_eq!(7, d.transform_offset(5, true));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let d = Delta::simple_edit(Interval::new_closed_open(1, 9), Rope::from("era"), 11);
+        let json = serde_json::to_string(&d).unwrap();
+        assert_eq!(r#"[[0,1],"era",[9,11]]"#, json);
+        let v = serde_json::from_str::<Value>(&json).unwrap();
+        let d2 = Delta::from_json(&v, 11).unwrap();
+        assert_eq!("herald", d2.apply_to_string("hello world"));
+        assert_eq!(d.to_json(), d2.to_json());
+
+        let d = Delta::simple_edit(Interval::new_closed_open(0, 0), Rope::from("\u{1F600}\n"), 0);
+        let d2 = Delta::from_json(&d.to_json(), 0).unwrap();
+        assert_eq!("\u{1F600}\n", d2.apply_to_string(""));
+    }
+
+    #[test]
+    fn json_validation() {
+        let parse = |s: &str, base_len| {
+            Delta::from_json(&serde_json::from_str::<Value>(s).unwrap(), base_len).map(|_| ())
+        };
+        assert_eq!(Ok(()), parse(r#"[[0,5],"!"]"#, 5));
+        assert_eq!(Ok(()), parse(r#"[]"#, 5));
+        assert_eq!(Err(DeltaJsonError::BadCopy(0, 6)), parse(r#"[[0,6]]"#, 5));
+        assert_eq!(Err(DeltaJsonError::BadCopy(1, 3)), parse(r#"[[2,4],[1,3]]"#, 5));
+        assert_eq!(Err(DeltaJsonError::BadCopy(3, 3)), parse(r#"[[3,3]]"#, 5));
+        assert_eq!(Err(DeltaJsonError::Malformed), parse(r#"{"els":[]}"#, 5));
+        assert_eq!(Err(DeltaJsonError::Malformed), parse(r#"[[0,1,2]]"#, 5));
+        assert_eq!(Err(DeltaJsonError::Malformed), parse(r#"[[-1,2]]"#, 5));
+        assert_eq!(Err(DeltaJsonError::Malformed), parse(r#"[42]"#, 5));
+    }
+
     #[test]
     fn transform_expand() {
         let str1 = "01259DGJKNQTUVWXYcdefghkmopqrstvwxy";
@@ -19,11 +19,18 @@
 
 use std::borrow::Cow;
 use std::collections::BTreeSet;
+use std::mem;
 
 use rope::{Rope, RopeInfo};
-use subset::Subset;
+use subset::{Subset, SubsetBuilder};
 use delta::Delta;
 
+/// Represents the current state of a document and all of its history.
+///
+/// Edits are applied against an explicit base revision, so that edits computed
+/// against a stale version of the document (for example, by a slow plugin) can
+/// be merged with concurrent edits. Ties between insertions at the same location
+/// are broken by priority; higher priority insertions land later in the text.
 pub struct Engine {
     rev_id_counter: usize,
     union_str: Rope,
@@ -52,6 +59,34 @@ enum Contents {
 }
 
 impl Engine {
+    /// Create a new Engine with a single edit that inserts `initial_contents`.
+    pub fn new(initial_contents: Rope) -> Engine {
+        Engine::with_first_rev_id(initial_contents, 0)
+    }
+
+    fn with_first_rev_id(initial_contents: Rope, rev_id: usize) -> Engine {
+        let rev = Revision {
+            rev_id: rev_id,
+            from_union: SubsetBuilder::new().build(),
+            union_str_len: initial_contents.len(),
+            edit: Undo {
+                groups: BTreeSet::new(),
+            }
+        };
+        Engine {
+            rev_id_counter: rev_id + 1,
+            union_str: initial_contents,
+            revs: vec![rev],
+        }
+    }
+
+    /// Replace the text with `initial_contents`, discarding all history, as
+    /// `Engine::new` would. Revision ids carry on from the old ones, so a
+    /// `base_rev` from before the reset is never taken for one after it.
+    pub fn reset(&mut self, initial_contents: Rope) {
+        *self = Engine::with_first_rev_id(initial_contents, self.rev_id_counter);
+    }
+
     fn get_current_undo(&self) -> Option<&BTreeSet<usize>> {
         for rev in self.revs.iter().rev() {
             if let Undo { ref groups } = rev.edit {
@@ -70,7 +105,7 @@ impl Engine {
         None
     }
 
-    fn get_rev(&self, rev_index: usize) -> Rope {
+    fn rev_content_for_index(&self, rev_index: usize) -> Rope {
         let mut from_union = Cow::Borrowed(&self.revs[rev_index].from_union);
         for rev in &self.revs[rev_index + 1..] {
             if let Edit { ref inserts, .. } = rev.edit {
@@ -82,8 +117,29 @@ impl Engine {
         from_union.apply(&self.union_str)
     }
 
+    /// Get text of head revision.
     pub fn get_head(&self) -> Rope {
-        self.get_rev(self.revs.len() - 1)
+        self.rev_content_for_index(self.revs.len() - 1)
+    }
+
+    /// Get text of a given revision, if it can be found.
+    pub fn get_rev(&self, rev_id: usize) -> Option<Rope> {
+        self.find_rev(rev_id).map(|rev_index| self.rev_content_for_index(rev_index))
+    }
+
+    /// Get the length of the text of a given revision, if it can be found,
+    /// without building the text.
+    pub fn get_rev_len(&self, rev_id: usize) -> Option<usize> {
+        self.find_rev(rev_id).map(|rev_index| {
+            let rev = &self.revs[rev_index];
+            rev.from_union.len(rev.union_str_len)
+        })
+    }
+
+    /// Get the id of the current head revision. This is the appropriate
+    /// `base_rev` for an edit computed against the text from `get_head`.
+    pub fn get_head_rev_id(&self) -> usize {
+        self.revs.last().unwrap().rev_id
     }
 
     /// A delta that, when applied to previous head, results in the current head. Panics
@@ -99,35 +155,57 @@ impl Engine {
         Delta::synthesize(&self.union_str, &prev_from_union, &rev.from_union)
     }
 
+    /// A delta that, when applied to `base_rev`, results in the current head. Panics
+    /// if there is not at least one edit.
+    pub fn delta_rev_head(&self, base_rev: usize) -> Delta<RopeInfo> {
+        let ix = self.find_rev(base_rev).expect("base revision not found");
+        let mut prev_from_union = Cow::Borrowed(&self.revs[ix].from_union);
+        for rev in &self.revs[ix + 1..] {
+            if let Edit { ref inserts, .. } = rev.edit {
+                if !inserts.is_trivial() {
+                    prev_from_union = Cow::Owned(prev_from_union.transform_intersect(inserts));
+                }
+            }
+        }
+        let head_rev = self.revs.last().unwrap();
+        Delta::synthesize(&self.union_str, &prev_from_union, &head_rev.from_union)
+    }
+
     fn mk_new_rev(&self, new_priority: usize, undo_group: usize,
             base_rev: usize, delta: Delta<RopeInfo>) -> (Revision, Rope) {
         let ix = self.find_rev(base_rev).expect("base revision not found");
         let rev = &self.revs[ix];
         let (ins_delta, deletes) = delta.factor();
+        // The deletions never cover text inserted by the same delta, so express them
+        // relative to the base text. They are then carried through the union string
+        // independently of the insertions, and expanded by the new inserts at the end.
+        let deletes_at_base = ins_delta.invert_insert().transform_shrink(&deletes);
         let mut union_ins_delta = ins_delta.transform_expand(&rev.from_union, rev.union_str_len, true);
-        let mut new_deletes = deletes.transform_expand(&rev.from_union);
+        let mut new_deletes = deletes_at_base.transform_expand(&rev.from_union);
         for r in &self.revs[ix + 1..] {
-            if let Edit { priority, ref inserts, .. } = rev.edit {
+            if let Edit { priority, ref inserts, .. } = r.edit {
                 if !inserts.is_trivial() {
                     let after = new_priority >= priority;  // should never be ==
-                    union_ins_delta = union_ins_delta.transform_expand(&inserts, r.union_str_len, after);
-                    new_deletes = new_deletes.transform_expand(&inserts);
+                    union_ins_delta = union_ins_delta.transform_expand(inserts, r.union_str_len, after);
+                    new_deletes = new_deletes.transform_expand(inserts);
                 }
             }
         }
         let new_inserts = union_ins_delta.invert_insert();
         let new_union_str = union_ins_delta.apply(&self.union_str);
-        let undone = self.get_current_undo().map_or(false, |undos| undos.contains(&undo_group));
-        let mut new_from_union = Cow::Borrowed(&rev.from_union);
-        {
-            let edit = if undone { &new_inserts } else { &new_deletes };
-            if !edit.is_trivial() {
-                new_from_union = Cow::Owned(new_from_union.intersect(edit));
-            }
+        if !new_inserts.is_trivial() {
+            new_deletes = new_deletes.transform_expand(&new_inserts);
         }
+        let undone = self.get_current_undo().map_or(false, |undos| undos.contains(&undo_group));
+        let head_from_union = &self.revs.last().unwrap().from_union;
+        let new_from_union = if undone {
+            head_from_union.transform_intersect(&new_inserts)
+        } else {
+            head_from_union.transform_expand(&new_inserts).intersect(&new_deletes)
+        };
         (Revision {
             rev_id: self.rev_id_counter,
-            from_union: new_from_union.into_owned(),
+            from_union: new_from_union,
             union_str_len: new_union_str.len(),
             edit: Edit {
                 priority: new_priority,
@@ -138,6 +216,8 @@ impl Engine {
         }, new_union_str)
     }
 
+    /// Apply `delta`, which was computed against the text of `base_rev`, creating
+    /// a new head revision. Panics if `base_rev` is not found.
     pub fn edit_rev(&mut self, priority: usize, undo_group: usize,
             base_rev: usize, delta: Delta<RopeInfo>) {
         let (new_rev, new_union_str) = self.mk_new_rev(priority, undo_group, base_rev, delta);
@@ -146,12 +226,13 @@ impl Engine {
         self.union_str = new_union_str;
     }
 
+    // Replay the edits up to and including `rev_index`, with `groups` undone.
     // This computes undo all the way from the beginning. An optimization would be to not
     // recompute the prefix up to where the history diverges, but it's not clear that's
-    // even worth the code complexity.
-    fn compute_undo(&self, groups: BTreeSet<usize>) -> Revision {
+    // even worth the code complexity. Garbage collection keeps the prefix short.
+    fn replay_from_union(&self, rev_index: usize, groups: &BTreeSet<usize>) -> Subset {
         let mut from_union = Cow::Borrowed(&self.revs[0].from_union);
-        for rev in &self.revs[1..] {
+        for rev in &self.revs[1..rev_index + 1] {
             if let Edit { ref undo_group, ref inserts, ref deletes, .. } = rev.edit {
                 if groups.contains(undo_group) {
                     if !inserts.is_trivial() {
@@ -167,9 +248,13 @@ impl Engine {
                 }
             }
         }
+        from_union.into_owned()
+    }
+
+    fn compute_undo(&self, groups: BTreeSet<usize>) -> Revision {
         Revision {
             rev_id: self.rev_id_counter,
-            from_union: from_union.into_owned(),
+            from_union: self.replay_from_union(self.revs.len() - 1, &groups),
             union_str_len: self.union_str.len(),
             edit: Undo {
                 groups: groups
@@ -177,9 +262,311 @@ impl Engine {
         }
     }
 
+    /// Set the undo groups that are undone, creating a new head revision.
     pub fn undo(&mut self, groups: BTreeSet<usize>) {
         let new_rev = self.compute_undo(groups);
         self.revs.push(new_rev);
         self.rev_id_counter += 1;
     }
+
+    /// Forget the given undo groups, which can no longer be toggled by `undo`.
+    /// Text that is permanently deleted as a result (inserted by an undone group,
+    /// or deleted by a group that is not undone) is removed from the union string.
+    /// Revisions that no longer carry any information are dropped, and the history
+    /// preceding the oldest edit that can still be undone is folded into a single
+    /// revision, so those revisions can no longer be used as a `base_rev` (the last
+    /// one before that edit, and the head, remain available). The caller must not
+    /// pass the forgotten groups to `undo` again.
+    ///
+    /// Removing deleted text from the union string also removes what inserts at
+    /// the same place are ordered around. Concurrent inserts later made against
+    /// revisions from before the gc can then end up in a different order than
+    /// they would have without it, so two engines with the same edits may not
+    /// agree unless they gc the same groups at the same point.
+    pub fn gc(&mut self, gc_groups: &BTreeSet<usize>) {
+        let undone = self.get_current_undo().cloned().unwrap_or_default();
+
+        // Find the text that can never become visible again, in head union coordinates.
+        let mut gc_dels = self.revs[0].from_union.clone();
+        let mut first_live_ix = self.revs.len();
+        for (i, rev) in self.revs.iter().enumerate().skip(1) {
+            if let Edit { ref undo_group, ref inserts, ref deletes, .. } = rev.edit {
+                if !gc_groups.contains(undo_group) && i < first_live_ix {
+                    first_live_ix = i;
+                }
+                if !inserts.is_trivial() {
+                    gc_dels = gc_dels.transform_expand(inserts);
+                }
+                if gc_groups.contains(undo_group) {
+                    if undone.contains(undo_group) {
+                        gc_dels = gc_dels.intersect(inserts);
+                    } else {
+                        gc_dels = gc_dels.intersect(deletes);
+                    }
+                }
+            }
+        }
+
+        if !gc_dels.is_trivial() {
+            self.union_str = gc_dels.apply(&self.union_str);
+        }
+
+        // Rebase the history onto the compacted union string, walking backwards so
+        // that `gc_dels` can be carried through each revision's inserts.
+        let old_revs = mem::replace(&mut self.revs, Vec::new());
+        let head_ix = old_revs.len() - 1;
+        for (i, rev) in old_revs.into_iter().enumerate().rev() {
+            let Revision { rev_id, from_union, union_str_len, edit } = rev;
+            let new_gc_dels = match edit {
+                Edit { ref inserts, .. } if !inserts.is_trivial() =>
+                    Some(inserts.transform_shrink(&gc_dels)),
+                _ => None,
+            };
+            let edit = match edit {
+                Edit { priority, undo_group, inserts, deletes } => {
+                    if gc_groups.contains(&undo_group) {
+                        let inserts = gc_dels.transform_shrink(&inserts);
+                        if inserts.is_trivial() && i > first_live_ix && i < head_ix {
+                            // all of the group's effect is now baked into the union string
+                            gc_dels = new_gc_dels.unwrap_or(gc_dels);
+                            continue;
+                        }
+                        Edit {
+                            priority: priority,
+                            undo_group: undo_group,
+                            inserts: inserts,
+                            deletes: SubsetBuilder::new().build(),
+                        }
+                    } else {
+                        Edit {
+                            priority: priority,
+                            undo_group: undo_group,
+                            inserts: gc_dels.transform_shrink(&inserts),
+                            deletes: gc_dels.transform_shrink(&deletes),
+                        }
+                    }
+                }
+                Undo { groups } => Undo {
+                    groups: &groups - gc_groups,
+                }
+            };
+            self.revs.push(Revision {
+                rev_id: rev_id,
+                from_union: gc_dels.transform_shrink(&from_union),
+                union_str_len: gc_dels.len(union_str_len),
+                edit: edit,
+            });
+            if let Some(new_gc_dels) = new_gc_dels {
+                gc_dels = new_gc_dels;
+            }
+        }
+        self.revs.reverse();
+
+        // Fold the prefix of history that contains no undoable edits into a single
+        // initial revision, so that undo doesn't have to replay it. Nothing before
+        // `first_live_ix` was dropped above, so indices in the prefix are unchanged.
+        let fold_ix = first_live_ix - 1;
+        if fold_ix > 0 {
+            let undone = &undone - gc_groups;
+            let from_union = self.replay_from_union(fold_ix, &undone);
+            let first = Revision {
+                rev_id: self.revs[fold_ix].rev_id,
+                from_union: from_union,
+                union_str_len: self.revs[fold_ix].union_str_len,
+                edit: Undo {
+                    groups: undone,
+                }
+            };
+            self.revs.drain(..fold_ix);
+            self.revs[0] = first;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use engine::Engine;
+    use rope::{Rope, RopeInfo};
+    use delta::Delta;
+    use interval::Interval;
+
+    const TEST_STR: &'static str = "0123456789abcdef";
+
+    fn simple_edit(start: usize, end: usize, new: &str, base_len: usize) -> Delta<RopeInfo> {
+        Delta::simple_edit(Interval::new_closed_open(start, end), Rope::from(new), base_len)
+    }
+
+    #[test]
+    fn edit_rev_simple() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(0, 1, first_rev, simple_edit(1, 9, "z", TEST_STR.len()));
+        assert_eq!("0z9abcdef", String::from(engine.get_head()));
+        assert_eq!(TEST_STR, String::from(engine.get_rev(first_rev).unwrap()));
+        assert!(engine.get_rev(42).is_none());
+    }
+
+    #[test]
+    fn rev_len() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(0, 1, first_rev, simple_edit(1, 9, "z", TEST_STR.len()));
+        engine.edit_rev(1, 2, first_rev, simple_edit(10, 10, "xyz", TEST_STR.len()));
+        let mut undos = BTreeSet::new();
+        undos.insert(1);
+        engine.undo(undos);
+        for rev in first_rev..engine.get_head_rev_id() + 1 {
+            assert_eq!(engine.get_rev(rev).map(|text| text.len()), engine.get_rev_len(rev));
+        }
+        assert_eq!(None, engine.get_rev_len(42));
+    }
+
+    #[test]
+    fn reset() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(0, 1, first_rev, simple_edit(1, 9, "z", TEST_STR.len()));
+        let old_head = engine.get_head_rev_id();
+        engine.reset(Rope::from("fedcba9876543210"));
+        assert_eq!("fedcba9876543210", String::from(engine.get_head()));
+        // no revision from before the reset can be found, or reused
+        assert!(engine.get_head_rev_id() > old_head);
+        assert!(engine.get_rev(first_rev).is_none());
+        assert!(engine.get_rev(old_head).is_none());
+    }
+
+    #[test]
+    fn edit_rev_undo() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(0, 1, first_rev, simple_edit(1, 9, "z", TEST_STR.len()));
+        let mut undos = BTreeSet::new();
+        undos.insert(1);
+        engine.undo(undos);
+        assert_eq!(TEST_STR, String::from(engine.get_head()));
+        engine.undo(BTreeSet::new());
+        assert_eq!("0z9abcdef", String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn delta_rev_head() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(0, 1, first_rev, simple_edit(1, 9, "z", TEST_STR.len()));
+        let d = engine.delta_rev_head(first_rev);
+        assert_eq!("0z9abcdef", String::from(d.apply(&Rope::from(TEST_STR))));
+    }
+
+    #[test]
+    fn concurrent_inserts_priority() {
+        let mut engine = Engine::new(Rope::from("ab"));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(1, 1, first_rev, simple_edit(1, 1, "X", 2));
+        engine.edit_rev(2, 2, first_rev, simple_edit(1, 1, "Y", 2));
+        assert_eq!("aXYb", String::from(engine.get_head()));
+
+        let mut engine = Engine::new(Rope::from("ab"));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(2, 1, first_rev, simple_edit(1, 1, "X", 2));
+        engine.edit_rev(1, 2, first_rev, simple_edit(1, 1, "Y", 2));
+        assert_eq!("aYXb", String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn concurrent_insert_delete() {
+        let mut engine = Engine::new(Rope::from("abcd"));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(1, 1, first_rev, simple_edit(1, 3, "", 4));
+        let second_rev = engine.get_head_rev_id();
+        engine.edit_rev(2, 2, first_rev, simple_edit(3, 4, "Z", 4));
+        assert_eq!("aZ", String::from(engine.get_head()));
+        assert_eq!("ad", String::from(engine.get_rev(second_rev).unwrap()));
+
+        // undoing the deletion keeps the concurrent edit
+        let mut undos = BTreeSet::new();
+        undos.insert(1);
+        engine.undo(undos);
+        assert_eq!("abcZ", String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn edit_over_tombstones() {
+        let mut engine = Engine::new(Rope::from("abcd"));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(1, 1, first_rev, simple_edit(1, 3, "", 4));
+        let second_rev = engine.get_head_rev_id();
+        // "ad" -> "aXd", then delete the "d" based on the edited text
+        engine.edit_rev(1, 2, second_rev, simple_edit(1, 1, "X", 2));
+        let third_rev = engine.get_head_rev_id();
+        engine.edit_rev(1, 3, third_rev, simple_edit(1, 3, "Y", 3));
+        assert_eq!("aY", String::from(engine.get_head()));
+        let mut undos = BTreeSet::new();
+        undos.insert(3);
+        engine.undo(undos);
+        assert_eq!("aXd", String::from(engine.get_head()));
+    }
+
+    fn undo_set(groups: &[usize]) -> BTreeSet<usize> {
+        groups.iter().cloned().collect()
+    }
+
+    #[test]
+    fn gc_undone_insert() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(0, 1, first_rev, simple_edit(1, 1, "xyz", TEST_STR.len()));
+        engine.undo(undo_set(&[1]));
+        let rev = engine.get_head_rev_id();
+        engine.edit_rev(0, 2, rev, simple_edit(0, 2, "!", TEST_STR.len()));
+        let union_len = engine.union_str.len();
+        engine.gc(&undo_set(&[1]));
+        assert_eq!("!23456789abcdef", String::from(engine.get_head()));
+        assert_eq!(union_len - 3, engine.union_str.len());
+
+        engine.undo(BTreeSet::new());
+        assert_eq!("!23456789abcdef", String::from(engine.get_head()));
+        engine.undo(undo_set(&[2]));
+        assert_eq!(TEST_STR, String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn gc_applied_delete() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(0, 1, first_rev, simple_edit(1, 9, "z", TEST_STR.len()));
+        let rev = engine.get_head_rev_id();
+        engine.edit_rev(0, 2, rev, simple_edit(2, 2, "--", 9));
+        assert_eq!("0z--9abcdef", String::from(engine.get_head()));
+        engine.gc(&undo_set(&[1]));
+        assert_eq!("0z--9abcdef", String::from(engine.get_head()));
+        assert_eq!(11, engine.union_str.len());
+        // the gc'd edit is folded into the initial revision
+        assert_eq!(2, engine.revs.len());
+
+        engine.undo(undo_set(&[2]));
+        assert_eq!("0z9abcdef", String::from(engine.get_head()));
+        engine.undo(BTreeSet::new());
+        let rev = engine.get_head_rev_id();
+        engine.edit_rev(0, 3, rev, simple_edit(11, 11, ".", 11));
+        assert_eq!("0z--9abcdef.", String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn gc_concurrent() {
+        let mut engine = Engine::new(Rope::from("abcd"));
+        let first_rev = engine.get_head_rev_id();
+        engine.edit_rev(1, 1, first_rev, simple_edit(1, 3, "", 4));
+        let second_rev = engine.get_head_rev_id();
+        engine.edit_rev(2, 2, second_rev, simple_edit(1, 1, "X", 2));
+        engine.gc(&undo_set(&[1]));
+        assert_eq!("aXd", String::from(engine.get_head()));
+        assert_eq!(3, engine.union_str.len());
+        // edit against a revision still retained after gc
+        engine.edit_rev(1, 3, second_rev, simple_edit(2, 2, "Y", 2));
+        assert_eq!("aXdY", String::from(engine.get_head()));
+        engine.undo(undo_set(&[2]));
+        assert_eq!("adY", String::from(engine.get_head()));
+    }
 }
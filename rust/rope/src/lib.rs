@@ -14,6 +14,10 @@
 
 //! A rope data structure suitable for text editing
 
+extern crate serde;
+extern crate serde_json;
+extern crate xi_unicode;
+
 pub mod tree;
 pub mod breaks;
 pub mod interval;
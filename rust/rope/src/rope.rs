@@ -12,8 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! A rope data structure with a line count metric and (soon) other useful
-//! info.
+//! A rope data structure with metrics counting lines, UTF-16 code units and
+//! code points, and (soon) other useful info.
 
 use std::cmp::{min,max};
 use std::borrow::Cow;
@@ -23,6 +23,8 @@ use std::string::ParseError;
 use tree::{Leaf, Node, NodeInfo, Metric, TreeBuilder, Cursor};
 use interval::Interval;
 
+use xi_unicode::{GraphemeCursor, GraphemeIncomplete};
+
 const MIN_LEAF: usize = 511;
 const MAX_LEAF: usize = 1024;
 
@@ -59,6 +61,8 @@ impl Leaf for String {
 #[derive(Clone, Copy)]
 pub struct RopeInfo {
     lines: usize,
+    utf16_size: usize,
+    codepoints: usize,
 }
 
 impl NodeInfo for RopeInfo {
@@ -66,17 +70,23 @@ impl NodeInfo for RopeInfo {
 
     fn accumulate(&mut self, other: &Self) {
         self.lines += other.lines;
+        self.utf16_size += other.utf16_size;
+        self.codepoints += other.codepoints;
     }
 
     fn compute_info(s: &String) -> Self {
         RopeInfo {
             lines: count_newlines(s),
+            utf16_size: count_utf16_code_units(s),
+            codepoints: count_codepoints(s),
         }
     }
 
     fn identity() -> Self {
         RopeInfo {
             lines: 0,
+            utf16_size: 0,
+            codepoints: 0,
         }
     }
 }
@@ -182,6 +192,83 @@ impl Metric<RopeInfo> for LinesMetric {
     fn can_fragment() -> bool { true }
 }
 
+/// Measures UTF-16 code units, as used for offsets by JavaScript, Cocoa and
+/// the Language Server Protocol. Boundaries are at code points; converting an
+/// offset in the middle of a surrogate pair gives the end of the pair.
+#[derive(Clone, Copy)]
+pub struct Utf16CodeUnitsMetric(usize);
+
+impl Metric<RopeInfo> for Utf16CodeUnitsMetric {
+    fn measure(info: &RopeInfo, _: usize) -> usize {
+        info.utf16_size
+    }
+
+    fn to_base_units(s: &String, in_measured_units: usize) -> usize {
+        let mut utf16_count = 0;
+        for (offset, c) in s.char_indices() {
+            if utf16_count >= in_measured_units {
+                return offset;
+            }
+            utf16_count += c.len_utf16();
+        }
+        s.len()
+    }
+
+    fn from_base_units(s: &String, in_base_units: usize) -> usize {
+        count_utf16_code_units(&s[..in_base_units])
+    }
+
+    fn is_boundary(s: &String, offset: usize) -> bool {
+        is_char_boundary(s, offset)
+    }
+
+    fn prev(s: &String, offset: usize) -> Option<usize> {
+        BaseMetric::prev(s, offset)
+    }
+
+    fn next(s: &String, offset: usize) -> Option<usize> {
+        BaseMetric::next(s, offset)
+    }
+
+    fn can_fragment() -> bool {
+        false
+    }
+}
+
+/// Measures Unicode code points (scalar values).
+#[derive(Clone, Copy)]
+pub struct CodepointsMetric(usize);
+
+impl Metric<RopeInfo> for CodepointsMetric {
+    fn measure(info: &RopeInfo, _: usize) -> usize {
+        info.codepoints
+    }
+
+    fn to_base_units(s: &String, in_measured_units: usize) -> usize {
+        s.char_indices().nth(in_measured_units).map_or(s.len(), |(offset, _)| offset)
+    }
+
+    fn from_base_units(s: &String, in_base_units: usize) -> usize {
+        count_codepoints(&s[..in_base_units])
+    }
+
+    fn is_boundary(s: &String, offset: usize) -> bool {
+        is_char_boundary(s, offset)
+    }
+
+    fn prev(s: &String, offset: usize) -> Option<usize> {
+        BaseMetric::prev(s, offset)
+    }
+
+    fn next(s: &String, offset: usize) -> Option<usize> {
+        BaseMetric::next(s, offset)
+    }
+
+    fn can_fragment() -> bool {
+        false
+    }
+}
+
 // Low level functions
 
 // TODO: explore ways to make this faster - SIMD would be a big win
@@ -190,6 +277,18 @@ fn count_newlines(s: &str) -> usize {
     s.as_bytes().iter().filter(|&&c| c == b'\n').count()
 }
 
+// Every byte other than a continuation byte starts a code point, and those
+// starting 4-byte sequences take two UTF-16 code units.
+fn count_utf16_code_units(s: &str) -> usize {
+    s.as_bytes().iter().map(|&b| {
+        if (b as i8) < -0x40 { 0 } else if b >= 0xf0 { 2 } else { 1 }
+    }).sum()
+}
+
+fn count_codepoints(s: &str) -> usize {
+    s.as_bytes().iter().filter(|&&b| (b as i8) >= -0x40).count()
+}
+
 // TODO: probably will be stabilized in Rust std lib
 // Note, this isn't exactly the same, it panics when index > s.len()
 fn is_char_boundary(s: &str, index: usize) -> bool {
@@ -256,15 +355,33 @@ impl Rope {
         cursor.next::<BaseMetric>()
     }
 
-    // graphemes should probably be developed as a cursor-based interface
+    /// Return the offset of the grapheme cluster boundary before `offset`.
     pub fn prev_grapheme_offset(&self, offset: usize) -> Option<usize> {
-        // TODO: actual grapheme analysis
-        self.prev_codepoint_offset(offset)
+        self.grapheme_offset(offset, |cursor, leaf, leaf_start|
+            cursor.prev_boundary(leaf, leaf_start))
     }
 
+    /// Return the offset of the grapheme cluster boundary after `offset`.
     pub fn next_grapheme_offset(&self, offset: usize) -> Option<usize> {
-        // TODO: actual grapheme analysis
-        self.next_codepoint_offset(offset)
+        self.grapheme_offset(offset, |cursor, leaf, leaf_start|
+            cursor.next_boundary(leaf, leaf_start))
+    }
+
+    // Run a grapheme cursor query starting at `offset`, feeding it leaves
+    // until it has enough context to answer.
+    fn grapheme_offset<F>(&self, offset: usize, f: F) -> Option<usize>
+            where F: Fn(&mut GraphemeCursor, &str, usize) -> Result<Option<usize>, GraphemeIncomplete> {
+        let mut cursor = Cursor::new(self, offset);
+        let mut graph_cursor = GraphemeCursor::new(offset, self.len());
+        let mut leaf = cursor.get_leaf();
+        while let Some((l, offset_in_leaf)) = leaf {
+            match f(&mut graph_cursor, l, cursor.pos() - offset_in_leaf) {
+                Ok(result) => return result,
+                Err(GraphemeIncomplete::PrevChunk) => leaf = cursor.prev_leaf(),
+                Err(GraphemeIncomplete::NextChunk) => leaf = cursor.next_leaf(),
+            }
+        }
+        None
     }
 
     /// Return the line number corresponding to the byte index `offset`.
@@ -289,6 +406,45 @@ impl Rope {
         self.convert_metrics::<LinesMetric, BaseMetric>(line)
     }
 
+    /// Return the number of UTF-16 code units before the byte index `offset`,
+    /// as used for positions by the Language Server Protocol, JavaScript and
+    /// Cocoa.
+    ///
+    /// Time complexity: O(log n)
+    pub fn utf16_of_offset(&self, offset: usize) -> usize {
+        self.convert_metrics::<BaseMetric, Utf16CodeUnitsMetric>(offset)
+    }
+
+    /// Return the byte index after `utf16_offset` UTF-16 code units. An offset
+    /// in the middle of a surrogate pair gives the end of the pair, and one
+    /// past the end of the text gives the end of the text.
+    ///
+    /// Time complexity: O(log n)
+    pub fn offset_of_utf16(&self, utf16_offset: usize) -> usize {
+        if utf16_offset >= self.measure::<Utf16CodeUnitsMetric>() {
+            return self.len();
+        }
+        self.convert_metrics::<Utf16CodeUnitsMetric, BaseMetric>(utf16_offset)
+    }
+
+    /// Return the number of code points before the byte index `offset`.
+    ///
+    /// Time complexity: O(log n)
+    pub fn codepoint_of_offset(&self, offset: usize) -> usize {
+        self.convert_metrics::<BaseMetric, CodepointsMetric>(offset)
+    }
+
+    /// Return the byte index after `codepoint_offset` code points, or the end
+    /// of the text if it has fewer.
+    ///
+    /// Time complexity: O(log n)
+    pub fn offset_of_codepoint(&self, codepoint_offset: usize) -> usize {
+        if codepoint_offset >= self.measure::<CodepointsMetric>() {
+            return self.len();
+        }
+        self.convert_metrics::<CodepointsMetric, BaseMetric>(codepoint_offset)
+    }
+
     /// Returns an iterator over chunks of the rope.
     ///
     /// Each chunk is a `&str` slice borrowed from the rope's storage. The size
@@ -504,7 +660,7 @@ impl<'a> Iterator for Lines<'a> {
 
 #[cfg(test)]
 mod tests {
-    use rope::Rope;
+    use rope::{Rope, BaseMetric, Utf16CodeUnitsMetric, CodepointsMetric};
 
     #[test]
     fn replace_small() {
@@ -547,4 +703,86 @@ mod tests {
         */
     }
 
+    #[test]
+    fn grapheme_offset_small() {
+        let a = Rope::from("e\u{0301}\u{1F1FA}\u{1F1F8}\u{1F1EB}\r\n");
+        assert_eq!(Some(3), a.next_grapheme_offset(0));
+        assert_eq!(Some(11), a.next_grapheme_offset(3));
+        assert_eq!(Some(15), a.next_grapheme_offset(11));
+        assert_eq!(Some(17), a.next_grapheme_offset(15));
+        assert_eq!(None, a.next_grapheme_offset(17));
+        assert_eq!(Some(15), a.prev_grapheme_offset(17));
+        assert_eq!(Some(11), a.prev_grapheme_offset(15));
+        assert_eq!(Some(3), a.prev_grapheme_offset(11));
+        assert_eq!(Some(0), a.prev_grapheme_offset(3));
+        assert_eq!(None, a.prev_grapheme_offset(0));
+    }
+
+    #[test]
+    fn grapheme_offset_across_leaves() {
+        // enough flags to span several leaves
+        let flags = "\u{1F1FA}\u{1F1F8}".repeat(300);
+        let a = Rope::from(format!("a{}", flags));
+        let mut offset = a.len();
+        while let Some(prev) = a.prev_grapheme_offset(offset) {
+            assert_eq!(Some(offset), a.next_grapheme_offset(prev));
+            assert!(prev == 0 || (offset - prev) == 8);
+            offset = prev;
+        }
+        assert_eq!(Some(1), a.prev_grapheme_offset(9));
+    }
+
+    #[test]
+    fn utf16_and_codepoint_metrics() {
+        let a = Rope::from("a\u{00A1}\u{4E00}\u{1F4A9}");
+        assert_eq!(5, a.measure::<Utf16CodeUnitsMetric>());
+        assert_eq!(4, a.measure::<CodepointsMetric>());
+        assert_eq!(3, a.convert_metrics::<BaseMetric, Utf16CodeUnitsMetric>(6));
+        assert_eq!(5, a.convert_metrics::<BaseMetric, Utf16CodeUnitsMetric>(10));
+        assert_eq!(6, a.convert_metrics::<Utf16CodeUnitsMetric, BaseMetric>(3));
+        // the middle of a surrogate pair goes to its end
+        assert_eq!(10, a.convert_metrics::<Utf16CodeUnitsMetric, BaseMetric>(4));
+        assert_eq!(3, a.convert_metrics::<BaseMetric, CodepointsMetric>(6));
+        assert_eq!(3, a.convert_metrics::<CodepointsMetric, BaseMetric>(2));
+        assert_eq!(10, a.convert_metrics::<CodepointsMetric, BaseMetric>(4));
+    }
+
+    #[test]
+    fn lsp_positions() {
+        // an LSP position is a line and a UTF-16 offset within it
+        let a = Rope::from("fn main() {\n    let s = \"\u{1F600}\u{00E9}\";\n}\n");
+        let line_start = a.offset_of_line(1);
+        let to_lsp = |offset| a.utf16_of_offset(offset) - a.utf16_of_offset(line_start);
+        let from_lsp = |character| a.offset_of_utf16(a.utf16_of_offset(line_start) + character);
+        let offset = a.slice_to_string(0, a.len()).find(';').unwrap();
+        assert_eq!(17, to_lsp(offset));
+        assert_eq!(offset, from_lsp(17));
+        assert_eq!(16, a.codepoint_of_offset(offset) - a.codepoint_of_offset(line_start));
+        assert_eq!(offset, a.offset_of_codepoint(a.codepoint_of_offset(line_start) + 16));
+    }
+
+    #[test]
+    fn utf16_and_codepoint_metrics_across_leaves() {
+        let line = "a\u{00A1}\u{4E00}\u{1F4A9}\n";
+        let a = Rope::from(line.repeat(200));
+        assert_eq!(1200, a.measure::<Utf16CodeUnitsMetric>());
+        assert_eq!(1000, a.measure::<CodepointsMetric>());
+        for i in 0..200 {
+            let offset = a.offset_of_line(i) + 6;
+            assert_eq!(i * 6 + 3, a.convert_metrics::<BaseMetric, Utf16CodeUnitsMetric>(offset));
+            assert_eq!(offset, a.convert_metrics::<Utf16CodeUnitsMetric, BaseMetric>(i * 6 + 3));
+            assert_eq!(i * 5 + 3, a.convert_metrics::<BaseMetric, CodepointsMetric>(offset));
+            assert_eq!(offset, a.convert_metrics::<CodepointsMetric, BaseMetric>(i * 5 + 3));
+        }
+        assert_eq!(1200, a.convert_metrics::<BaseMetric, Utf16CodeUnitsMetric>(a.len()));
+        assert_eq!(a.len(), a.convert_metrics::<Utf16CodeUnitsMetric, BaseMetric>(1200));
+        assert_eq!(1200, a.utf16_of_offset(a.len()));
+        assert_eq!(a.len(), a.offset_of_utf16(5000));
+        assert_eq!(a.len(), a.offset_of_codepoint(5000));
+        // edits keep the counts up to date
+        let mut b = a.clone();
+        b.edit_str(0, 11, "");
+        assert_eq!(1194, b.measure::<Utf16CodeUnitsMetric>());
+        assert_eq!(995, b.measure::<CodepointsMetric>());
+    }
 }
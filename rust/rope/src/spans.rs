@@ -42,7 +42,8 @@ pub struct SpansLeaf<T: Clone> {
 #[derive(Clone)]
 pub struct SpansInfo<T> {
     n_spans: usize,
-    iv: Interval,
+    len: usize,  // of the subtree, which `iv` of the next one is translated by
+    iv: Interval,  // encloses the spans, from the start of the subtree
     phantom: PhantomData<T>,
 }
 
@@ -92,7 +93,8 @@ impl<T: Clone + Default> NodeInfo for SpansInfo<T> {
 
     fn accumulate(&mut self, other: &Self) {
         self.n_spans += other.n_spans;
-        self.iv = self.iv.union(other.iv);
+        self.iv = self.iv.union(other.iv.translate(self.len));
+        self.len += other.len;
     }
 
     fn compute_info(l: &SpansLeaf<T>) -> Self {
@@ -102,6 +104,7 @@ impl<T: Clone + Default> NodeInfo for SpansInfo<T> {
         }
         SpansInfo {
             n_spans: l.spans.len(),
+            len: l.len,
             iv: iv,
             phantom: PhantomData,
         }
@@ -154,15 +157,88 @@ pub struct SpanIter<'a, T: 'a + Clone + Default> {
     ix: usize,
 }
 
+pub struct SpanRangeIter<'a, T: 'a + Clone + Default> {
+    iv: Interval,
+    stack: Vec<(&'a Spans<T>, usize)>,  // subtrees left to visit and their offsets, last first
+    leaf: Option<(&'a SpansLeaf<T>, usize)>,
+    ix: usize,
+}
+
 impl<T: Clone + Default> Spans<T> {
-    // possible future: an iterator that takes an interval, so results are the same as
-    // taking a subseq on the spans object. Would require specialized Cursor.
     pub fn iter(&self) -> SpanIter<T> {
         SpanIter {
             cursor: Cursor::new(self, 0),
             ix: 0,
         }
     }
+
+    /// The spans that intersect `iv`, whole and in order, as `iter` gives
+    /// them. Unlike taking a `subseq`, this copies nothing, and subtrees
+    /// without any spans in `iv` are skipped.
+    pub fn iter_range(&self, iv: Interval) -> SpanRangeIter<T> {
+        SpanRangeIter {
+            iv: iv,
+            stack: vec![(self, 0)],
+            leaf: None,
+            ix: 0,
+        }
+    }
+
+    /// The spans that contain `offset`, such as the ones under the mouse.
+    pub fn spans_at(&self, offset: usize) -> SpanRangeIter<T> {
+        self.iter_range(Interval::new_closed_closed(offset, offset))
+    }
+
+    /// The number of spans starting before `offset`, found by adding up the
+    /// counts of whole subtrees, so it's O(log n).
+    pub fn count_before(&self, offset: usize) -> usize {
+        let mut count = 0;
+        let mut node = self;
+        let mut node_offset = 0;
+        'descend: while !node.is_leaf() {
+            for child in node.get_children() {
+                if node_offset + child.len() <= offset {
+                    count += child.info().n_spans;
+                    node_offset += child.len();
+                } else {
+                    node = child;
+                    continue 'descend;
+                }
+            }
+            return count;
+        }
+        let spans = &node.get_leaf().spans;
+        count + spans.iter().take_while(|span| node_offset + span.iv.start() < offset).count()
+    }
+
+    /// The span at index `n`, counting from 0 in the order `iter` gives them,
+    /// found in O(log n).
+    pub fn nth(&self, mut n: usize) -> Option<(Interval, &T)> {
+        let mut node = self;
+        let mut node_offset = 0;
+        'descend: while !node.is_leaf() {
+            for child in node.get_children() {
+                if n < child.info().n_spans {
+                    node = child;
+                    continue 'descend;
+                }
+                n -= child.info().n_spans;
+                node_offset += child.len();
+            }
+            return None;
+        }
+        node.get_leaf().spans.get(n).map(|span| (span.iv.translate(node_offset), &span.data))
+    }
+
+    /// Update the spans for an edit to the text they annotate, which replaced
+    /// the interval `iv` with `new_len` units of new text. Spans after the edit
+    /// move along with the text. The parts of spans inside `iv` are removed, so
+    /// a span overlapping it shrinks, and one enclosing it is broken in two;
+    /// the new text has no spans. That suits syntax highlighting, which redoes
+    /// the edited region anyway, but not rich text.
+    pub fn apply_edit(&mut self, iv: Interval, new_len: usize) {
+        self.edit(iv, SpansBuilder::new(new_len).build());
+    }
 }
 
 impl<'a, T: Clone + Default> Iterator for SpanIter<'a, T> {
@@ -183,3 +259,161 @@ impl<'a, T: Clone + Default> Iterator for SpanIter<'a, T> {
         None
     }
 }
+
+impl<'a, T: Clone + Default> Iterator for SpanRangeIter<'a, T> {
+    type Item = (Interval, &'a T);
+
+    fn next(&mut self) -> Option<(Interval, &'a T)> {
+        loop {
+            if let Some((leaf, offset)) = self.leaf {
+                while self.ix < leaf.spans.len() {
+                    let span = &leaf.spans[self.ix];
+                    self.ix += 1;
+                    let span_iv = span.iv.translate(offset);
+                    if !span_iv.intersect(self.iv).is_empty() {
+                        return Some((span_iv, &span.data));
+                    }
+                }
+                self.leaf = None;
+            }
+            let (node, offset) = match self.stack.pop() {
+                Some(next) => next,
+                None => return None,
+            };
+            if node.info().iv.translate(offset).intersect(self.iv).is_empty() {
+                continue;
+            }
+            if node.is_leaf() {
+                self.leaf = Some((node.get_leaf(), offset));
+                self.ix = 0;
+            } else {
+                let mut child_offset = offset + node.len();
+                for child in node.get_children().iter().rev() {
+                    child_offset -= child.len();
+                    self.stack.push((child, child_offset));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use interval::Interval;
+    use super::{Spans, SpansBuilder};
+
+    fn build(len: usize, spans: &[(usize, usize, u32)]) -> Spans<u32> {
+        let mut sb = SpansBuilder::new(len);
+        for &(start, end, data) in spans {
+            sb.add_span(Interval::new_closed_open(start, end), data);
+        }
+        sb.build()
+    }
+
+    fn contents(spans: &Spans<u32>) -> Vec<(usize, usize, u32)> {
+        spans.iter().map(|(iv, &data)| (iv.start(), iv.end(), data)).collect()
+    }
+
+    fn in_range(spans: &Spans<u32>, start: usize, end: usize) -> Vec<(usize, usize, u32)> {
+        spans.iter_range(Interval::new_closed_open(start, end))
+            .map(|(iv, &data)| (iv.start(), iv.end(), data)).collect()
+    }
+
+    fn at(spans: &Spans<u32>, offset: usize) -> Vec<u32> {
+        spans.spans_at(offset).map(|(_, &data)| data).collect()
+    }
+
+    #[test]
+    fn apply_edit() {
+        let spans = build(20, &[(0, 2, 1), (4, 10, 2), (12, 14, 3), (16, 18, 4)]);
+        let edit = |start: usize, end: usize, new_len: usize| {
+            let mut spans = spans.clone();
+            spans.apply_edit(Interval::new_closed_open(start, end), new_len);
+            (spans.len(), contents(&spans))
+        };
+        // spans after an insertion move, and one around it is broken in two
+        assert_eq!((23, vec![(0, 2, 1), (4, 6, 2), (9, 13, 2), (15, 17, 3), (19, 21, 4)]),
+            edit(6, 6, 3));
+        // spans overlapping a deletion shrink, and ones inside it go
+        assert_eq!((12, vec![(0, 2, 1), (4, 8, 2), (8, 10, 4)]), edit(8, 16, 0));
+        // a replacement has no spans
+        assert_eq!((21, vec![(0, 2, 1), (4, 5, 2), (13, 15, 3), (17, 19, 4)]),
+            edit(5, 11, 7));
+        assert_eq!((20, vec![(0, 2, 1), (4, 10, 2), (12, 14, 3), (16, 18, 4)]),
+            edit(20, 20, 0));
+    }
+
+    #[test]
+    fn apply_edit_many_leaves() {
+        let n = 500;
+        let mut spans = build(n * 4, &(0..n).map(|i| (i * 4, i * 4 + 2, i as u32))
+            .collect::<Vec<_>>());
+        spans.apply_edit(Interval::new_closed_open(1, n * 4 - 1), 2);
+        assert_eq!((4, vec![(0, 1, 0)]), (spans.len(), contents(&spans)));
+        let mut spans = build(n * 4, &(0..n).map(|i| (i * 4, i * 4 + 2, i as u32))
+            .collect::<Vec<_>>());
+        spans.apply_edit(Interval::new_closed_open(n * 2, n * 2), 1);
+        let moved = contents(&spans);
+        assert_eq!(n, moved.len());
+        assert_eq!((n * 2 - 4, n * 2 - 2, n as u32 / 2 - 1), moved[n / 2 - 1]);
+        assert_eq!((n * 2 + 1, n * 2 + 3, n as u32 / 2), moved[n / 2]);
+    }
+
+    #[test]
+    fn iter_range() {
+        let spans = build(20, &[(0, 2, 1), (4, 10, 2), (5, 7, 3), (12, 14, 4)]);
+        // spans overlapping the range are given whole
+        assert_eq!(vec![(4, 10, 2), (5, 7, 3)], in_range(&spans, 6, 11));
+        assert_eq!(vec![(0, 2, 1), (4, 10, 2), (5, 7, 3), (12, 14, 4)], in_range(&spans, 0, 20));
+        assert!(in_range(&spans, 2, 4).is_empty());
+        assert!(in_range(&spans, 5, 5).is_empty());
+        assert_eq!(vec![1], at(&spans, 0));
+        assert!(at(&spans, 2).is_empty());
+        assert_eq!(vec![2, 3], at(&spans, 6));
+        assert_eq!(vec![2], at(&spans, 7));
+        assert!(at(&spans, 20).is_empty());
+    }
+
+    #[test]
+    fn iter_range_many_leaves() {
+        let n = 500;
+        let mut spans = build(n * 4, &(0..n).map(|i| (i * 4, i * 4 + 2, i as u32))
+            .collect::<Vec<_>>());
+        // and after edits, which rebuild the tree from pieces of it
+        spans.apply_edit(Interval::new_closed_open(n, n * 2), 0);
+        spans.apply_edit(Interval::new_closed_open(n / 2, n / 2), 7);
+        let all = contents(&spans);
+        for &(start, end) in &[(0, 1), (3, 9), (n, n + 100), (n * 2 - 2, n * 3), (0, n * 4)] {
+            let expected = all.iter().cloned()
+                .filter(|&(s, e, _)| s < end && start < e)
+                .collect::<Vec<_>>();
+            assert_eq!(expected, in_range(&spans, start, end));
+        }
+        let (start, end, data) = all[all.len() - 1];
+        assert_eq!(vec![data], at(&spans, start));
+        assert!(at(&spans, end).is_empty());
+    }
+
+    #[test]
+    fn count_before_and_nth() {
+        let spans = build(20, &[(0, 2, 1), (4, 10, 2), (5, 7, 3), (12, 14, 4)]);
+        assert_eq!(0, spans.count_before(0));
+        assert_eq!(1, spans.count_before(1));
+        assert_eq!(3, spans.count_before(6));
+        assert_eq!(4, spans.count_before(20));
+        assert_eq!(Some((Interval::new_closed_open(5, 7), &3)), spans.nth(2));
+        assert_eq!(None, spans.nth(4));
+        let n = 500;
+        let mut spans = build(n * 4, &(0..n).map(|i| (i * 4, i * 4 + 2, i as u32))
+            .collect::<Vec<_>>());
+        spans.apply_edit(Interval::new_closed_open(n, n * 2), 0);
+        let all = contents(&spans);
+        for (ix, &(start, end, data)) in all.iter().enumerate() {
+            assert_eq!(ix, spans.count_before(start));
+            assert_eq!(ix + 1, spans.count_before(start + 1));
+            assert_eq!(Some((Interval::new_closed_open(start, end), &data)), spans.nth(ix));
+        }
+        assert_eq!(all.len(), spans.count_before(spans.len()));
+        assert_eq!(None, spans.nth(all.len()));
+    }
+}
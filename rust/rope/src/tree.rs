@@ -180,15 +180,20 @@ impl<N: NodeInfo> Node<N> {
         self.0.height
     }
     
-    fn is_leaf(&self) -> bool {
+    pub fn is_leaf(&self) -> bool {
         self.0.height == 0
     }
 
+    /// The info of the whole subtree, as accumulated from its leaves.
+    pub fn info(&self) -> &N {
+        &self.0.info
+    }
+
     fn interval(&self) -> Interval {
         self.0.info.interval(self.0.len)
     }
 
-    fn get_children(&self) -> &[Node<N>] {
+    pub fn get_children(&self) -> &[Node<N>] {
         if let NodeVal::Internal(ref v) = self.0.val {
             v
         } else {
@@ -196,7 +201,7 @@ impl<N: NodeInfo> Node<N> {
         }
     }
 
-    fn get_leaf(&self) -> &N::L {
+    pub fn get_leaf(&self) -> &N::L {
         if let NodeVal::Leaf(ref l) = self.0.val {
             l
         } else {
@@ -0,0 +1,76 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The units in which columns are exchanged with the front-end.
+
+use std::cmp::min;
+
+use xi_rope::rope::Rope;
+
+/// How columns within a line are counted on the RPC boundary. The front-end
+/// chooses one with `negotiate_column_unit`; UTF-8 is the default.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColumnUnit {
+    Utf8,
+    Utf16,
+    Codepoints,
+}
+
+impl Default for ColumnUnit {
+    fn default() -> ColumnUnit {
+        ColumnUnit::Utf8
+    }
+}
+
+impl ColumnUnit {
+    pub fn from_name(name: &str) -> Option<ColumnUnit> {
+        match name {
+            "utf8" => Some(ColumnUnit::Utf8),
+            "utf16" => Some(ColumnUnit::Utf16),
+            "codepoints" => Some(ColumnUnit::Codepoints),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ColumnUnit::Utf8 => "utf8",
+            ColumnUnit::Utf16 => "utf16",
+            ColumnUnit::Codepoints => "codepoints",
+        }
+    }
+
+    /// The column of `offset` in the line starting at `line_start`.
+    pub fn col_of_offset(&self, text: &Rope, line_start: usize, offset: usize) -> usize {
+        match *self {
+            ColumnUnit::Utf8 => offset - line_start,
+            ColumnUnit::Utf16 => text.utf16_of_offset(offset) - text.utf16_of_offset(line_start),
+            ColumnUnit::Codepoints =>
+                text.codepoint_of_offset(offset) - text.codepoint_of_offset(line_start),
+        }
+    }
+
+    /// The offset of column `col` in the line starting at `line_start`,
+    /// clamped to the end of the text. It may be past the end of the line;
+    /// it's up to the caller to clamp it to a boundary within the line.
+    pub fn offset_of_col(&self, text: &Rope, line_start: usize, col: usize) -> usize {
+        match *self {
+            ColumnUnit::Utf8 => min(line_start.saturating_add(col), text.len()),
+            ColumnUnit::Utf16 =>
+                text.offset_of_utf16(text.utf16_of_offset(line_start).saturating_add(col)),
+            ColumnUnit::Codepoints =>
+                text.offset_of_codepoint(text.codepoint_of_offset(line_start).saturating_add(col)),
+        }
+    }
+}
@@ -12,219 +12,630 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::cmp::max;
-use std::fs::File;
-use std::io::{Read,Write};
+use std::cmp::{min, max};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
 use std::sync::Mutex;
+use std::sync::mpsc::Sender;
 use serde_json::Value;
+use serde_json::builder::ObjectBuilder;
 
 use xi_rope::rope::{LinesMetric,Rope,RopeInfo};
 use xi_rope::interval::Interval;
-use xi_rope::delta::OldDelta;
+use xi_rope::delta::{Delta, DeltaBuilder};
 use xi_rope::tree::Cursor;
+use xi_rope::engine::Engine;
 use view::View;
+use columns::ColumnUnit;
+use find::Query;
+use encoding;
+use encoding::Encoding;
+use files;
+use files::FileStamp;
+use line_ending;
+use line_ending::LineEnding;
+use highlight::Syntax;
+use selection::{Selection, SelRegion};
+use styles::StyleMap;
+use word_boundaries::{prev_word_offset, next_word_offset, segment_around};
+use plugins::PluginPeer;
 
-use tabs::update_tab;
+use tabs::{alert, def_styles, ok_response, update_tab};
+use ::MainMsg;
 
 const MODIFIER_SHIFT: u64 = 2;
+const MODIFIER_COMMAND: u64 = 16;
 
+// The priority of the user's own edits. Plugins' edits must have a higher one,
+// as the engine can't order insertions at the same place with equal priorities.
+const USER_PRIORITY: usize = 0;
+const MIN_PLUGIN_PRIORITY: usize = USER_PRIORITY + 1;
+
+/// A buffer, along with all the views onto it. Each view is identified by the
+/// name of the tab showing it, which is used for sending updates back to the
+/// front-end.
 pub struct Editor {
-    tabname: String,  // used for sending updates back to front-end
+    buffer_id: usize,  // used to route messages from plugins back to us
 
     text: Rope,
+    encoding: Encoding,  // of the file the text was loaded from, used to save it
+    line_ending: LineEnding,  // likewise; the text itself only has `\n`
+    path: Option<PathBuf>,  // the file last opened or saved
+    file_stamp: Option<FileStamp>,  // what that file was like then
+    pristine_rev_id: usize,  // the revision of the text that was in that file
+    pristine_line_ending: LineEnding,  // the line ending that file had
+
+    // The view the current command came from; the others are kept in
+    // `other_views`, and swapped in by `set_view`.
     view: View,
-    delta: OldDelta<RopeInfo>,
+    view_id: String,
+    other_views: BTreeMap<String, View>,
+    column_unit: ColumnUnit,  // shared by all the views
+    styles: Rc<RefCell<StyleMap>>,  // shared by all the buffers
+
+    engine: Engine,
+    last_rev_id: usize,
+    undo_group_id: usize,
+    live_undos: Vec<usize>,  // undo groups that may still be toggled
+    cur_undo: usize,  // index into live_undos; groups at and after this are undone
+    undos: BTreeSet<usize>,  // undo groups that are undone
+    gc_undos: BTreeSet<usize>,  // undo groups that are no longer live and should be gc'ed
+
+    this_edit_type: EditType,
+    last_edit_type: EditType,
+
+    plugins: Vec<PluginPeer>,
+    plugin_tx: Sender<MainMsg>,
 
-    // update to cursor, to be committed atomically with delta
-    // TODO: use for all cursor motion?
-    new_cursor: Option<usize>,
+    // update to selection, to be committed atomically with delta
+    new_sel: Option<Selection>,
+}
 
-    dirty: bool,
-    scroll_to: Option<usize>,
-    col: usize  // maybe this should live in view, it's similar to selection
+// Consecutive edits of the same type (other than `Other`) are merged into
+// a single undo group, so that (for example) undo removes a whole run of
+// typing rather than one character at a time.
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum EditType {
+    Other,
+    InsertChars,
+    Delete,
+    Undo,
 }
 
 impl Editor {
-    pub fn new(tabname: &str) -> Editor {
+    /// Create an empty buffer, with a single view shown in the tab `tabname`.
+    pub fn new(buffer_id: usize, tabname: &str, plugin_tx: Sender<MainMsg>,
+            styles: Rc<RefCell<StyleMap>>) -> Editor {
+        let engine = Engine::new(Rope::from(""));
+        let last_rev_id = engine.get_head_rev_id();
         Editor {
-            tabname: tabname.to_string(),
+            buffer_id: buffer_id,
             text: Rope::from(""),
+            encoding: Encoding::default(),
+            line_ending: LineEnding::default(),
+            path: None,
+            file_stamp: None,
+            pristine_rev_id: last_rev_id,
+            pristine_line_ending: LineEnding::default(),
             view: View::new(),
-            dirty: false,
-            delta: OldDelta::new(),
-            new_cursor: None,
-            scroll_to: Some(0),
-            col: 0
+            view_id: tabname.to_string(),
+            other_views: BTreeMap::new(),
+            column_unit: ColumnUnit::default(),
+            styles: styles,
+            engine: engine,
+            last_rev_id: last_rev_id,
+            undo_group_id: 0,
+            live_undos: Vec::new(),
+            cur_undo: 0,
+            undos: BTreeSet::new(),
+            gc_undos: BTreeSet::new(),
+            this_edit_type: EditType::Other,
+            last_edit_type: EditType::Other,
+            plugins: Vec::new(),
+            plugin_tx: plugin_tx,
+            new_sel: None,
         }
     }
 
-    fn insert(&mut self, s: &str) {
-        let sel_interval = Interval::new_closed_open(self.view.sel_min(), self.view.sel_max());
-        let new_cursor = self.view.sel_min() + s.len();
-        self.add_delta(sel_interval, Rope::from(s), new_cursor);
+    /// Add a new view onto the buffer, shown in the tab `tabname`. It starts
+    /// with the cursor at the beginning, and is sent to the front-end when it
+    /// next makes a request in that tab.
+    pub fn add_view(&mut self, tabname: &str) {
+        let mut view = View::new();
+        view.reset(&self.text);
+        view.set_column_unit(&self.text, self.column_unit);
+        view.set_syntax(&self.text, self.view.syntax());
+        self.other_views.insert(tabname.to_string(), view);
     }
 
-    fn set_cursor_impl(&mut self, offset: usize, set_start: bool, hard: bool) {
-        if set_start {
-            self.view.sel_start = offset;
+    /// Remove the view shown in the tab `tabname`. The last view of a buffer
+    /// can't be removed; drop the `Editor` instead.
+    pub fn remove_view(&mut self, tabname: &str) {
+        if self.view_id == tabname {
+            if let Some(new_id) = self.other_views.keys().next().cloned() {
+                self.view = self.other_views.remove(&new_id).unwrap();
+                self.view_id = new_id;
+            }
+        } else {
+            self.other_views.remove(tabname);
         }
-        self.view.sel_end = offset;
-        if hard {
-            self.col = self.view.offset_to_line_col(&self.text, offset).1;
-            self.scroll_to = Some(offset);
+    }
+
+    // Make the view shown in the tab `tabname` the one commands apply to.
+    fn set_view(&mut self, tabname: &str) {
+        if let Some(view) = self.other_views.remove(tabname) {
+            let old_view = mem::replace(&mut self.view, view);
+            let old_id = mem::replace(&mut self.view_id, tabname.to_string());
+            self.other_views.insert(old_id, old_view);
+        }
+    }
+
+    // Replace the whole contents of the buffer, discarding undo history.
+    fn reset_contents(&mut self, text: Rope) {
+        self.engine.reset(text.clone());
+        self.last_rev_id = self.engine.get_head_rev_id();
+        self.pristine_rev_id = self.last_rev_id;
+        self.pristine_line_ending = self.line_ending;
+        self.text = text;
+        self.live_undos.clear();
+        self.cur_undo = 0;
+        self.undos.clear();
+        self.gc_undos.clear();
+        self.view.reset(&self.text);
+        for view in self.other_views.values_mut() {
+            view.reset(&self.text);
         }
-        self.view.scroll_to_cursor(&self.text);
-        self.dirty = true;
+        self.update_plugins(None);
     }
 
+    fn insert(&mut self, s: &str) {
+        self.this_edit_type = EditType::InsertChars;
+        let rope = line_ending::normalize(s);
+        let edits = self.view.selection.iter().map(|region|
+            (Interval::new_closed_open(region.min(), region.max()), rope.clone())
+        ).collect();
+        self.add_edits(edits);
+    }
+
+    // Replace the selection with a single caret.
     fn set_cursor(&mut self, offset: usize, hard: bool) {
-        self.set_cursor_impl(offset, true, hard);
+        self.set_selection(Selection::new_simple(SelRegion::caret(offset)), hard);
+    }
+
+    // Replace the selection. A hard change also scrolls the front-end to the
+    // caret of the last region.
+    fn set_selection(&mut self, selection: Selection, hard: bool) {
+        if hard {
+            self.view.scroll_to = selection.last().map(|region| region.end);
+        }
+        self.view.set_selection(&self.text, selection);
+    }
+
+    // Apply `motion` to each region of the selection, giving the new position of
+    // its caret. With shift, the regions are extended rather than moved.
+    fn move_selection<F>(&mut self, flags: u64, motion: F)
+            where F: Fn(&View, &Rope, &SelRegion) -> SelRegion {
+        let mut selection = Selection::new();
+        for region in self.view.selection.iter() {
+            let new_region = motion(&self.view, &self.text, region);
+            if flags & MODIFIER_SHIFT != 0 {
+                selection.add_region(SelRegion { start: region.start, ..new_region });
+            } else {
+                selection.add_region(new_region);
+            }
+        }
+        self.set_selection(selection, true);
     }
 
-    // set the cursor or update the selection, depending on the flags
-    fn set_cursor_or_sel(&mut self, offset: usize, flags: u64, hard: bool) {
-        self.set_cursor_impl(offset, flags & MODIFIER_SHIFT == 0, hard);
+    // Make an edit replacing each interval (in order, and not overlapping) with
+    // the corresponding text. The selection becomes a caret after each replacement.
+    fn add_edits(&mut self, edits: Vec<(Interval, Rope)>) {
+        if edits.iter().all(|edit| edit.0.is_empty() && edit.1.len() == 0) {
+            return;
+        }
+        let mut builder = DeltaBuilder::new(self.text.len());
+        let mut new_sel = Selection::new();
+        let mut deleted = 0;
+        let mut inserted = 0;
+        for (iv, rope) in edits {
+            let caret = iv.start() - deleted + inserted + rope.len();
+            new_sel.add_region(SelRegion::caret(caret));
+            deleted += iv.end() - iv.start();
+            inserted += rope.len();
+            builder.replace(iv, rope);
+        }
+        let head_rev_id = self.engine.get_head_rev_id();
+        let undo_group = self.calculate_undo_group();
+        self.engine.edit_rev(USER_PRIORITY, undo_group, head_rev_id, builder.build());
+        self.new_sel = Some(new_sel);
     }
 
-    fn add_delta(&mut self, iv: Interval, new: Rope, new_cursor: usize) {
-        self.delta.add(iv, new);
-        self.new_cursor = Some(new_cursor);
+    fn calculate_undo_group(&mut self) -> usize {
+        if self.this_edit_type == self.last_edit_type &&
+                self.this_edit_type != EditType::Other && self.cur_undo > 0 &&
+                self.cur_undo == self.live_undos.len() {
+            self.live_undos[self.cur_undo - 1]
+        } else {
+            // A new edit makes undone groups unreachable by redo, so they're
+            // garbage collected. The history of the rest is kept, however long.
+            let undo_group = self.undo_group_id;
+            self.gc_undos.extend(self.live_undos.drain(self.cur_undo..));
+            self.live_undos.push(undo_group);
+            self.cur_undo += 1;
+            self.undo_group_id += 1;
+            undo_group
+        }
     }
 
     // commit the current delta, updating views and other invariants as needed
     fn commit_delta(&mut self) {
-        if !self.delta.is_empty() {
-            self.view.before_edit(&self.text, &self.delta);
-            self.delta.apply(&mut self.text);
-            self.view.after_edit(&self.text, &self.delta);
-            if let Some(c) = self.new_cursor {
-                self.set_cursor(c, true);
-                self.new_cursor = None;
+        if self.engine.get_head_rev_id() != self.last_rev_id {
+            let delta = self.engine.delta_rev_head(self.last_rev_id);
+            self.view.before_edit(&self.text, &delta);
+            for view in self.other_views.values_mut() {
+                view.before_edit(&self.text, &delta);
+            }
+            self.text = self.engine.get_head();
+            self.view.after_edit(&self.text, &delta);
+            for view in self.other_views.values_mut() {
+                view.after_edit(&self.text, &delta);
             }
-            self.dirty = true;
-            self.delta = OldDelta::new();
+            match self.new_sel.take() {
+                Some(sel) => self.set_selection(sel, true),
+                // undo and redo leave the cursor after the change
+                None if self.this_edit_type == EditType::Undo => {
+                    let (iv, new_len) = delta.summary();
+                    self.set_cursor(iv.start() + new_len, true);
+                }
+                // edits made by plugins carry the selection along with the text
+                None => self.view.scroll_to_cursor(&self.text),
+            }
+            self.last_rev_id = self.engine.get_head_rev_id();
+            self.update_plugins(Some(&delta));
+        }
+        if !self.gc_undos.is_empty() {
+            self.engine.gc(&self.gc_undos);
+            self.undos = &self.undos - &self.gc_undos;
+            self.gc_undos.clear();
         }
     }
 
-    // render if needed, sending to ui
-    fn render(&mut self) {
-        if self.dirty {
-            update_tab(&self.view.render(&self.text, self.scroll_to), &self.tabname);
-            self.dirty = false;
-            self.scroll_to = None;
+    // The update sent to plugins for the head revision: either the delta from the
+    // previous update, or the whole text.
+    fn plugin_update_params(&self, delta: Option<&Delta<RopeInfo>>) -> Value {
+        let builder = ObjectBuilder::new()
+            .insert("rev", self.engine.get_head_rev_id());
+        match delta {
+            Some(delta) => builder.insert("delta", delta),
+            None => builder.insert("text", String::from(self.text.clone())),
+        }.unwrap()
+    }
+
+    fn update_plugins(&mut self, delta: Option<&Delta<RopeInfo>>) {
+        if !self.plugins.is_empty() {
+            let params = self.plugin_update_params(delta);
+            for plugin in &mut self.plugins {
+                plugin.send_rpc("update", &params);
+            }
         }
     }
 
-    fn delete_backward(&mut self) {
-        let start = if self.view.sel_start != self.view.sel_end {
-            self.view.sel_min()
-        } else {
-            if let Some(bsp_pos) = self.text.prev_codepoint_offset(self.view.sel_end) {
-            // TODO: implement complex emoji logic
-                bsp_pos
-           } else {
-                self.view.sel_max()
+    fn update_undos(&mut self) {
+        self.engine.undo(self.undos.clone());
+    }
+
+    fn undo(&mut self) {
+        if self.cur_undo > 0 {
+            self.cur_undo -= 1;
+            self.undos.insert(self.live_undos[self.cur_undo]);
+            self.this_edit_type = EditType::Undo;
+            self.update_undos();
+        }
+    }
+
+    fn redo(&mut self) {
+        if self.cur_undo < self.live_undos.len() {
+            self.undos.remove(&self.live_undos[self.cur_undo]);
+            self.cur_undo += 1;
+            self.this_edit_type = EditType::Undo;
+            self.update_undos();
+        }
+    }
+
+    /// Change the units of columns exchanged with the front-end, for all views.
+    /// They resend their lines with the next update.
+    pub fn set_column_unit(&mut self, unit: ColumnUnit) {
+        self.column_unit = unit;
+        self.view.set_column_unit(&self.text, unit);
+        for view in self.other_views.values_mut() {
+            view.set_column_unit(&self.text, unit);
+        }
+    }
+
+    /// Resend the lines of all the views, as their styles have changed.
+    pub fn restyle(&mut self) {
+        self.view.restyle();
+        for view in self.other_views.values_mut() {
+            view.restyle();
+        }
+        self.render();
+    }
+
+    // render the views that need it, sending to ui
+    fn render(&mut self) {
+        let mut styles = self.styles.borrow_mut();
+        if self.view.dirty {
+            let update = self.view.render(&self.text, &mut styles);
+            def_styles(&mut styles);
+            update_tab(&update, &self.view_id);
+        }
+        for (tabname, view) in &mut self.other_views {
+            if view.dirty {
+                let update = view.render(&self.text, &mut styles);
+                def_styles(&mut styles);
+                update_tab(&update, tabname);
             }
-        };
-        if start < self.view.sel_max() {
-            let del_interval = Interval::new_closed_open(start, self.view.sel_max());
-            self.add_delta(del_interval, Rope::from(""), start);
         }
     }
 
+    // Delete the selected regions or, for carets, the text between the caret
+    // and the position `motion` gives.
+    fn delete_by_motion<F>(&mut self, motion: F)
+            where F: Fn(&Rope, usize) -> usize {
+        self.this_edit_type = EditType::Delete;
+        let mut edits = Vec::new();
+        let mut last_end = 0;
+        for region in self.view.selection.iter() {
+            let (start, end) = if !region.is_caret() {
+                (region.min(), region.max())
+            } else {
+                let offset = motion(&self.text, region.end);
+                (min(offset, region.end), max(offset, region.end))
+            };
+            // regions whose deletions overlap an earlier one's are clamped
+            let start = max(start, last_end);
+            let end = max(end, start);
+            edits.push((Interval::new_closed_open(start, end), Rope::from("")));
+            last_end = end;
+        }
+        self.add_edits(edits);
+    }
+
+    fn delete_backward(&mut self) {
+        self.delete_by_motion(|text, offset| text.prev_grapheme_offset(offset).unwrap_or(offset));
+    }
+
+    fn delete_word_backward(&mut self) {
+        self.delete_by_motion(prev_word_offset);
+    }
+
+    fn delete_word_forward(&mut self) {
+        self.delete_by_motion(next_word_offset);
+    }
+
+    // The buffer's own line ending is only used when it's saved.
     fn insert_newline(&mut self) {
         self.insert("\n");
     }
-    
+
     fn move_up(&mut self, flags: u64) {
-        let old_offset = self.view.sel_end;
-        let offset = self.view.vertical_motion(&self.text, -1, self.col);
-        self.set_cursor_or_sel(offset, flags, old_offset == offset);
-        self.scroll_to = Some(offset);
+        self.move_selection(flags, |view, text, region| view.vertical_motion(text, region, -1));
     }
 
     fn move_down(&mut self, flags: u64) {
-        let old_offset = self.view.sel_end;
-        let offset = self.view.vertical_motion(&self.text, 1, self.col);
-        self.set_cursor_or_sel(offset, flags, old_offset == offset);
-        self.scroll_to = Some(offset);
+        self.move_selection(flags, |view, text, region| view.vertical_motion(text, region, 1));
     }
 
     fn move_left(&mut self, flags: u64) {
-        if self.view.sel_start != self.view.sel_end && (flags & MODIFIER_SHIFT) == 0 {
-            let offset = self.view.sel_min();
-            self.set_cursor(offset, true);
-        } else {
-            if let Some(offset) = self.text.prev_grapheme_offset(self.view.sel_end) {
-                self.set_cursor_or_sel(offset, flags, true);
+        self.move_selection(flags, |_, text, region| {
+            if !region.is_caret() && (flags & MODIFIER_SHIFT) == 0 {
+                SelRegion::caret(region.min())
             } else {
-                self.col = 0;
-                // TODO: should set scroll_to_cursor in this case too,
-                // but it won't get sent; probably it needs to be a separate cmd
+                SelRegion::caret(text.prev_grapheme_offset(region.end).unwrap_or(region.end))
             }
-        }
+        });
     }
 
     fn move_right(&mut self, flags: u64) {
-        if self.view.sel_start != self.view.sel_end && (flags & MODIFIER_SHIFT) == 0 {
-            let offset = self.view.sel_max();
-            self.set_cursor(offset, true);
-        } else {
-            if let Some(offset) = self.text.next_grapheme_offset(self.view.sel_end) {
-                self.set_cursor_or_sel(offset, flags, true);
+        self.move_selection(flags, |_, text, region| {
+            if !region.is_caret() && (flags & MODIFIER_SHIFT) == 0 {
+                SelRegion::caret(region.max())
             } else {
-                self.col = self.view.offset_to_line_col(&self.text, self.view.sel_end).1;
-                // see above
+                SelRegion::caret(text.next_grapheme_offset(region.end).unwrap_or(region.end))
             }
-        }
+        });
+    }
+
+    // Without shift, a selection moves from its left (or right) edge.
+    fn move_word_left(&mut self, flags: u64) {
+        self.move_selection(flags, |_, text, region| {
+            let from = if flags & MODIFIER_SHIFT == 0 { region.min() } else { region.end };
+            SelRegion::caret(prev_word_offset(text, from))
+        });
+    }
+
+    fn move_word_right(&mut self, flags: u64) {
+        self.move_selection(flags, |_, text, region| {
+            let from = if flags & MODIFIER_SHIFT == 0 { region.max() } else { region.end };
+            SelRegion::caret(next_word_offset(text, from))
+        });
     }
 
     fn cursor_start(&mut self) {
-        let start = self.view.sel_min() - self.col;
-        self.set_cursor(start, true);
+        self.move_selection(0, |_, text, region|
+            SelRegion::caret(text.offset_of_line(text.line_of_offset(region.min())))
+        );
     }
 
     fn cursor_end(&mut self) {
-        let offset = self.cursor_end_offset();
-        self.set_cursor(offset, true);
-    }
-
-    fn cursor_end_offset(&mut self) -> usize {
-        let current = self.view.sel_max();
-        let rope = self.text.clone();
-        let mut cursor = Cursor::new(&rope, current);
-        match cursor.next::<LinesMetric>() {
-            None => current,
-            Some(offset) => {
-                if cursor.is_boundary::<LinesMetric>() {
-                    if let Some(new) = rope.prev_grapheme_offset(offset) {
-                        new
-                    } else {
-                        offset
-                    }
-                } else {
-                    offset
-                }
-            }
-        }
+        self.move_selection(0, |_, text, region|
+            SelRegion::caret(cursor_end_offset(text, region.max()))
+        );
     }
 
     fn scroll_page_up(&mut self, flags: u64) {
         let scroll = -max(self.view.scroll_height() as isize - 2, 1);
-        let old_offset = self.view.sel_end;
-        let offset = self.view.vertical_motion(&self.text, scroll, self.col);
-        self.set_cursor_or_sel(offset, flags, old_offset == offset);
-        let scroll_offset = self.view.vertical_motion(&self.text, scroll, self.col);
-        self.scroll_to = Some(scroll_offset);
+        self.scroll_page(flags, scroll);
     }
 
     fn scroll_page_down(&mut self, flags: u64) {
         let scroll = max(self.view.scroll_height() as isize - 2, 1);
-        let old_offset = self.view.sel_end;
-        let offset = self.view.vertical_motion(&self.text, scroll, self.col);
-        self.set_cursor_or_sel(offset, flags, old_offset == offset);
-        let scroll_offset = self.view.vertical_motion(&self.text, scroll, self.col);
-        self.scroll_to = Some(scroll_offset);
+        self.scroll_page(flags, scroll);
+    }
+
+    // Move the selection by a page, and scroll so that the last caret ends up
+    // at the far edge of the new page.
+    fn scroll_page(&mut self, flags: u64, scroll: isize) {
+        self.move_selection(flags, |view, text, region| view.vertical_motion(text, region, scroll));
+        if let Some(region) = self.view.selection.last() {
+            let scroll_region = self.view.vertical_motion(&self.text, region, scroll);
+            self.view.scroll_to = Some(scroll_region.end);
+        }
+    }
+
+    // Add a caret (or region) on the line above or below each region.
+    fn add_selection_by_movement(&mut self, line_delta: isize) {
+        let mut selection = Selection::new();
+        let mut scroll_to = None;
+        for region in self.view.selection.iter() {
+            selection.add_region(*region);
+            let (line, _) = self.view.caret_line_col(&self.text, region);
+            let end = self.view.vertical_motion(&self.text, region, line_delta);
+            // nothing is added when there is no line to add it on
+            if self.view.caret_line_col(&self.text, &end).0 == line {
+                continue;
+            }
+            let start = if region.is_caret() {
+                end.end
+            } else {
+                self.view.vertical_motion(&self.text, &SelRegion::caret(region.start), line_delta).end
+            };
+            selection.add_region(SelRegion { start: start, ..end });
+            if scroll_to.is_none() || line_delta > 0 {
+                scroll_to = Some(end.end);
+            }
+        }
+        if let Some(offset) = scroll_to {
+            self.view.set_selection(&self.text, selection);
+            self.view.scroll_to = Some(offset);
+        }
+    }
+
+    fn add_selection_above(&mut self) {
+        self.add_selection_by_movement(-1);
+    }
+
+    fn add_selection_below(&mut self) {
+        self.add_selection_by_movement(1);
+    }
+
+    // Add the next occurrence of the text of the last region to the selection,
+    // wrapping around at the end. If the last region is a caret, select the word
+    // around it instead.
+    fn select_next_occurrence(&mut self) {
+        let region = match self.view.selection.last() {
+            Some(region) => *region,
+            None => return,
+        };
+        let mut selection = self.view.selection.clone();
+        if region.is_caret() {
+            let word = segment_around(&self.text, region.end);
+            if word.is_word {
+                selection.add_region(SelRegion::new(word.start, word.end));
+                self.set_selection(selection, true);
+            }
+            return;
+        }
+        let needle = self.text.slice_to_string(region.min(), region.max());
+        let found = {
+            let is_unselected = |start: usize| !self.view.selection
+                .regions_in_range(start, start + needle.len()).iter()
+                .any(|r| r.min() == start && r.max() == start + needle.len());
+            let len = self.text.len();
+            find_in_rope(&self.text, &needle, region.max(), len, is_unselected)
+                .or_else(|| find_in_rope(&self.text, &needle, 0, region.max(), is_unselected))
+        };
+        if let Some(start) = found {
+            selection.add_region(SelRegion::new(start, start + needle.len()));
+            self.set_selection(selection, true);
+        }
+    }
+
+    // Start a search, highlighting its matches and selecting the first one at
+    // or after the start of the last region. An empty or invalid pattern clears
+    // the search.
+    fn do_find(&mut self, args: &Value) {
+        if let Some(dict) = args.as_object() {
+            let chars = dict.get("chars").and_then(|v| v.as_string()).unwrap_or("");
+            let flag = |name: &str| dict.get(name).and_then(|v| v.as_boolean()).unwrap_or(false);
+            if chars.is_empty() {
+                self.view.set_query(&self.text, None);
+                return;
+            }
+            match Query::new(chars, flag("ignore_case"), flag("whole_words"), flag("regex")) {
+                Ok(query) => {
+                    self.view.set_query(&self.text, Some(query));
+                    let from = self.view.selection.last().map_or(0, |region| region.min());
+                    let found = self.view.search().and_then(|search|
+                        search.next_match(from));
+                    if let Some((start, end)) = found {
+                        self.set_selection(Selection::new_simple(SelRegion::new(start, end)), true);
+                    }
+                }
+                Err(e) => {
+                    print_err!("invalid search {:?}: {}", chars, e);
+                    self.view.set_query(&self.text, None);
+                }
+            }
+        }
+    }
+
+    // Select the next match after the last region or, going backward, the
+    // previous one before it, wrapping around at the ends of the text.
+    fn find_next(&mut self, forward: bool) {
+        let found = match (self.view.search(), self.view.selection.last()) {
+            (Some(search), Some(region)) if forward => search.next_match(region.max()),
+            (Some(search), Some(region)) => search.prev_match(region.min()),
+            _ => None,
+        };
+        if let Some((start, end)) = found {
+            self.set_selection(Selection::new_simple(SelRegion::new(start, end)), true);
+        }
+    }
+
+    // Replace the last region, if it is a match, and select the next match.
+    fn do_replace(&mut self, args: &Value) {
+        if let Some(chars) = args.as_object()
+                .and_then(|v| v.get("chars")).and_then(|v| v.as_string()) {
+            let edit = match (self.view.search(), self.view.selection.last()) {
+                (Some(search), Some(region)) if !region.is_caret() =>
+                    search.query().replacement(&self.text, region.min(), region.max(), chars)
+                        .map(|replacement| (Interval::new_closed_open(region.min(), region.max()),
+                            Rope::from(replacement))),
+                _ => None,
+            };
+            if let Some(edit) = edit {
+                self.add_edits(vec![edit]);
+                self.commit_delta();
+            }
+            self.find_next(true);
+        }
+    }
+
+    // Replace every match, as a single edit, so that it's undone in one step.
+    fn do_replace_all(&mut self, args: &Value) {
+        if let Some(chars) = args.as_object()
+                .and_then(|v| v.get("chars")).and_then(|v| v.as_string()) {
+            let edits = match self.view.search() {
+                Some(search) => search.query().replace_all(&self.text, chars),
+                None => return,
+            };
+            self.add_edits(edits);
+        }
     }
 
     fn do_key(&mut self, args: &Value) {
@@ -275,34 +686,133 @@ impl Editor {
     fn do_open(&mut self, args: &Value) {
         if let Some(path) = args.as_object()
                 .and_then(|v| v.get("filename")).and_then(|v| v.as_string()) {
-            match File::open(&path) {
-                Ok(mut f) => {
-                    let mut s = String::new();
-                    if f.read_to_string(&mut s).is_ok() {
-                        self.text = Rope::from(s);
-                        self.view.reset_breaks();
-                        self.set_cursor(0, true);
-                    }
-                },
-                Err(e) => print_err!("error {}", e)
+            match files::read_stamped(Path::new(path), encoding::load) {
+                Ok(((text, encoding, line_ending), stamp)) => {
+                    self.encoding = encoding;
+                    self.line_ending = line_ending;
+                    self.path = Some(PathBuf::from(path));
+                    self.file_stamp = Some(stamp);
+                    self.reset_contents(text);
+                    self.set_syntax(Syntax::from_path(Path::new(path)));
+                    self.set_cursor(0, true);
+                }
+                Err(e) => alert(&self.view_id, &format!("couldn't open {}: {}", path, e)),
             }
         }
     }
 
-    fn do_save(&mut self, args: &Value) {
-        if let Some(path) = args.as_object()
+    // Color the text as `syntax`, in all the views.
+    fn set_syntax(&mut self, syntax: Option<Syntax>) {
+        self.view.set_syntax(&self.text, syntax);
+        for view in self.other_views.values_mut() {
+            view.set_syntax(&self.text, syntax);
+        }
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.engine.get_head_rev_id() != self.pristine_rev_id ||
+            self.line_ending != self.pristine_line_ending
+    }
+
+    /// Look for changes made to the buffer's file by something else since it
+    /// was opened or saved. If the buffer has no unsaved changes, it's
+    /// reloaded; otherwise the front-end is alerted to the conflict, once for
+    /// each change.
+    pub fn check_file(&mut self) {
+        let changed = match (self.path.as_ref(), self.file_stamp.as_mut()) {
+            // a file that's gone may be in the middle of being replaced
+            (Some(path), Some(stamp)) => stamp.refresh(path).unwrap_or(false),
+            _ => false,
+        };
+        if !changed {
+            return;
+        }
+        if self.is_dirty() {
+            let path = self.path.as_ref().unwrap().display().to_string();
+            alert(&self.view_id,
+                &format!("{} has changed on disk, and the buffer has unsaved changes", path));
+        } else {
+            self.this_edit_type = EditType::Other;
+            self.reload();
+            self.last_edit_type = self.this_edit_type;
+            self.render();
+        }
+    }
+
+    // Replace the text with what's in the file now, as an edit that can be
+    // undone.
+    fn reload(&mut self) {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => return,
+        };
+        match files::read_stamped(&path, encoding::load) {
+            Ok(((text, encoding, line_ending), stamp)) => {
+                self.encoding = encoding;
+                self.line_ending = line_ending;
+                self.file_stamp = Some(stamp);
+                let (iv, new_text) = simple_diff(&self.text, &text);
+                if !iv.is_empty() || new_text.len() != 0 {
+                    let delta = Delta::simple_edit(iv, new_text, self.text.len());
+                    let head_rev_id = self.engine.get_head_rev_id();
+                    let undo_group = self.calculate_undo_group();
+                    self.engine.edit_rev(USER_PRIORITY, undo_group, head_rev_id, delta);
+                    self.commit_delta();
+                }
+                self.pristine_rev_id = self.engine.get_head_rev_id();
+                self.pristine_line_ending = line_ending;
+            }
+            Err(e) => alert(&self.view_id, &format!("couldn't reload {}: {}", path.display(), e)),
+        }
+    }
+
+    // Save in the encoding and line ending the file was loaded with,
+    // responding with whether it worked, and whether it was written in place.
+    fn do_save(&mut self, args: &Value) -> Value {
+        let path = match args.as_object()
                 .and_then(|v| v.get("filename")).and_then(|v| v.as_string()) {
-            match File::create(&path) {
-                Ok(mut f) => {
-                    for chunk in self.text.iter_chunks(0, self.text.len()) {
-                        if let Err(e) = f.write_all(chunk.as_bytes()) {
-                            print_err!("write error {}", e);
-                            break;
-                        }
-                    }
-                },
-                Err(e) => print_err!("create error {}", e)
+            Some(path) => path,
+            None => return ok_response(Err("no filename given".to_string())),
+        };
+        let text = &self.text;
+        let (encoding, line_ending) = (self.encoding, self.line_ending);
+        let result = files::write_atomically(Path::new(path), |f|
+            encoding::write(text, encoding, line_ending, &mut BufWriter::new(f)));
+        match result {
+            Ok((stamp, in_place)) => {
+                self.path = Some(PathBuf::from(path));
+                self.file_stamp = Some(stamp);
+                self.pristine_rev_id = self.engine.get_head_rev_id();
+                self.pristine_line_ending = line_ending;
+                self.set_syntax(Syntax::from_path(Path::new(path)));
+                ObjectBuilder::new()
+                    .insert("ok", true)
+                    .insert("in_place", in_place)
+                    .unwrap()
             }
+            Err(e) => ok_response(Err(format!("couldn't save {}: {}", path, e))),
+        }
+    }
+
+    // Convert the buffer to another line ending, the next time it's saved,
+    // responding with the one it has now. Until then, the buffer counts as
+    // having unsaved changes.
+    fn do_set_line_ending(&mut self, args: &Value) -> Value {
+        let line_ending = match args.as_object()
+                .and_then(|v| v.get("line_ending")).and_then(|v| v.as_string()) {
+            Some(name) => LineEnding::from_name(name)
+                .ok_or_else(|| format!("unknown line ending {:?}", name)),
+            None => Err("no line ending given".to_string()),
+        };
+        match line_ending {
+            Ok(line_ending) => {
+                self.line_ending = line_ending;
+                ObjectBuilder::new()
+                    .insert("ok", true)
+                    .insert("line_ending", line_ending.name())
+                    .unwrap()
+            }
+            Err(msg) => ok_response(Err(msg)),
         }
     }
 
@@ -316,10 +826,35 @@ impl Editor {
 
     fn do_click(&mut self, args: &Value) {
         if let Some(array) = args.as_array() {
-            if let (Some(line), Some(col), Some(flags), Some(_click_count)) =
+            if let (Some(line), Some(col), Some(flags), Some(click_count)) =
                     (array[0].as_u64(), array[1].as_u64(), array[2].as_u64(), array[3].as_u64()) {
-                let offset = self.view.line_col_to_offset(&self.text, line as usize, col as usize);
-                self.set_cursor_or_sel(offset, flags, true);
+                let mut caret = self.view.line_col_to_caret(&self.text, line as usize, col as usize);
+                let mut anchor = caret.end;
+                // A double click selects the word (or run of whitespace or
+                // punctuation) under it.
+                if click_count == 2 {
+                    let segment = segment_around(&self.text, caret.end);
+                    anchor = segment.start;
+                    caret = SelRegion::caret(segment.end);
+                }
+                // The click makes a region from an anchor to the caret, which later
+                // drags replace. Command adds it to the selection, shift extends the
+                // last region.
+                let mut base = Selection::new();
+                if flags & MODIFIER_COMMAND != 0 {
+                    base = self.view.selection.clone();
+                } else if flags & MODIFIER_SHIFT != 0 {
+                    if let Some((last, rest)) = self.view.selection.split_last() {
+                        for region in rest {
+                            base.add_region(*region);
+                        }
+                        anchor = last.start;
+                    }
+                }
+                let mut selection = base.clone();
+                selection.add_region(SelRegion { start: anchor, ..caret });
+                self.set_selection(selection, true);
+                self.view.drag_state = Some((base, anchor));
             }
         }
     }
@@ -328,8 +863,16 @@ impl Editor {
         if let Some(array) = args.as_array() {
             if let (Some(line), Some(col), Some(_flags)) =
                     (array[0].as_u64(), array[1].as_u64(), array[2].as_u64()) {
-                let offset = self.view.line_col_to_offset(&self.text, line as usize, col as usize);
-                self.set_cursor_or_sel(offset, MODIFIER_SHIFT, true);
+                let caret = self.view.line_col_to_caret(&self.text, line as usize, col as usize);
+                let selection = match self.view.drag_state {
+                    Some((ref base, anchor)) => {
+                        let mut selection = base.clone();
+                        selection.add_region(SelRegion { start: anchor, ..caret });
+                        selection
+                    }
+                    None => Selection::new_simple(caret),
+                };
+                self.set_selection(selection, true);
             }
         }
     }
@@ -338,63 +881,152 @@ impl Editor {
         if let Some(dict) = args.as_object() {
             let first_line = dict.get("first_line").unwrap().as_u64().unwrap();
             let last_line = dict.get("last_line").unwrap().as_u64().unwrap();
-            self.view.render_lines(&self.text, first_line as usize, last_line as usize)
+            let mut styles = self.styles.borrow_mut();
+            let lines = self.view.render_lines(&self.text, &mut styles, first_line as usize,
+                last_line as usize);
+            def_styles(&mut styles);
+            lines
         } else {
             Value::Null
         }
     }
 
+    fn do_request_lines(&mut self, args: &Value) {
+        if let Some(dict) = args.as_object() {
+            if let (Some(first_line), Some(last_line)) =
+                    (dict.get("first_line").and_then(Value::as_u64),
+                    dict.get("last_line").and_then(Value::as_u64)) {
+                let mut styles = self.styles.borrow_mut();
+                let update = self.view.render_request(&self.text, &mut styles,
+                    first_line as usize, last_line as usize);
+                def_styles(&mut styles);
+                update_tab(&update, &self.view_id);
+            }
+        }
+    }
+
+    fn do_start_plugin(&mut self, args: &Value) {
+        if let Some(path) = args.as_object()
+                .and_then(|v| v.get("path")).and_then(|v| v.as_string()) {
+            match PluginPeer::start(path, self.buffer_id, self.plugin_tx.clone()) {
+                Ok(mut plugin) => {
+                    plugin.send_rpc("update", &self.plugin_update_params(None));
+                    self.plugins.push(plugin);
+                }
+                Err(e) => print_err!("error {} starting plugin {}", e, path)
+            }
+        }
+    }
+
+    // Apply an edit from a plugin. The delta is relative to the text at revision
+    // `base_rev`; it is merged with whatever edits happened since then.
+    fn plugin_edit(&mut self, args: &Value) {
+        if let Some(dict) = args.as_object() {
+            if let (Some(base_rev), Some(priority), Some(delta)) =
+                    (dict.get("base_rev").and_then(|v| v.as_u64()),
+                     dict.get("priority").and_then(|v| v.as_u64()),
+                     dict.get("delta")) {
+                let (base_rev, priority) = (base_rev as usize, priority as usize);
+                if priority < MIN_PLUGIN_PRIORITY {
+                    print_err!("plugin edit priority {} is below {}", priority, MIN_PLUGIN_PRIORITY);
+                    return;
+                }
+                // Revisions can disappear in garbage collection, or when the
+                // contents are replaced, in which case the plugin will get
+                // another chance when it receives the next update.
+                let base_len = match self.engine.get_rev_len(base_rev) {
+                    Some(base_len) => base_len,
+                    None => {
+                        print_err!("plugin edit against unknown revision {}", base_rev);
+                        return;
+                    }
+                };
+                let delta = match Delta::from_json(delta, base_len) {
+                    Ok(delta) => delta,
+                    Err(e) => {
+                        print_err!("invalid plugin delta for revision {}: {:?}", base_rev, e);
+                        return;
+                    }
+                };
+                let undo_group = self.calculate_undo_group();
+                self.engine.edit_rev(priority, undo_group, base_rev, delta);
+            } else {
+                print_err!("malformed plugin edit {:?}", args);
+            }
+        }
+    }
+
+    pub fn do_plugin_rpc(&mut self, method: &str, params: &Value) {
+        self.this_edit_type = EditType::Other;
+        match method {
+            "edit" => self.plugin_edit(params),
+            _ => print_err!("unknown plugin method {}", method)
+        }
+        self.commit_delta();
+        self.last_edit_type = self.this_edit_type;
+        self.render();
+    }
+
     fn debug_rewrap(&mut self) {
         self.view.rewrap(&self.text, 72);
-        self.dirty = true;
+        self.view.dirty = true;
     }
 
     fn debug_test_fg_spans(&mut self) {
         print_err!("setting fg spans");
-        self.view.set_test_fg_spans();
-        self.dirty = true;
+        self.view.set_test_fg_spans(&self.text);
+        self.view.dirty = true;
     }
 
-    fn do_cut(&mut self) -> Value {
-        let min = self.view.sel_min();
-        if min != self.view.sel_max() {
-            let del_interval = Interval::new_closed_open(min, self.view.sel_max());
-            self.add_delta(del_interval, Rope::from(""), min);
-            let val = self.text.slice_to_string(min, self.view.sel_max());
-            Value::String(val)
+    // The text of the selected regions, joined by newlines, unless all are carets.
+    fn selected_text(&self) -> Option<String> {
+        let pieces: Vec<String> = self.view.selection.iter()
+            .filter(|region| !region.is_caret())
+            .map(|region| self.text.slice_to_string(region.min(), region.max()))
+            .collect();
+        if pieces.is_empty() {
+            None
         } else {
-            Value::Null
+            Some(pieces.join("\n"))
+        }
+    }
+
+    fn do_cut(&mut self) -> Value {
+        match self.selected_text() {
+            Some(val) => {
+                let edits = self.view.selection.iter().map(|region|
+                    (Interval::new_closed_open(region.min(), region.max()), Rope::from(""))
+                ).collect();
+                self.add_edits(edits);
+                Value::String(val)
+            }
+            None => Value::Null
         }
     }
 
     fn do_copy(&mut self) -> Value {
-        if self.view.sel_start != self.view.sel_end {
-            let val = self.text.slice_to_string(self.view.sel_min(), self.view.sel_max());
-            Value::String(val)
-        } else {
-            Value::Null
+        match self.selected_text() {
+            Some(val) => Value::String(val),
+            None => Value::Null
         }
     }
 
     fn delete_to_end_of_paragraph(&mut self, kill_ring: &Mutex<Rope>) {
-        let current = self.view.sel_max();
-        let offset = self.cursor_end_offset();
-        let mut val = String::from("");
-
-        if current != offset {
-            val = self.text.slice_to_string(current, offset);
-            let del_interval = Interval::new_closed_open(current, offset);
-            self.add_delta(del_interval, Rope::from(""), current);
-        } else {
-            if let Some(grapheme_offset) = self.text.next_grapheme_offset(self.view.sel_end) {
-                val = self.text.slice_to_string(current, grapheme_offset);
-                let del_interval = Interval::new_closed_open(current, grapheme_offset);
-                self.add_delta(del_interval, Rope::from(""), current)
+        let mut edits = Vec::new();
+        let mut killed = Vec::new();
+        for region in self.view.selection.iter() {
+            let current = region.max();
+            let mut offset = cursor_end_offset(&self.text, current);
+            if current == offset {
+                offset = self.text.next_grapheme_offset(current).unwrap_or(current);
             }
+            killed.push(self.text.slice_to_string(current, offset));
+            edits.push((Interval::new_closed_open(current, offset), Rope::from("")));
         }
+        self.add_edits(edits);
 
         let mut kill_ring = kill_ring.lock().unwrap();
-        *kill_ring = Rope::from(val);
+        *kill_ring = Rope::from(killed.join("\n"));
     }
 
     fn yank(&mut self, kill_ring: &Mutex<Rope>) {
@@ -402,12 +1034,20 @@ impl Editor {
         self.insert(&*String::from(data.clone()));
     }
 
-    pub fn do_rpc(&mut self, method: &str, params: &Value, kill_ring: &Mutex<Rope>) -> Option<Value> {
+    /// Handle a request made from the tab `tabname`, which must show one of
+    /// the views of this buffer.
+    pub fn do_rpc(&mut self, tabname: &str, method: &str, params: &Value,
+            kill_ring: &Mutex<Rope>) -> Option<Value> {
+        self.set_view(tabname);
+        self.this_edit_type = EditType::Other;
         let result = match method {
             "render_lines" => Some(self.do_render_lines(params)),
+            "request_lines" => async(self.do_request_lines(params)),
             "key" => async(self.do_key(params)),
             "insert" => async(self.do_insert(params)),
             "delete_backward" => async(self.delete_backward()),
+            "delete_word_backward" => async(self.delete_word_backward()),
+            "delete_word_forward" => async(self.delete_word_forward()),
             "delete_to_end_of_paragraph" => async(self.delete_to_end_of_paragraph(kill_ring)),
             "insert_newline" => async(self.insert_newline()),
             "move_up" => async(self.move_up(0)),
@@ -420,6 +1060,12 @@ impl Editor {
             "move_right" |
             "move_forward" => async(self.move_right(0)),
             "move_right_and_modify_selection" => async(self.move_right(MODIFIER_SHIFT)),
+            "move_word_left" |
+            "move_word_backward" => async(self.move_word_left(0)),
+            "move_word_left_and_modify_selection" => async(self.move_word_left(MODIFIER_SHIFT)),
+            "move_word_right" |
+            "move_word_forward" => async(self.move_word_right(0)),
+            "move_word_right_and_modify_selection" => async(self.move_word_right(MODIFIER_SHIFT)),
             "move_to_beginning_of_paragraph" => async(self.cursor_start()),
             "move_to_end_of_paragraph" => async(self.cursor_end()),
             "scroll_page_up" |
@@ -429,11 +1075,24 @@ impl Editor {
             "page_down" => async(self.scroll_page_down(0)),
             "page_down_and_modify_selection" => async(self.scroll_page_down(MODIFIER_SHIFT)),
             "open" => async(self.do_open(params)),
-            "save" => async(self.do_save(params)),
+            "save" => Some(self.do_save(params)),
+            "reload" => async(self.reload()),
+            "set_line_ending" => Some(self.do_set_line_ending(params)),
             "scroll" => async(self.do_scroll(params)),
             "yank" => async(self.yank(kill_ring)),
+            "undo" => async(self.undo()),
+            "redo" => async(self.redo()),
             "click" => async(self.do_click(params)),
             "drag" => async(self.do_drag(params)),
+            "add_selection_above" => async(self.add_selection_above()),
+            "add_selection_below" => async(self.add_selection_below()),
+            "select_next_occurrence" => async(self.select_next_occurrence()),
+            "find" => async(self.do_find(params)),
+            "find_next" => async(self.find_next(true)),
+            "find_previous" => async(self.find_next(false)),
+            "replace" => async(self.do_replace(params)),
+            "replace_all" => async(self.do_replace_all(params)),
+            "start_plugin" => async(self.do_start_plugin(params)),
             "cut" => Some(self.do_cut()),
             "copy" => Some(self.do_copy()),
             "debug_rewrap" => async(self.debug_rewrap()),
@@ -442,12 +1101,873 @@ impl Editor {
         };
         // TODO: could defer this until input quiesces - will this help?
         self.commit_delta();
+        self.last_edit_type = self.this_edit_type;
         self.render();
         result
     }
 }
 
+// The end of the paragraph containing `offset`, before its newline.
+fn cursor_end_offset(text: &Rope, offset: usize) -> usize {
+    let mut cursor = Cursor::new(text, offset);
+    match cursor.next::<LinesMetric>() {
+        None => offset,
+        Some(next) => {
+            if cursor.is_boundary::<LinesMetric>() {
+                text.prev_grapheme_offset(next).unwrap_or(next)
+            } else {
+                next
+            }
+        }
+    }
+}
+
+// The start of the first occurrence of `needle` within `start..end` of `text`
+// that `accept` takes. The text is searched a chunk at a time, keeping enough
+// of each chunk to find the occurrences that run on into the next.
+fn find_in_rope<F>(text: &Rope, needle: &str, start: usize, end: usize, mut accept: F)
+        -> Option<usize> where F: FnMut(usize) -> bool {
+    if needle.is_empty() {
+        return None;
+    }
+    let mut buf = String::new();
+    let mut buf_start = start;
+    let mut unseen = start;  // occurrences starting before this were looked at
+    for chunk in text.iter_chunks(start, end) {
+        buf.push_str(chunk);
+        for (ix, _) in buf.match_indices(needle) {
+            if buf_start + ix >= unseen && accept(buf_start + ix) {
+                return Some(buf_start + ix);
+            }
+        }
+        unseen = buf_start + (buf.len() + 1).saturating_sub(needle.len());
+        let mut keep = unseen - buf_start;
+        while !buf.is_char_boundary(keep) {
+            keep -= 1;
+        }
+        buf.drain(..keep);
+        buf_start += keep;
+    }
+    None
+}
+
+// The smallest single edit that turns `old` into `new`, leaving out what
+// they have in common at the start and the end.
+fn simple_diff(old: &Rope, new: &Rope) -> (Interval, Rope) {
+    let is_continuation = |b: u8| b & 0xC0 == 0x80;
+    let mut prefix = old.iter_chunks(0, old.len()).flat_map(str::bytes)
+        .zip(new.iter_chunks(0, new.len()).flat_map(str::bytes))
+        .take_while(|&(a, b)| a == b)
+        .count();
+    while prefix < old.len() && is_continuation(old.byte_at(prefix)) {
+        prefix -= 1;
+    }
+    let old_chunks: Vec<&str> = old.iter_chunks(0, old.len()).collect();
+    let new_chunks: Vec<&str> = new.iter_chunks(0, new.len()).collect();
+    let mut suffix = old_chunks.iter().rev().flat_map(|chunk| chunk.bytes().rev())
+        .zip(new_chunks.iter().rev().flat_map(|chunk| chunk.bytes().rev()))
+        .take(min(old.len(), new.len()) - prefix)
+        .take_while(|&(a, b)| a == b)
+        .count();
+    while suffix > 0 && is_continuation(old.byte_at(old.len() - suffix)) {
+        suffix -= 1;
+    }
+    (Interval::new_closed_open(prefix, old.len() - suffix),
+        new.subseq(Interval::new_closed_open(prefix, new.len() - suffix)))
+}
+
 // wrapper so async methods don't have to return None themselves
 fn async(_: ()) -> Option<Value> {
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use serde_json;
+    use serde_json::Value;
+    use serde_json::builder::ObjectBuilder;
+
+    use xi_rope::rope::Rope;
+    use xi_rope::delta::Delta;
+    use xi_rope::interval::Interval;
+    use selection::{Selection, SelRegion};
+    use super::{find_in_rope, simple_diff, Editor, EditType, MODIFIER_SHIFT};
+    use columns::ColumnUnit;
+    use highlight::Syntax;
+    use styles::StyleMap;
+    use theme;
+    use ::MainMsg;
+
+    fn test_editor(text: &str) -> Editor {
+        let (tx, _rx) = mpsc::channel();
+        let mut editor = Editor::new(0, "0", tx, Rc::new(RefCell::new(StyleMap::new())));
+        editor.reset_contents(Rope::from(text));
+        editor
+    }
+
+    // Render an update for the current view, as `render` would send it.
+    fn render(editor: &mut Editor) -> Value {
+        editor.view.render(&editor.text, &mut editor.styles.borrow_mut())
+    }
+
+    fn render_request(editor: &mut Editor, first: usize, last: usize) -> Value {
+        editor.view.render_request(&editor.text, &mut editor.styles.borrow_mut(), first, last)
+    }
+
+    fn render_lines(editor: &Editor, first: usize, last: usize) -> Value {
+        editor.view.render_lines(&editor.text, &mut editor.styles.borrow_mut(), first, last)
+    }
+
+    // The `def_style` params of the styles given ids since the last call.
+    fn new_styles(editor: &Editor) -> Vec<String> {
+        editor.styles.borrow_mut().take_definitions().into_iter()
+            .map(|def| serde_json::to_string(&def.unwrap()).unwrap())
+            .collect()
+    }
+
+    // A toy plugin: given the params of an `update` carrying the whole text,
+    // produce the params of an `edit` that uppercases the first occurrence of `word`.
+    fn uppercase_plugin(update: &Value, word: &str) -> Option<Value> {
+        let update = update.as_object().unwrap();
+        let rev = update.get("rev").unwrap().as_u64().unwrap();
+        let text = update.get("text").unwrap().as_string().unwrap();
+        text.find(word).map(|start| {
+            let delta = Delta::simple_edit(Interval::new_closed_open(start, start + word.len()),
+                Rope::from(word.to_uppercase()), text.len());
+            ObjectBuilder::new()
+                .insert("base_rev", rev)
+                .insert("priority", 1)
+                .insert("delta", delta)
+                .unwrap()
+        })
+    }
+
+    fn type_chars(editor: &mut Editor, s: &str) {
+        editor.this_edit_type = EditType::Other;
+        editor.insert(s);
+        editor.commit_delta();
+        editor.last_edit_type = editor.this_edit_type;
+    }
+
+    fn plugin_edit(editor: &mut Editor, edit: &Value) {
+        editor.this_edit_type = EditType::Other;
+        editor.plugin_edit(edit);
+        editor.commit_delta();
+        editor.last_edit_type = editor.this_edit_type;
+    }
+
+    fn text(editor: &Editor) -> String {
+        String::from(editor.text.clone())
+    }
+
+    // The (start, end) pairs of the regions of the selection.
+    fn selection(editor: &Editor) -> Vec<(usize, usize)> {
+        editor.view.selection.iter().map(|region| (region.start, region.end)).collect()
+    }
+
+    fn set_carets(editor: &mut Editor, offsets: &[usize]) {
+        let mut selection = Selection::new();
+        for &offset in offsets {
+            selection.add_region(SelRegion::caret(offset));
+        }
+        editor.set_selection(selection, true);
+    }
+
+    // The (op, n) pairs of the ops in an update.
+    fn update_ops(update: &Value) -> Vec<(String, u64)> {
+        update.as_object().unwrap().get("ops").unwrap().as_array().unwrap().iter().map(|op| {
+            let op = op.as_object().unwrap();
+            (op.get("op").unwrap().as_string().unwrap().to_string(),
+                op.get("n").unwrap().as_u64().unwrap())
+        }).collect()
+    }
+
+    #[test]
+    fn incremental_update() {
+        let mut editor = test_editor("one\ntwo\nthree\n");
+        let update = render(&mut editor);
+        assert_eq!(vec![("ins".to_string(), 4)], update_ops(&update));
+        editor.set_cursor(7, true);
+        type_chars(&mut editor, "!");
+        // the old and new cursor lines are sent, the rest is copied
+        let update = render(&mut editor);
+        assert_eq!(vec![("ins".to_string(), 2), ("skip".to_string(), 2), ("copy".to_string(), 2)],
+            update_ops(&update));
+        type_chars(&mut editor, "\n");
+        let update = render(&mut editor);
+        assert_eq!(vec![("copy".to_string(), 1), ("ins".to_string(), 2), ("skip".to_string(), 1),
+            ("copy".to_string(), 2)], update_ops(&update));
+    }
+
+    #[test]
+    fn request_lines() {
+        let mut editor = test_editor("one\ntwo\nthree\nfour\n");
+        render(&mut editor);
+        // lines the front-end asks for are sent even if it should have them
+        let update = render_request(&mut editor, 1, 3);
+        assert_eq!(vec![("copy".to_string(), 1), ("ins".to_string(), 2), ("skip".to_string(), 2),
+            ("copy".to_string(), 2)], update_ops(&update));
+        // requests past the end are clamped
+        let update = render_request(&mut editor, 4, 10);
+        assert_eq!(vec![("copy".to_string(), 4), ("ins".to_string(), 1)], update_ops(&update));
+    }
+
+    #[test]
+    fn multiple_views() {
+        let mut editor = test_editor("one\ntwo\n");
+        editor.add_view("1");
+        editor.set_view("1");
+        editor.set_cursor(5, true);
+        render(&mut editor);
+        editor.set_view("0");
+        type_chars(&mut editor, "zero\n");
+        assert_eq!(vec![(5, 5)], selection(&editor));
+        // the other view's selection and line cache follow the edit
+        editor.set_view("1");
+        assert_eq!("1", editor.view_id);
+        assert_eq!(vec![(10, 10)], selection(&editor));
+        assert!(editor.view.dirty);
+        let update = render(&mut editor);
+        assert_eq!(vec![("ins".to_string(), 3), ("skip".to_string(), 2), ("copy".to_string(), 1)],
+            update_ops(&update));
+        editor.remove_view("1");
+        assert_eq!("0", editor.view_id);
+        assert!(editor.other_views.is_empty());
+    }
+
+    #[test]
+    fn multiple_carets() {
+        let mut editor = test_editor("one\ntwo\nthree");
+        set_carets(&mut editor, &[3, 7, 13]);
+        type_chars(&mut editor, "!");
+        assert_eq!("one!\ntwo!\nthree!", text(&editor));
+        assert_eq!(vec![(4, 4), (9, 9), (16, 16)], selection(&editor));
+        for _ in 0..2 {
+            editor.delete_backward();
+            editor.commit_delta();
+        }
+        assert_eq!("on\ntw\nthre", text(&editor));
+        editor.move_left(2);
+        assert_eq!(vec![(2, 1), (5, 4), (10, 9)], selection(&editor));
+        editor.move_right(0);
+        assert_eq!(vec![(2, 2), (5, 5), (10, 10)], selection(&editor));
+        // carets that collide are merged
+        editor.cursor_start();
+        editor.move_up(0);
+        assert_eq!(vec![(0, 0), (3, 3)], selection(&editor));
+    }
+
+    #[test]
+    fn add_selection_below() {
+        let mut editor = test_editor("abc\nde\nfghi\n");
+        editor.set_cursor(2, true);
+        editor.add_selection_below();
+        // the column is remembered through the short line
+        editor.add_selection_below();
+        assert_eq!(vec![(2, 2), (6, 6), (9, 9)], selection(&editor));
+        editor.add_selection_below();
+        editor.add_selection_below();
+        assert_eq!(vec![(2, 2), (6, 6), (9, 9), (12, 12)], selection(&editor));
+        editor.add_selection_above();
+        assert_eq!(vec![(2, 2), (6, 6), (9, 9), (12, 12)], selection(&editor));
+    }
+
+    #[test]
+    fn select_next_occurrence() {
+        let mut editor = test_editor("foo bar foo_bar foo");
+        editor.set_cursor(1, true);
+        editor.select_next_occurrence();
+        assert_eq!(vec![(0, 3)], selection(&editor));
+        editor.select_next_occurrence();
+        editor.select_next_occurrence();
+        assert_eq!(vec![(0, 3), (8, 11), (16, 19)], selection(&editor));
+        // all occurrences are selected, so nothing more is added
+        editor.select_next_occurrence();
+        assert_eq!(3, selection(&editor).len());
+        type_chars(&mut editor, "x");
+        assert_eq!("x bar x_bar x", text(&editor));
+        // the search wraps around to the start
+        let mut editor = test_editor("foo bar foo");
+        editor.set_selection(Selection::new_simple(SelRegion::new(8, 11)), true);
+        editor.select_next_occurrence();
+        assert_eq!(vec![(0, 3), (8, 11)], selection(&editor));
+    }
+
+    #[test]
+    fn render_multiple_carets() {
+        let mut editor = test_editor("one two\n");
+        let mut selection = Selection::new();
+        selection.add_region(SelRegion::caret(1));
+        selection.add_region(SelRegion::new(4, 6));
+        editor.set_selection(selection, true);
+        let lines = render_lines(&editor, 0, 1);
+        let line = &lines.as_array().unwrap()[0];
+        assert_eq!("[\"one two\\n\",[\"style\",4,6,0],[\"cursor\",1],[\"cursor\",6]]",
+            serde_json::to_string(line).unwrap());
+        assert_eq!(vec![r#"{"bg":4289976828,"id":0}"#], new_styles(&editor));
+    }
+
+    #[test]
+    fn grapheme_clusters() {
+        // an accented letter, a flag and a ZWJ emoji sequence
+        let mut editor = test_editor("e\u{0301}\u{1F1FA}\u{1F1F8}\u{1F468}\u{200D}\u{1F469}");
+        editor.set_cursor(0, true);
+        editor.move_right(0);
+        assert_eq!(vec![(3, 3)], selection(&editor));
+        editor.move_right(0);
+        assert_eq!(vec![(11, 11)], selection(&editor));
+        // clicking inside a cluster snaps to its start
+        let caret = editor.view.line_col_to_caret(&editor.text, 0, 15);
+        assert_eq!(11, caret.end);
+        editor.set_cursor(22, true);
+        editor.delete_backward();
+        editor.commit_delta();
+        assert_eq!("e\u{0301}\u{1F1FA}\u{1F1F8}", text(&editor));
+        editor.delete_backward();
+        editor.commit_delta();
+        assert_eq!("e\u{0301}", text(&editor));
+    }
+
+    #[test]
+    fn word_motion() {
+        let mut editor = test_editor("one, two\n  three");
+        editor.set_cursor(0, true);
+        editor.move_word_right(0);
+        assert_eq!(vec![(3, 3)], selection(&editor));
+        // punctuation and whitespace are skipped
+        editor.move_word_right(0);
+        assert_eq!(vec![(8, 8)], selection(&editor));
+        // and so are newlines
+        editor.move_word_right(0);
+        assert_eq!(vec![(16, 16)], selection(&editor));
+        editor.move_word_right(0);
+        assert_eq!(vec![(16, 16)], selection(&editor));
+        editor.move_word_left(MODIFIER_SHIFT);
+        assert_eq!(vec![(16, 11)], selection(&editor));
+        editor.move_word_left(MODIFIER_SHIFT);
+        assert_eq!(vec![(16, 5)], selection(&editor));
+        // without shift, a selection moves from its edge
+        editor.move_word_left(0);
+        assert_eq!(vec![(0, 0)], selection(&editor));
+        // apostrophes and decimal points don't split words
+        let mut editor = test_editor("can't 3.14");
+        editor.set_cursor(0, true);
+        editor.move_word_right(0);
+        assert_eq!(vec![(5, 5)], selection(&editor));
+        editor.move_word_right(0);
+        assert_eq!(vec![(10, 10)], selection(&editor));
+    }
+
+    #[test]
+    fn delete_word() {
+        let mut editor = test_editor("one two three");
+        set_carets(&mut editor, &[5, 13]);
+        editor.delete_word_backward();
+        editor.commit_delta();
+        assert_eq!("one wo ", text(&editor));
+        assert_eq!(vec![(4, 4), (7, 7)], selection(&editor));
+        editor.set_cursor(0, true);
+        editor.delete_word_forward();
+        editor.commit_delta();
+        assert_eq!(" wo ", text(&editor));
+        // overlapping deletions are merged
+        let mut editor = test_editor("one two");
+        set_carets(&mut editor, &[1, 2]);
+        editor.delete_word_forward();
+        editor.commit_delta();
+        assert_eq!("o two", text(&editor));
+        assert_eq!(vec![(1, 1)], selection(&editor));
+    }
+
+    #[test]
+    fn double_click() {
+        let mut editor = test_editor("foo bar_baz, qux\n");
+        let click = |line: usize, col: usize, count: usize|
+            serde_json::from_str::<Value>(&format!("[{}, {}, 0, {}]", line, col, count)).unwrap();
+        editor.do_click(&click(0, 6, 2));
+        assert_eq!(vec![(4, 11)], selection(&editor));
+        // just past the end of a word selects the word
+        editor.do_click(&click(0, 11, 2));
+        assert_eq!(vec![(4, 11)], selection(&editor));
+        editor.do_click(&click(0, 12, 2));
+        assert_eq!(vec![(12, 13)], selection(&editor));
+        // a drag extends from the start of the word
+        editor.do_drag(&serde_json::from_str::<Value>("[0, 1, 0]").unwrap());
+        assert_eq!(vec![(12, 1)], selection(&editor));
+        editor.do_click(&click(0, 6, 1));
+        assert_eq!(vec![(6, 6)], selection(&editor));
+    }
+
+    #[test]
+    fn find() {
+        let mut editor = test_editor("Foo bar\nfoo baz foo\n");
+        editor.set_cursor(2, true);
+        let find = |params: &str| serde_json::from_str::<Value>(params).unwrap();
+        editor.do_find(&find(r#"{"chars": "foo"}"#));
+        assert_eq!(vec![(8, 11)], selection(&editor));
+        // all the matches on a line are highlighted, with the selection over them
+        let lines = render_lines(&editor, 1, 2);
+        assert_eq!("[\"foo baz foo\\n\",[\"style\",0,3,0],[\"style\",8,11,1],[\"cursor\",3]]",
+            serde_json::to_string(&lines.as_array().unwrap()[0]).unwrap());
+        assert_eq!(vec![r#"{"bg":4289976828,"id":0}"#, r#"{"bg":4294963353,"id":1}"#],
+            new_styles(&editor));
+        editor.find_next(true);
+        assert_eq!(vec![(16, 19)], selection(&editor));
+        editor.find_next(true);
+        assert_eq!(vec![(8, 11)], selection(&editor));
+        editor.find_next(false);
+        assert_eq!(vec![(16, 19)], selection(&editor));
+        // a new search starts from the selection, wrapping around
+        editor.do_find(&find(r#"{"chars": "foo", "ignore_case": true}"#));
+        assert_eq!(vec![(16, 19)], selection(&editor));
+        editor.find_next(true);
+        assert_eq!(vec![(0, 3)], selection(&editor));
+        editor.do_find(&find(r#"{"chars": "ba[rz]", "regex": true}"#));
+        assert_eq!(vec![(4, 7)], selection(&editor));
+        // updates carry the number of matches and the index of the selected one,
+        // when they change
+        let find_status = |update: &Value| update.as_object().unwrap().get("find")
+            .map(|status| serde_json::to_string(status).unwrap());
+        let update = render(&mut editor);
+        assert_eq!(Some("{\"count\":2,\"current\":0}".to_string()), find_status(&update));
+        editor.set_cursor(19, true);
+        type_chars(&mut editor, " bar");
+        let update = render(&mut editor);
+        assert_eq!(Some("{\"count\":3,\"current\":null}".to_string()), find_status(&update));
+        editor.find_next(false);
+        render(&mut editor);
+        editor.set_cursor(0, true);
+        editor.find_next(false);
+        let update = render(&mut editor);
+        assert_eq!(None, find_status(&update));
+        assert_eq!(vec![(20, 23)], selection(&editor));
+        // an invalid pattern clears the search
+        editor.do_find(&find(r#"{"chars": "ba[", "regex": true}"#));
+        assert!(editor.view.search().is_none());
+        editor.find_next(true);
+        assert_eq!(vec![(20, 23)], selection(&editor));
+        let update = render(&mut editor);
+        assert_eq!(Some("null".to_string()), find_status(&update));
+    }
+
+    #[test]
+    fn replace() {
+        let mut editor = test_editor("f(a) f(b) g(c) f(d)");
+        let params = |params: &str| serde_json::from_str::<Value>(params).unwrap();
+        editor.do_find(&params(r#"{"chars": "f\\((\\w)\\)", "regex": true}"#));
+        assert_eq!(vec![(0, 4)], selection(&editor));
+        editor.do_replace(&params(r#"{"chars": "h($1)"}"#));
+        assert_eq!("h(a) f(b) g(c) f(d)", text(&editor));
+        assert_eq!(vec![(5, 9)], selection(&editor));
+        // a region that isn't a match is left alone
+        editor.set_selection(Selection::new_simple(SelRegion::new(10, 14)), true);
+        editor.do_replace(&params(r#"{"chars": "h($1)"}"#));
+        assert_eq!("h(a) f(b) g(c) f(d)", text(&editor));
+        assert_eq!(vec![(15, 19)], selection(&editor));
+        editor.this_edit_type = EditType::Other;
+        editor.do_replace_all(&params(r#"{"chars": "$1"}"#));
+        editor.commit_delta();
+        assert_eq!("h(a) b g(c) d", text(&editor));
+        // replacing all is undone in one step
+        editor.undo();
+        editor.commit_delta();
+        assert_eq!("h(a) f(b) g(c) f(d)", text(&editor));
+    }
+
+    #[test]
+    fn line_endings() {
+        let path = env::temp_dir().join(format!("xi-test-line-endings-{}.txt", process::id()));
+        fs::write(&path, "one\r\ntwo\r\n").unwrap();
+        let params = |params: String| serde_json::from_str::<Value>(&params).unwrap();
+        let filename = params(format!("{{\"filename\": {:?}}}", path.to_str().unwrap()));
+        let mut editor = test_editor("");
+        editor.do_open(&filename);
+        assert_eq!("one\ntwo\n", text(&editor));
+        // pasted text is converted too, and the file keeps its line ending
+        editor.set_cursor(8, true);
+        type_chars(&mut editor, "three\r\nfour\n");
+        assert_eq!("one\ntwo\nthree\nfour\n", text(&editor));
+        editor.do_save(&filename);
+        assert_eq!("one\r\ntwo\r\nthree\r\nfour\r\n", fs::read_to_string(&path).unwrap());
+        assert!(!editor.is_dirty());
+        let response = editor.do_set_line_ending(&params(r#"{"line_ending": "lf"}"#.to_string()));
+        assert_eq!(r#"{"line_ending":"lf","ok":true}"#, serde_json::to_string(&response).unwrap());
+        assert!(editor.is_dirty());
+        let response = editor.do_set_line_ending(&params(r#"{"line_ending": "lfcr"}"#.to_string()));
+        assert_eq!(r#"{"error":"unknown line ending \"lfcr\"","ok":false}"#,
+            serde_json::to_string(&response).unwrap());
+        let response = editor.do_save(&filename);
+        assert_eq!(r#"{"in_place":false,"ok":true}"#, serde_json::to_string(&response).unwrap());
+        assert!(!editor.is_dirty());
+        assert_eq!("one\ntwo\nthree\nfour\n", fs::read_to_string(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fg_spans_follow_edits() {
+        let mut editor = test_editor("0123456789abcdef");
+        editor.view.set_test_fg_spans(&editor.text);
+        let first_line = |editor: &Editor| serde_json::to_string(
+            &render_lines(editor, 0, 1).as_array().unwrap()[0]).unwrap();
+        editor.set_cursor(0, true);
+        type_chars(&mut editor, "xy");
+        assert_eq!("[\"xy0123456789abcdef\",[\"style\",7,12,0],[\"cursor\",2]]",
+            first_line(&editor));
+        assert_eq!(vec![r#"{"fg":4290772992,"id":0}"#], new_styles(&editor));
+        // typing inside a span breaks it
+        editor.set_cursor(10, true);
+        type_chars(&mut editor, "z");
+        assert_eq!("[\"xy01234567z89abcdef\",[\"style\",7,10,0],[\"style\",11,13,0],\
+            [\"cursor\",11]]", first_line(&editor));
+        // typing at carets on either side of a span leaves it whole
+        let mut selection = Selection::new();
+        selection.add_region(SelRegion::caret(1));
+        selection.add_region(SelRegion::caret(16));
+        editor.set_selection(selection, true);
+        type_chars(&mut editor, "w");
+        assert_eq!("[\"xwy01234567z89abcwdef\",[\"style\",8,11,0],[\"style\",12,14,0],\
+            [\"cursor\",2],[\"cursor\",18]]", first_line(&editor));
+    }
+
+    #[test]
+    fn syntax_highlighting() {
+        let mut editor = test_editor("fn f() {}\nx\ny\n");
+        editor.set_syntax(Some(Syntax::Rust));
+        let line = |editor: &Editor, n: usize| serde_json::to_string(
+            &render_lines(editor, n, n + 1).as_array().unwrap()[0]).unwrap();
+        assert_eq!("[\"fn f() {}\\n\",[\"style\",0,2,0],[\"style\",3,4,1],[\"cursor\",0]]",
+            line(&editor, 0));
+        assert_eq!(vec![r#"{"fg":4287191464,"id":0}"#, r#"{"fg":4282544558,"id":1}"#],
+            new_styles(&editor));
+        // selected text keeps its color, under the selection's background
+        editor.set_selection(Selection::new_simple(SelRegion::new(1, 4)), true);
+        assert_eq!("[\"fn f() {}\\n\",[\"style\",0,1,0],[\"style\",1,2,2],[\"style\",2,3,3],\
+            [\"style\",3,4,4],[\"cursor\",4]]", line(&editor, 0));
+        assert_eq!(vec![r#"{"bg":4289976828,"fg":4287191464,"id":2}"#,
+            r#"{"bg":4289976828,"id":3}"#, r#"{"bg":4289976828,"fg":4282544558,"id":4}"#],
+            new_styles(&editor));
+        editor.set_cursor(10, true);
+        render(&mut editor);
+        // opening a comment recolors, and resends, the lines after it
+        type_chars(&mut editor, "/*");
+        assert_eq!("[\"y\\n\",[\"style\",0,1,5]]", line(&editor, 2));
+        assert_eq!(vec![r#"{"fg":4287533196,"id":5}"#], new_styles(&editor));
+        let update = render(&mut editor);
+        assert_eq!(vec![("copy".to_string(), 1), ("ins".to_string(), 3)], update_ops(&update));
+        // and a new view of the buffer is colored the same way
+        editor.add_view("1");
+        editor.set_view("1");
+        assert_eq!("[\"y\\n\",[\"style\",0,1,5]]", line(&editor, 2));
+        assert!(new_styles(&editor).is_empty());
+    }
+
+    #[test]
+    fn themes() {
+        let mut editor = test_editor("x // hi\n");
+        editor.set_syntax(Some(Syntax::Rust));
+        editor.set_selection(Selection::new_simple(SelRegion::new(0, 1)), true);
+        render(&mut editor);
+        new_styles(&editor);
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/themes");
+        editor.styles.borrow_mut().set_theme(theme::load_theme(&dir, "Test").unwrap());
+        editor.view.restyle();
+        // the lines are resent, in the theme's styles
+        let update = render(&mut editor);
+        assert_eq!(vec![("ins".to_string(), 2)], update_ops(&update));
+        assert_eq!(vec![r#"{"bg":4292269782,"id":2}"#,
+            r#"{"fg":4287533196,"id":3,"italic":true,"underline":false,"weight":400}"#],
+            new_styles(&editor));
+        assert_eq!("[\"x // hi\\n\",[\"style\",0,1,2],[\"style\",2,7,3],[\"cursor\",1]]",
+            serde_json::to_string(&render_lines(&editor, 0, 1).as_array().unwrap()[0]).unwrap());
+    }
+
+    #[test]
+    fn diff() {
+        let diff = |old: &str, new: &str| {
+            let (iv, text) = simple_diff(&Rope::from(old), &Rope::from(new));
+            (iv.start(), iv.end(), String::from(text))
+        };
+        assert_eq!((6, 7, "zoo".to_string()), diff("foo bar quux", "foo bazoo quux"));
+        assert_eq!((3, 3, "".to_string()), diff("abc", "abc"));
+        assert_eq!((2, 2, "bb".to_string()), diff("ab", "abbb"));
+        assert_eq!((0, 3, "".to_string()), diff("abc", ""));
+        // the edit doesn't split a character
+        assert_eq!((1, 3, "\u{E8}".to_string()), diff("a\u{E9}", "a\u{E8}"));
+        assert_eq!((0, 2, "\u{1E9}".to_string()), diff("\u{E9}b", "\u{1E9}b"));
+    }
+
+    #[test]
+    fn find_across_chunks() {
+        let mut s = "\u{E9}".repeat(1000);
+        s.push_str("needle");
+        s.push_str(&"\u{E9}needle".repeat(1000));
+        let text = Rope::from(&s[..]);
+        assert!(text.iter_chunks(0, text.len()).count() > 2);
+        let all = |start: usize, end: usize| {
+            let mut found = Vec::new();
+            find_in_rope(&text, "\u{E9}needle", start, end, |offset| {
+                found.push(offset);
+                false
+            });
+            found
+        };
+        let expected = s.match_indices("\u{E9}needle").map(|(ix, _)| ix).collect::<Vec<_>>();
+        assert_eq!(expected, all(0, text.len()));
+        // only occurrences wholly inside the range count
+        assert_eq!(&expected[1..], &all(expected[0] + 2, text.len())[..]);
+        assert_eq!(&expected[..2], &all(0, expected[2] + 7)[..]);
+        assert_eq!(Some(expected[500]), find_in_rope(&text, "\u{E9}needle", 0, text.len(),
+            |offset| offset >= expected[500]));
+    }
+
+    #[test]
+    fn reload_changed_file() {
+        let path = env::temp_dir().join(format!("xi-test-reload-{}.txt", process::id()));
+        fs::write(&path, "one\ntwo\n").unwrap();
+        let filename = serde_json::from_str::<Value>(
+            &format!("{{\"filename\": {:?}}}", path.to_str().unwrap())).unwrap();
+        let mut editor = test_editor("");
+        editor.do_open(&filename);
+        editor.set_cursor(5, true);
+        editor.check_file();
+        assert_eq!("one\ntwo\n", text(&editor));
+        // a clean buffer is reloaded, keeping the cursor in place
+        fs::write(&path, "zero\none\ntwo\n").unwrap();
+        editor.check_file();
+        assert_eq!("zero\none\ntwo\n", text(&editor));
+        assert_eq!(vec![(10, 10)], selection(&editor));
+        assert!(!editor.is_dirty());
+        // and the reload can be undone
+        editor.undo();
+        editor.commit_delta();
+        assert_eq!("one\ntwo\n", text(&editor));
+        // a dirty buffer is left alone
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        editor.check_file();
+        assert_eq!("one\ntwo\n", text(&editor));
+        editor.reload();
+        assert_eq!("one\ntwo\nthree\n", text(&editor));
+        assert!(!editor.is_dirty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn caret_at_soft_break() {
+        let mut editor = test_editor("hello world");
+        editor.view.rewrap(&editor.text, 8);
+        // past the end of the first line, the caret stays on that line
+        let caret = editor.view.line_col_to_caret(&editor.text, 0, 20);
+        assert_eq!(6, caret.end);
+        assert_eq!((0, 6), editor.view.caret_line_col(&editor.text, &caret));
+        editor.set_selection(Selection::new_simple(caret), true);
+        editor.move_down(0);
+        assert_eq!(vec![(11, 11)], selection(&editor));
+        // while moving right to the same offset puts it at the start of the next
+        editor.set_cursor(5, true);
+        editor.move_right(0);
+        let caret = editor.view.selection[0];
+        assert_eq!((1, 0), editor.view.caret_line_col(&editor.text, &caret));
+    }
+
+    #[test]
+    fn wide_chars() {
+        // six ideographs, two columns wide and three bytes long each
+        let mut editor = test_editor("\u{4E2D}\u{6587}\u{4E2D}\u{6587}\u{4E2D}\u{6587}");
+        editor.view.rewrap(&editor.text, 8);
+        assert_eq!((0, 6), editor.view.offset_to_line_col(&editor.text, 9));
+        assert_eq!((1, 2), editor.view.offset_to_line_col(&editor.text, 15));
+        // vertical motion keeps the column on screen, not in bytes
+        let mut editor = test_editor("ab\u{4E2D}x\n\u{4E2D}\u{6587}y\n");
+        editor.set_cursor(5, true);
+        editor.move_down(0);
+        assert_eq!(vec![(13, 13)], selection(&editor));
+        // and a column in the middle of a wide character goes before it
+        editor.set_cursor(1, true);
+        editor.move_down(0);
+        assert_eq!(vec![(7, 7)], selection(&editor));
+    }
+
+    #[test]
+    fn column_units() {
+        let mut editor = test_editor("\u{00E9}\u{4E2D}\u{1F600}x\n");
+        let cursor_col = |editor: &Editor| {
+            let lines = render_lines(editor, 0, 1);
+            let line = lines.as_array().unwrap()[0].as_array().unwrap();
+            line[1].as_array().unwrap()[1].as_u64().unwrap()
+        };
+        editor.set_column_unit(ColumnUnit::Utf16);
+        let caret = editor.view.line_col_to_caret(&editor.text, 0, 4);
+        assert_eq!(9, caret.end);
+        editor.set_selection(Selection::new_simple(caret), true);
+        assert_eq!(4, cursor_col(&editor));
+        // the middle of a surrogate pair snaps to a boundary
+        assert_eq!(9, editor.view.line_col_to_caret(&editor.text, 0, 3).end);
+        editor.set_column_unit(ColumnUnit::Codepoints);
+        assert_eq!(9, editor.view.line_col_to_caret(&editor.text, 0, 3).end);
+        assert_eq!(3, cursor_col(&editor));
+        // past the end of the line, the caret goes before the newline
+        assert_eq!(10, editor.view.line_col_to_caret(&editor.text, 0, 40).end);
+        editor.set_column_unit(ColumnUnit::Utf8);
+        assert_eq!(9, cursor_col(&editor));
+    }
+
+    #[test]
+    fn plugin_edit_concurrent() {
+        let mut editor = test_editor("hello world");
+        editor.set_cursor(11, true);
+        let update = editor.plugin_update_params(None);
+        // the user keeps typing while the plugin works on the old revision
+        type_chars(&mut editor, "!");
+        type_chars(&mut editor, " again");
+        let edit = uppercase_plugin(&update, "world").unwrap();
+        plugin_edit(&mut editor, &edit);
+        assert_eq!("hello WORLD! again", text(&editor));
+        assert_eq!(vec![(18, 18)], selection(&editor));
+    }
+
+    #[test]
+    fn undo_whole_history() {
+        let mut editor = test_editor("");
+        for _ in 0..50 {
+            type_chars(&mut editor, "x");
+            // as moving the cursor does, so each is undone by itself
+            editor.last_edit_type = EditType::Other;
+        }
+        for _ in 0..10 {
+            editor.undo();
+            editor.commit_delta();
+        }
+        // this forgets the undone edits, but none of the others
+        type_chars(&mut editor, "y");
+        assert_eq!(format!("{}y", "x".repeat(40)), text(&editor));
+        for _ in 0..41 {
+            editor.undo();
+            editor.commit_delta();
+        }
+        assert_eq!("", text(&editor));
+    }
+
+    #[test]
+    fn plugin_edit_undo() {
+        let mut editor = test_editor("hello world");
+        let update = editor.plugin_update_params(None);
+        let edit = uppercase_plugin(&update, "hello").unwrap();
+        plugin_edit(&mut editor, &edit);
+        assert_eq!("HELLO world", text(&editor));
+        editor.undo();
+        editor.commit_delta();
+        assert_eq!("hello world", text(&editor));
+    }
+
+    #[test]
+    fn plugin_edit_bad_delta() {
+        let mut editor = test_editor("hello world");
+        let edit = ObjectBuilder::new()
+            .insert("base_rev", editor.engine.get_head_rev_id())
+            .insert("priority", 1)
+            .insert_array("delta", |builder| builder
+                .push_array(|builder| builder.push(0).push(20)))
+            .unwrap();
+        plugin_edit(&mut editor, &edit);
+        assert_eq!("hello world", text(&editor));
+    }
+
+    #[test]
+    fn plugin_update_delta() {
+        let mut editor = test_editor("hello world");
+        editor.set_cursor(5, true);
+        type_chars(&mut editor, ",");
+        let delta = editor.engine.delta_rev_head(editor.engine.get_head_rev_id() - 1);
+        let update = editor.plugin_update_params(Some(&delta));
+        let delta_json = update.as_object().unwrap().get("delta").unwrap();
+        let delta = Delta::from_json(delta_json, 11).unwrap();
+        assert_eq!("hello, world", String::from(delta.apply(&Rope::from("hello world"))));
+    }
+
+    // Build the toy plugin in examples/uppercase_plugin.rs, which `cargo test`
+    // only checks, returning the path of the executable.
+    fn build_uppercase_plugin() -> PathBuf {
+        let output = process::Command::new(env!("CARGO"))
+            .args(["build", "--example", "uppercase_plugin", "--message-format=json"])
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "couldn't build the toy plugin:\n{}",
+            String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .filter_map(|msg| msg.as_object()
+                .and_then(|msg| msg.get("executable"))
+                .and_then(|path| path.as_string())
+                .map(PathBuf::from))
+            .next()
+            .expect("no executable built for the toy plugin")
+    }
+
+    #[test]
+    fn plugin_subprocess() {
+        let path = build_uppercase_plugin();
+        let (tx, rx) = mpsc::channel();
+        let mut editor = Editor::new(7, "0", tx, Rc::new(RefCell::new(StyleMap::new())));
+        editor.reset_contents(Rope::from("hello world"));
+        editor.set_cursor(11, true);
+        editor.do_start_plugin(&ObjectBuilder::new()
+            .insert("path", path.to_str().unwrap())
+            .unwrap());
+        // the user keeps typing while the plugin works on the text it was sent
+        type_chars(&mut editor, "!");
+        match rx.recv_timeout(Duration::from_secs(10)).expect("no edit from the plugin") {
+            MainMsg::PluginRpc(buffer_id, data) => {
+                assert_eq!(7, buffer_id);
+                let data = data.as_object().unwrap();
+                assert_eq!(Some("edit"), data.get("method").and_then(|v| v.as_string()));
+                plugin_edit(&mut editor, data.get("params").unwrap());
+            }
+            _ => panic!("expected a message from the plugin"),
+        }
+        assert_eq!("HELLO world!", text(&editor));
+    }
+
+    #[test]
+    fn plugin_edit_user_priority() {
+        let mut editor = test_editor("hello world");
+        let edit = ObjectBuilder::new()
+            .insert("base_rev", editor.engine.get_head_rev_id())
+            .insert("priority", 0)
+            .insert_array("delta", |builder| builder.push("HELLO"))
+            .unwrap();
+        plugin_edit(&mut editor, &edit);
+        assert_eq!("hello world", text(&editor));
+    }
+
+    #[test]
+    fn plugin_edit_after_reset() {
+        let mut editor = test_editor("hello world");
+        let update = editor.plugin_update_params(None);
+        // opening another file of the same length doesn't take the old edit
+        editor.reset_contents(Rope::from("hello there"));
+        let edit = uppercase_plugin(&update, "hello").unwrap();
+        plugin_edit(&mut editor, &edit);
+        assert_eq!("hello there", text(&editor));
+    }
+
+    #[test]
+    fn plugin_edit_unknown_rev() {
+        let mut editor = test_editor("hello world");
+        let edit = ObjectBuilder::new()
+            .insert("base_rev", 42)
+            .insert("priority", 1)
+            .insert_array("delta", |builder| builder.push("HELLO"))
+            .unwrap();
+        plugin_edit(&mut editor, &edit);
+        assert_eq!("hello world", text(&editor));
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoding files into ropes and encoding them back, in the encodings and
+//! line endings we detect.
+
+use std::char;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::str;
+
+use xi_rope::rope::Rope;
+
+use line_ending::{LineEnding, Normalizer};
+
+// The number of bytes read at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// The encoding of a file, detected when it's loaded and used again to save it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Default for Encoding {
+    fn default() -> Encoding {
+        Encoding::Utf8
+    }
+}
+
+impl Encoding {
+    fn bom(&self) -> &'static [u8] {
+        match *self {
+            Encoding::Utf8Bom => UTF8_BOM,
+            Encoding::Utf16Le => UTF16LE_BOM,
+            Encoding::Utf16Be => UTF16BE_BOM,
+            Encoding::Utf8 | Encoding::Latin1 => b"",
+        }
+    }
+}
+
+/// Read a file into a rope, a chunk at a time. The encoding is UTF-8 or
+/// UTF-16 if there's a byte order mark saying so, and otherwise UTF-8 unless
+/// the file isn't valid UTF-8, in which case it's Latin-1. Line endings are
+/// converted to `\n`, and the one most lines had is returned. The file is
+/// read once, from start to end.
+pub fn load<R: Read>(reader: &mut R) -> io::Result<(Rope, Encoding, LineEnding)> {
+    let mut start = [0; 3];
+    let n = try!(read_full(reader, &mut start));
+    let encoding = [Encoding::Utf8Bom, Encoding::Utf16Le, Encoding::Utf16Be].iter()
+        .find(|encoding| start[..n].starts_with(encoding.bom()))
+        .cloned()
+        .unwrap_or(Encoding::Utf8);
+    // the bytes after the byte order mark are decoded with the rest
+    let mut reader = (&start[encoding.bom().len()..n]).chain(reader);
+    let (text, line_ending) = match encoding {
+        Encoding::Utf8 | Encoding::Utf8Bom => return decode_utf8(&mut reader, encoding),
+        Encoding::Utf16Le => try!(decode_utf16(&mut reader, false)),
+        Encoding::Utf16Be => try!(decode_utf16(&mut reader, true)),
+        Encoding::Latin1 => unreachable!(),
+    };
+    Ok((text, encoding, line_ending))
+}
+
+/// Write `text` in `encoding`, after its byte order mark if it has one, with
+/// `line_ending` in place of `\n`. For Latin-1, a character that can't be
+/// encoded is an error, found part way through writing.
+pub fn write<W: Write>(text: &Rope, encoding: Encoding, line_ending: LineEnding, writer: &mut W)
+        -> io::Result<()> {
+    try!(writer.write_all(encoding.bom()));
+    let mut buf = Vec::new();
+    for chunk in text.iter_chunks(0, text.len()) {
+        let chunk = line_ending.apply(chunk);
+        buf.clear();
+        match encoding {
+            Encoding::Utf8 | Encoding::Utf8Bom => buf.extend_from_slice(chunk.as_bytes()),
+            Encoding::Utf16Le => for unit in chunk.encode_utf16() {
+                buf.push(unit as u8);
+                buf.push((unit >> 8) as u8);
+            },
+            Encoding::Utf16Be => for unit in chunk.encode_utf16() {
+                buf.push((unit >> 8) as u8);
+                buf.push(unit as u8);
+            },
+            Encoding::Latin1 => for c in chunk.chars() {
+                if c > '\u{FF}' {
+                    return Err(invalid_data(&format!("{:?} can't be encoded in latin-1", c)));
+                }
+                buf.push(c as u8);
+            },
+        }
+        try!(writer.write_all(&buf));
+    }
+    writer.flush()
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+// Read into `buf`, returning the number of bytes read, which is 0 only at the
+// end of the file.
+fn read_some<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buf) {
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+            result => return result,
+        }
+    }
+}
+
+// Fill as much of `buf` as the file has left.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match try!(read_some(reader, &mut buf[n..])) {
+            0 => break,
+            k => n += k,
+        }
+    }
+    Ok(n)
+}
+
+// Move the last `n` bytes of `buf[..len]` to its start.
+fn keep_tail(buf: &mut [u8], len: usize, n: usize) {
+    for i in 0..n {
+        buf[i] = buf[len - n + i];
+    }
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+// Decode UTF-8, or if the input isn't valid and `encoding` has no byte order
+// mark to say it's UTF-8, Latin-1. The bytes already read are decoded again
+// from the text they made, rather than read again.
+fn decode_utf8<R: Read>(reader: &mut R, encoding: Encoding)
+        -> io::Result<(Rope, Encoding, LineEnding)> {
+    let mut builder = Normalizer::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut pending = 0;  // bytes not decoded yet
+    loop {
+        let n = try!(read_some(reader, &mut buf[pending..]));
+        if n == 0 {
+            if pending == 0 {
+                let (text, line_ending) = builder.finish();
+                return Ok((text, encoding, line_ending));
+            }
+            // a sequence cut off at the end of the file
+            break;
+        }
+        let len = pending + n;
+        let valid = match str::from_utf8(&buf[..len]) {
+            Ok(s) => {
+                builder.push_str(s);
+                len
+            }
+            // anything other than a sequence cut off at the end is invalid
+            Err(ref e) if e.error_len().is_some() => {
+                pending = len;
+                break;
+            }
+            Err(e) => {
+                builder.push_str(str::from_utf8(&buf[..e.valid_up_to()]).unwrap());
+                e.valid_up_to()
+            }
+        };
+        pending = len - valid;
+        keep_tail(&mut buf, len, pending);
+    }
+    if encoding != Encoding::Utf8 {
+        return Err(invalid_data("invalid UTF-8"));
+    }
+    builder.redecode(|s| latin1(s.as_bytes()));
+    builder.push_str(&latin1(&buf[..pending]));
+    let (text, line_ending) = try!(decode_latin1(reader, builder));
+    Ok((text, Encoding::Latin1, line_ending))
+}
+
+fn decode_latin1<R: Read>(reader: &mut R, mut builder: Normalizer)
+        -> io::Result<(Rope, LineEnding)> {
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = try!(read_some(reader, &mut buf));
+        if n == 0 {
+            return Ok(builder.finish());
+        }
+        builder.push_str(&latin1(&buf[..n]));
+    }
+}
+
+fn decode_utf16<R: Read>(reader: &mut R, big_endian: bool) -> io::Result<(Rope, LineEnding)> {
+    let mut builder = Normalizer::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut pending = 0;  // an odd byte, or a high surrogate, split between chunks
+    loop {
+        let n = try!(read_some(reader, &mut buf[pending..]));
+        let len = pending + n;
+        if n == 0 {
+            if pending != 0 {
+                return Err(invalid_data("invalid UTF-16: incomplete at end of file"));
+            }
+            return Ok(builder.finish());
+        }
+        let mut units: Vec<u16> = buf[..len - len % 2].chunks(2).map(|b|
+            if big_endian { (b[0] as u16) << 8 | b[1] as u16 } else { (b[1] as u16) << 8 | b[0] as u16 }
+        ).collect();
+        let mut keep = len % 2;
+        // a high surrogate waits for the low one after it
+        if units.last().map_or(false, |&u| 0xD800 <= u && u < 0xDC00) {
+            units.pop();
+            keep += 2;
+        }
+        let s: String = try!(char::decode_utf16(units).collect::<Result<String, _>>()
+            .map_err(|e| invalid_data(&format!("invalid UTF-16: {}", e))));
+        builder.push_str(&s);
+        keep_tail(&mut buf, len, keep);
+        pending = keep;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use xi_rope::rope::Rope;
+    use line_ending::LineEnding;
+    use super::{load, write, Encoding, CHUNK_SIZE};
+
+    fn load_bytes(bytes: &[u8]) -> Result<(String, Encoding), String> {
+        load(&mut &bytes[..])
+            .map(|(text, encoding, _)| (String::from(text), encoding))
+            .map_err(|e| e.to_string())
+    }
+
+    fn write_bytes(text: &str, encoding: Encoding) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write(&Rope::from(text), encoding, LineEnding::Lf, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn detect() {
+        assert_eq!(Ok(("h\u{E9}!".to_string(), Encoding::Utf8)), load_bytes(b"h\xC3\xA9!"));
+        assert_eq!(Ok(("h\u{E9}!".to_string(), Encoding::Utf8Bom)),
+            load_bytes(b"\xEF\xBB\xBFh\xC3\xA9!"));
+        assert_eq!(Ok(("h\u{E9}!".to_string(), Encoding::Latin1)), load_bytes(b"h\xE9!"));
+        // valid UTF-8 before the invalid byte is Latin-1 too
+        assert_eq!(Ok(("h\u{C3}\u{A9}\nx\u{FF}".to_string(), Encoding::Latin1)),
+            load_bytes(b"h\xC3\xA9\r\nx\xFF"));
+        assert_eq!(Ok(("h\u{1F600}".to_string(), Encoding::Utf16Le)),
+            load_bytes(b"\xFF\xFEh\x00\x3D\xD8\x00\xDE"));
+        assert_eq!(Ok(("h\u{1F600}".to_string(), Encoding::Utf16Be)),
+            load_bytes(b"\xFE\xFF\x00h\xD8\x3D\xDE\x00"));
+        assert_eq!(Ok(("".to_string(), Encoding::Utf8)), load_bytes(b""));
+        // with a byte order mark, invalid text is an error
+        assert!(load_bytes(b"\xEF\xBB\xBFh\xE9!").is_err());
+        assert!(load_bytes(b"\xFF\xFEh\x00\x3D\xD8").is_err());
+        assert!(load_bytes(b"\xFF\xFEh\x00\x00").is_err());
+        assert!(load_bytes(b"\xFF\xFEh\x00\x00\xDEh\x00").is_err());
+    }
+
+    #[test]
+    fn across_chunks() {
+        // multi-byte sequences straddling the chunk boundary
+        let mut s = "a".repeat(CHUNK_SIZE - 1);
+        s.push_str("\u{E9}\u{1F600}b");
+        assert_eq!(Ok((s.clone(), Encoding::Utf8)), load_bytes(s.as_bytes()));
+        let bytes = write_bytes(&s, Encoding::Utf16Le);
+        assert_eq!(Ok((s.clone(), Encoding::Utf16Le)), load_bytes(&bytes));
+        // invalid UTF-8 late in the file makes all of it Latin-1
+        let mut bytes = s.clone().into_bytes();
+        bytes.push(0xFF);
+        let (text, encoding) = load_bytes(&bytes).unwrap();
+        assert_eq!(Encoding::Latin1, encoding);
+        assert_eq!(bytes.iter().map(|&b| b as char).collect::<String>(), text);
+    }
+
+    #[test]
+    fn round_trip() {
+        let s = "h\u{E9}llo\nw\u{F6}rld\n";
+        for &encoding in &[Encoding::Utf8, Encoding::Utf8Bom, Encoding::Utf16Le,
+                Encoding::Utf16Be, Encoding::Latin1] {
+            let bytes = write_bytes(s, encoding);
+            assert_eq!(Ok((s.to_string(), encoding)), load_bytes(&bytes));
+        }
+        assert!(write(&Rope::from("\u{4E2D}"), Encoding::Latin1, LineEnding::Lf, &mut Vec::new())
+            .is_err());
+    }
+
+    #[test]
+    fn line_endings() {
+        let bytes = b"\xFF\xFEa\x00\r\x00\n\x00b\x00\r\x00\n\x00";
+        let (text, encoding, line_ending) = load(&mut Cursor::new(&bytes[..])).unwrap();
+        assert_eq!("a\nb\n", String::from(text.clone()));
+        assert_eq!((Encoding::Utf16Le, LineEnding::CrLf), (encoding, line_ending));
+        let mut written = Vec::new();
+        write(&text, encoding, line_ending, &mut written).unwrap();
+        assert_eq!(&bytes[..], &written[..]);
+    }
+}
@@ -0,0 +1,354 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Writing files without risking their contents, and noticing when
+//! something else changes them.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::hash::Hasher;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+#[cfg(unix)]
+use libc;
+
+/// What a file was like when we last read or wrote it, to tell whether
+/// something else has changed it since.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: Option<u64>,  // None once it's changed size, until it's read again
+}
+
+impl FileStamp {
+    /// The stamp of a file with `metadata`, whose contents hashed to `hash`
+    /// as they went through `Hashing`. For a change made while the file is
+    /// being read to be noticed, `metadata` is taken before reading it.
+    pub fn new(metadata: &fs::Metadata, hash: u64) -> FileStamp {
+        FileStamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            hash: Some(hash),
+        }
+    }
+
+    /// Look at the file again, returning whether its contents have changed.
+    /// A different size is a change. A different modification time alone is
+    /// ambiguous, so then the file is read, and only counts as changed if its
+    /// contents are too, so that a file rewritten as it was (by a `git
+    /// checkout`, say) isn't reloaded.
+    pub fn refresh(&mut self, path: &Path) -> io::Result<bool> {
+        let metadata = try!(fs::metadata(path));
+        let modified = metadata.modified().ok();
+        if modified == self.modified && metadata.len() == self.len {
+            return Ok(false);
+        }
+        let hash = if metadata.len() == self.len { Some(try!(hash_file(path))) } else { None };
+        let changed = hash.is_none() || hash != self.hash;
+        self.modified = modified;
+        self.len = metadata.len();
+        self.hash = hash;
+        Ok(changed)
+    }
+}
+
+/// A reader or writer that hashes the bytes that go through it, so that a
+/// file can be stamped as it's loaded or saved, without reading it again.
+pub struct Hashing<T> {
+    inner: T,
+    hasher: DefaultHasher,
+}
+
+impl<T> Hashing<T> {
+    pub fn new(inner: T) -> Hashing<T> {
+        Hashing {
+            inner: inner,
+            hasher: DefaultHasher::new(),
+        }
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hasher.finish()
+    }
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.inner.write(buf));
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Read the file at `path` with `read`, returning what it does and a stamp
+/// of the file as it was read. `read` should read to the end of the file.
+pub fn read_stamped<T, F>(path: &Path, read: F) -> io::Result<(T, FileStamp)>
+        where F: FnOnce(&mut Hashing<File>) -> io::Result<T> {
+    let metadata = try!(fs::metadata(path));
+    let mut file = Hashing::new(try!(File::open(path)));
+    let result = try!(read(&mut file));
+    Ok((result, FileStamp::new(&metadata, file.hash())))
+}
+
+fn hash_file(path: &Path) -> io::Result<u64> {
+    let mut file = Hashing::new(try!(File::open(path)));
+    try!(io::copy(&mut file, &mut io::sink()));
+    Ok(file.hash())
+}
+
+/// Write the file at `path` with `write`, replacing it only once the new
+/// contents are safely on disk, and return a stamp of it, and whether it was
+/// written in place instead. The contents are written to a temporary file in
+/// the same directory, which is synced and then renamed over the original, so
+/// that a failure or crash part way through leaves the original intact. The
+/// original's permissions and owner are kept, and if it's a symlink, the file
+/// it links to is replaced. If the owner can't be given to the temporary file
+/// (when saving someone else's file, say), the original is truncated and
+/// written in place, which keeps its owner but not its contents on failure.
+pub fn write_atomically<F>(path: &Path, write: F) -> io::Result<(FileStamp, bool)>
+        where F: FnOnce(&mut Hashing<File>) -> io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let (file, temp_path) = try!(create_temp(&path));
+    if let Ok(metadata) = fs::metadata(&path) {
+        if !set_owner(&file, &metadata) {
+            drop(file);
+            let _ = fs::remove_file(&temp_path);
+            return write_in_place(&path, write).map(|stamp| (stamp, true));
+        }
+    }
+    let mut file = Hashing::new(file);
+    let result = write_temp(&path, &mut file, write)
+        .and_then(|_| fs::rename(&temp_path, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    try!(result);
+    sync_dir(&path);
+    let metadata = try!(fs::metadata(&path));
+    Ok((FileStamp::new(&metadata, file.hash()), false))
+}
+
+fn write_temp<F>(path: &Path, file: &mut Hashing<File>, write: F) -> io::Result<()>
+        where F: FnOnce(&mut Hashing<File>) -> io::Result<()> {
+    if let Ok(metadata) = fs::metadata(path) {
+        try!(file.inner.set_permissions(metadata.permissions()));
+    }
+    try!(write(file));
+    file.inner.sync_all()
+}
+
+fn write_in_place<F>(path: &Path, write: F) -> io::Result<FileStamp>
+        where F: FnOnce(&mut Hashing<File>) -> io::Result<()> {
+    let file = try!(OpenOptions::new().write(true).truncate(true).open(path));
+    let mut file = Hashing::new(file);
+    try!(write(&mut file));
+    try!(file.inner.sync_all());
+    let metadata = try!(fs::metadata(path));
+    Ok(FileStamp::new(&metadata, file.hash()))
+}
+
+// Give `file` the owner and group in `metadata`, returning whether it has
+// them. This is done before its permissions are set, which changing the
+// owner can clear bits of.
+#[cfg(unix)]
+fn set_owner(file: &File, metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+    match file.metadata() {
+        Ok(ref m) if m.uid() == metadata.uid() && m.gid() == metadata.gid() => true,
+        Ok(_) => unsafe { libc::fchown(file.as_raw_fd(), metadata.uid(), metadata.gid()) == 0 },
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn set_owner(_file: &File, _metadata: &fs::Metadata) -> bool {
+    true
+}
+
+// Create a file next to `path` that doesn't exist yet, named after it.
+fn create_temp(path: &Path) -> io::Result<(File, PathBuf)> {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return Err(io::Error::new(ErrorKind::InvalidInput, "not a file name")),
+    };
+    let mut i = 0;
+    loop {
+        let temp_path = path.with_file_name(format!(".{}.xi-save-{}-{}", name, process::id(), i));
+        match OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((file, temp_path)),
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => i += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Make the rename durable. This is best effort: the file is saved either way.
+#[cfg(unix)]
+fn sync_dir(path: &Path) {
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) {
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::{read_stamped, write_atomically, FileStamp};
+
+    fn stamp_of(path: &PathBuf) -> FileStamp {
+        read_stamped(path, |f| io::copy(f, &mut io::sink())).unwrap().1
+    }
+
+    // An empty directory for the test `name` to work in.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("xi-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_names(dir: &PathBuf) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn write_new_and_existing() {
+        let dir = test_dir("write");
+        let path = dir.join("a.txt");
+        write_atomically(&path, |f| f.write_all(b"one")).unwrap();
+        assert_eq!("one", fs::read_to_string(&path).unwrap());
+        write_atomically(&path, |f| f.write_all(b"two")).unwrap();
+        assert_eq!("two", fs::read_to_string(&path).unwrap());
+        assert_eq!(vec!["a.txt"], file_names(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_write_keeps_original() {
+        let dir = test_dir("failed");
+        let path = dir.join("a.txt");
+        fs::write(&path, "original").unwrap();
+        let result = write_atomically(&path, |f| {
+            try!(f.write_all(b"partial"));
+            Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+        });
+        assert!(result.is_err());
+        assert_eq!("original", fs::read_to_string(&path).unwrap());
+        assert_eq!(vec!["a.txt"], file_names(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stamp() {
+        let dir = test_dir("stamp");
+        let path = dir.join("a.txt");
+        fs::write(&path, "one").unwrap();
+        let mut stamp = stamp_of(&path);
+        assert!(!stamp.refresh(&path).unwrap());
+        // the same contents written again aren't a change
+        fs::write(&path, "one").unwrap();
+        assert!(!stamp.refresh(&path).unwrap());
+        fs::write(&path, "three").unwrap();
+        assert!(stamp.refresh(&path).unwrap());
+        assert!(!stamp.refresh(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+        assert!(stamp.refresh(&path).is_err());
+        // saving stamps what was written
+        let (stamp, _) = write_atomically(&path, |f| f.write_all(b"four")).unwrap();
+        assert_eq!(stamp_of(&path), stamp);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions_and_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        let dir = test_dir("permissions");
+        let path = dir.join("script.sh");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+        let link = dir.join("link.sh");
+        symlink(&path, &link).unwrap();
+        write_atomically(&link, |f| f.write_all(b"new")).unwrap();
+        assert_eq!("new", fs::read_to_string(&path).unwrap());
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(0o750, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_owner() {
+        use std::os::unix::fs::MetadataExt;
+        let dir = test_dir("owner");
+        let path = dir.join("a.txt");
+        fs::write(&path, "old").unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        // giving the file away only works as root; otherwise it stays ours,
+        // which the temporary file gets anyway
+        let owner = if chown(&path, 65534, 65534) { (65534, 65534) } else {
+            (metadata.uid(), metadata.gid())
+        };
+        let (_, in_place) = write_atomically(&path, |f| f.write_all(b"new")).unwrap();
+        assert!(!in_place);
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(owner, (metadata.uid(), metadata.gid()));
+        assert_eq!("new", fs::read_to_string(&path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    fn chown(path: &PathBuf, uid: u32, gid: u32) -> bool {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        unsafe { ::libc::chown(path.as_ptr(), uid, gid) == 0 }
+    }
+}
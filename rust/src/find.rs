@@ -0,0 +1,477 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Searching the text, for find and replace.
+
+use std::cmp::{max, min};
+
+use regex::{quote, Captures, Error, Regex, RegexBuilder};
+
+use xi_rope::rope::{Rope, RopeInfo};
+use xi_rope::delta::Delta;
+use xi_rope::interval::Interval;
+use xi_rope::spans::{Spans, SpansBuilder};
+use word_boundaries::is_word_char;
+
+/// A compiled search. Matches are found a line at a time, so they never span
+/// a line ending, and empty matches are skipped.
+pub struct Query {
+    regex: Regex,
+    whole_words: bool,
+    is_regex: bool,  // whether replacements expand references to groups
+    margin: usize,  // how far from an edit matches can be changed by it, in bytes
+}
+
+// Any length of text can match a regular expression, so how far from an edit
+// to search again after it is a compromise.
+const REGEX_MARGIN: usize = 1024;
+
+impl Query {
+    /// Compile a search for `pattern`, which is a regular expression if
+    /// `is_regex` is set, and otherwise literal text. With `whole_words`, only
+    /// matches with no word characters immediately around them count.
+    pub fn new(pattern: &str, ignore_case: bool, whole_words: bool, is_regex: bool)
+            -> Result<Query, Error> {
+        // a match of literal text has as many characters as it, of at most 4
+        // bytes each, and whether it's a whole word depends on one more
+        let margin = if is_regex { REGEX_MARGIN } else { 4 * (pattern.chars().count() + 1) };
+        let pattern = if is_regex { pattern.to_string() } else { quote(pattern) };
+        let regex = try!(RegexBuilder::new(&pattern).case_insensitive(ignore_case).compile());
+        Ok(Query {
+            regex: regex,
+            whole_words: whole_words,
+            is_regex: is_regex,
+            margin: margin,
+        })
+    }
+
+    // Call `f` with the start, end and groups of each match in the lines from
+    // the one containing `start` up to the one containing `end`, in order,
+    // until it returns false. Lines are assembled from the chunks of the rope,
+    // so matches may cross chunk boundaries.
+    fn each_match<F>(&self, text: &Rope, start: usize, end: usize, mut f: F)
+            where F: FnMut(usize, usize, &Captures) -> bool {
+        let mut line_start = text.offset_of_line(text.line_of_offset(start));
+        for raw_line in text.lines_raw(line_start, text.len()) {
+            if line_start >= end {
+                break;
+            }
+            let line = strip_line_ending(&raw_line);
+            for caps in self.regex.captures_iter(line) {
+                let (s, e) = caps.pos(0).unwrap();
+                if s == e || (self.whole_words && !is_whole_word(line, s, e)) {
+                    continue;
+                }
+                if !f(line_start + s, line_start + e, &caps) {
+                    return;
+                }
+            }
+            line_start += raw_line.len();
+        }
+    }
+
+    // Call `f` with the start and end of each candidate match starting in
+    // `start..end`, as though the search of its line began at `start`, and
+    // whether it's taken. The ones not taken aren't whole words, but they use
+    // up the text they match all the same. Only the query's margin of the text
+    // around `start..end` is looked at, unless a match runs on past that.
+    fn each_candidate<F>(&self, text: &Rope, start: usize, end: usize, mut f: F)
+            where F: FnMut(usize, usize, bool) {
+        let mut line = text.line_of_offset(start);
+        let mut pos = start;
+        while pos < end {
+            let line_end = line_end(text, line);
+            let context_start = max(text.offset_of_line(line), pos.saturating_sub(self.margin));
+            let context_start = floor_char_boundary(text, context_start);
+            let mut context_end = floor_char_boundary(text, min(line_end, end + self.margin));
+            let mut context = text.slice_to_string(context_start, context_end);
+            let mut at = pos - context_start;
+            while let Some((s, e)) = self.regex.find_at(&context, at) {
+                if e == context.len() && context_end < line_end {
+                    context_end = min(line_end, context_end + self.margin);
+                    context_end = floor_char_boundary(text, context_end);
+                    context = text.slice_to_string(context_start, context_end);
+                    continue;
+                }
+                if context_start + s >= end {
+                    break;
+                }
+                if s == e {
+                    // as in `captures_iter`, go on from the next character
+                    match context[e..].chars().next() {
+                        Some(c) => at = e + c.len_utf8(),
+                        None => break,
+                    }
+                    continue;
+                }
+                let taken = !self.whole_words || is_whole_word(&context, s, e);
+                f(context_start + s, context_start + e, taken);
+                at = e;
+            }
+            line += 1;
+            pos = text.offset_of_line(line);
+        }
+    }
+
+    /// The text replacing the match `start..end` with `with`, in which a regular
+    /// expression search expands references to groups such as `$1`. There is
+    /// none if `start..end` isn't a match.
+    pub fn replacement(&self, text: &Rope, start: usize, end: usize, with: &str)
+            -> Option<String> {
+        let mut result = None;
+        self.each_match(text, start, end, |s, e, caps| {
+            if (s, e) == (start, end) {
+                result = Some(self.expand(caps, with));
+            }
+            s < start
+        });
+        result
+    }
+
+    /// Edits replacing every match in the text with `with`, as for `replacement`.
+    pub fn replace_all(&self, text: &Rope, with: &str) -> Vec<(Interval, Rope)> {
+        let mut edits = Vec::new();
+        self.each_match(text, 0, text.len(), |s, e, caps| {
+            edits.push((Interval::new_closed_open(s, e), Rope::from(self.expand(caps, with))));
+            true
+        });
+        edits
+    }
+
+    fn expand(&self, caps: &Captures, with: &str) -> String {
+        if self.is_regex {
+            caps.expand(with)
+        } else {
+            with.to_string()
+        }
+    }
+}
+
+/// A search along with its matches in the text, which are kept up to date
+/// as the text is edited by searching again only around each edit.
+pub struct Search {
+    query: Query,
+    matches: Spans<()>,
+}
+
+impl Search {
+    pub fn new(query: Query, text: &Rope) -> Search {
+        let matches = find_spans(&query, text);
+        Search {
+            query: query,
+            matches: matches,
+        }
+    }
+
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    pub fn into_query(self) -> Query {
+        self.query
+    }
+
+    /// The number of matches in the text.
+    pub fn count(&self) -> usize {
+        self.matches.count_before(self.matches.len())
+    }
+
+    /// Update the matches for an edit to the text by `delta`, giving `text`.
+    /// Only around each place the delta changes is searched again.
+    pub fn after_edit(&mut self, text: &Rope, delta: &Delta<RopeInfo>) {
+        let edits = delta.edits();
+        for &(iv, new_len) in edits.iter().rev() {
+            self.matches.apply_edit(iv, new_len);
+        }
+        let (mut inserted, mut deleted) = (0, 0);  // by the edits before
+        for (iv, new_len) in edits {
+            let start = iv.start() + inserted - deleted;
+            self.search_around(text, start, start + new_len);
+            inserted += new_len;
+            deleted += iv.size();
+        }
+    }
+
+    // Search again around the new text `edit_start..edit_end`, from the query's
+    // margin before it to its margin after, within the lines it touches, and
+    // not from or to the middle of a match. Past that, the matches are the same
+    // as before, unless one found runs on past the end, when the search goes on
+    // from the end of it.
+    fn search_around(&mut self, text: &Rope, edit_start: usize, edit_end: usize) {
+        let margin = self.query.margin;
+        let line_start = text.offset_of_line(text.line_of_offset(edit_start));
+        let mut start = max(line_start, edit_start.saturating_sub(margin));
+        start = floor_char_boundary(text, start);
+        if let Some((old, _)) = self.matches.spans_at(start).next() {
+            start = old.start();
+        }
+        let line_end = line_end(text, text.line_of_offset(edit_end));
+        let mut end = floor_char_boundary(text, min(line_end, edit_end + margin));
+        let mut found = Vec::new();
+        let mut pos = start;
+        loop {
+            if let Some((old, _)) = self.matches.spans_at(end).next() {
+                if old.start() < end {
+                    end = old.end();
+                }
+            }
+            let mut scanned = pos;
+            self.query.each_candidate(text, pos, end, |s, e, taken| {
+                if taken {
+                    found.push(Interval::new_closed_open(s - start, e - start));
+                }
+                scanned = e;
+            });
+            if scanned <= end {
+                break;
+            }
+            pos = scanned;
+            end = scanned;
+        }
+        let mut builder = SpansBuilder::new(end - start);
+        for iv in found {
+            builder.add_span(iv, ());
+        }
+        self.matches.edit(Interval::new_closed_open(start, end), builder.build());
+    }
+
+    /// The matches that overlap `start..end`, clipped to it.
+    pub fn matches(&self, start: usize, end: usize) -> Vec<(usize, usize)> {
+        self.matches.iter_range(Interval::new_closed_open(start, end))
+            .map(|(iv, _)| (max(start, iv.start()), min(end, iv.end())))
+            .collect()
+    }
+
+    /// The index of the match `start..end`, counting from 0, or none if it
+    /// isn't a match.
+    pub fn index_of(&self, start: usize, end: usize) -> Option<usize> {
+        let ix = self.matches.count_before(start);
+        match self.matches.nth(ix) {
+            Some((iv, _)) if iv.start_end() == (start, end) => Some(ix),
+            _ => None,
+        }
+    }
+
+    /// The first match starting at or after `offset`, wrapping around to the
+    /// first in the text if there is none.
+    pub fn next_match(&self, offset: usize) -> Option<(usize, usize)> {
+        self.matches.nth(self.matches.count_before(offset))
+            .or_else(|| self.matches.nth(0))
+            .map(|(iv, _)| iv.start_end())
+    }
+
+    /// The last match ending at or before `offset`, wrapping around to the
+    /// last in the text if there is none.
+    pub fn prev_match(&self, offset: usize) -> Option<(usize, usize)> {
+        // matches don't overlap, so only the last one starting before
+        // `offset` can end after it
+        let before = self.matches.count_before(offset);
+        let ix = match before.checked_sub(1).and_then(|ix| self.matches.nth(ix)) {
+            Some((iv, _)) if iv.end() <= offset => Some(before - 1),
+            _ if before >= 2 => Some(before - 2),
+            _ => self.count().checked_sub(1),
+        };
+        ix.and_then(|ix| self.matches.nth(ix)).map(|(iv, _)| iv.start_end())
+    }
+}
+
+// The matches in the text.
+fn find_spans(query: &Query, text: &Rope) -> Spans<()> {
+    let mut builder = SpansBuilder::new(text.len());
+    query.each_match(text, 0, text.len(), |s, e, _| {
+        builder.add_span(Interval::new_closed_open(s, e), ());
+        true
+    });
+    builder.build()
+}
+
+// The end of `line`, before its line ending.
+fn line_end(text: &Rope, line: usize) -> usize {
+    let start = text.offset_of_line(line);
+    let mut end = text.offset_of_line(line + 1);
+    if end > start && text.byte_at(end - 1) == b'\n' {
+        end -= 1;
+    }
+    if end > start && text.byte_at(end - 1) == b'\r' {
+        end -= 1;
+    }
+    end
+}
+
+// The start of the character `offset` is in.
+fn floor_char_boundary(text: &Rope, mut offset: usize) -> usize {
+    while offset > 0 && offset < text.len() && text.byte_at(offset) & 0xC0 == 0x80 {
+        offset -= 1;
+    }
+    offset
+}
+
+fn strip_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+fn is_whole_word(line: &str, start: usize, end: usize) -> bool {
+    !line[..start].ends_with(is_word_char) && !line[end..].starts_with(is_word_char)
+}
+
+#[cfg(test)]
+mod tests {
+    use xi_rope::rope::Rope;
+    use xi_rope::delta::{Delta, DeltaBuilder};
+    use xi_rope::interval::Interval;
+    use super::{Query, Search};
+
+    fn matches(query: Query, text: &str) -> Vec<(usize, usize)> {
+        let text = Rope::from(text);
+        Search::new(query, &text).matches(0, text.len())
+    }
+
+    #[test]
+    fn options() {
+        let text = "Foo foo.bar food\nfoo";
+        assert_eq!(vec![(4, 7), (12, 15), (17, 20)],
+            matches(Query::new("foo", false, false, false).unwrap(), text));
+        assert_eq!(vec![(0, 3), (4, 7), (12, 15), (17, 20)],
+            matches(Query::new("FOO", true, false, false).unwrap(), text));
+        assert_eq!(vec![(4, 7), (17, 20)],
+            matches(Query::new("foo", false, true, false).unwrap(), text));
+        // literal searches don't treat the pattern as a regular expression
+        assert_eq!(vec![(4, 11)], matches(Query::new("foo.bar", false, false, false).unwrap(), text));
+        assert!(matches(Query::new("o.b", false, false, false).unwrap(), "foobar").is_empty());
+        assert_eq!(vec![(0, 3), (17, 20)],
+            matches(Query::new("^[a-z]+$|^Foo", false, false, true).unwrap(), text));
+        assert!(Query::new("(", false, false, true).is_err());
+        // empty matches are skipped
+        assert!(matches(Query::new("x*", false, false, true).unwrap(), text).is_empty());
+    }
+
+    #[test]
+    fn across_chunks() {
+        let mut s = "a".repeat(1500);
+        s.push_str("needle");
+        s.push_str(&"b".repeat(1500));
+        let text = Rope::from(&s[..]);
+        assert!(text.iter_chunks(0, text.len()).count() > 1);
+        let search = Search::new(Query::new("ANEEDLEB", true, false, false).unwrap(), &text);
+        assert_eq!(vec![(1499, 1507)], search.matches(0, text.len()));
+        let search = Search::new(Query::new("a+NEEDLE", true, false, true).unwrap(), &text);
+        assert_eq!(vec![(0, 1506)], search.matches(0, text.len()));
+    }
+
+    #[test]
+    fn next_and_prev() {
+        let text = Rope::from("one two\none two\r\none");
+        let search = Search::new(Query::new("one", false, false, false).unwrap(), &text);
+        assert_eq!(3, search.count());
+        assert_eq!(Some((8, 11)), search.next_match(1));
+        assert_eq!(Some((8, 11)), search.next_match(8));
+        assert_eq!(Some((0, 3)), search.next_match(19));
+        assert_eq!(Some((8, 11)), search.prev_match(17));
+        assert_eq!(Some((0, 3)), search.prev_match(10));
+        assert_eq!(Some((17, 20)), search.prev_match(2));
+        assert_eq!(Some((17, 20)), search.prev_match(20));
+        assert_eq!(Some(1), search.index_of(8, 11));
+        assert_eq!(None, search.index_of(9, 11));
+        let search = Search::new(Query::new("two$", false, false, true).unwrap(), &text);
+        assert_eq!(Some((12, 15)), search.next_match(5));
+        let search = Search::new(Query::new("three", false, false, false).unwrap(), &text);
+        assert_eq!(None, search.next_match(5));
+        assert_eq!(None, search.prev_match(5));
+    }
+
+    #[test]
+    fn incremental() {
+        let mut text = Rope::from("foo bar\n".repeat(200));
+        let query = || Query::new("foo\\b", false, false, true).unwrap();
+        let mut search = Search::new(query(), &text);
+        assert_eq!(200, search.count());
+        // joining lines, splitting them, and making and breaking matches
+        let edits = [(803, 808, "\nfoo"), (10, 20, ""), (0, 3, "food"), (1200, 1200, "foo\nfoo")];
+        for &(start, end, new) in &edits {
+            let delta = Delta::simple_edit(Interval::new_closed_open(start, end),
+                Rope::from(new), text.len());
+            text = delta.apply(&text);
+            search.after_edit(&text, &delta);
+            let fresh = Search::new(query(), &text);
+            assert_eq!(fresh.matches(0, text.len()), search.matches(0, text.len()));
+            assert_eq!(fresh.count(), search.count());
+        }
+    }
+
+    #[test]
+    fn incremental_many_regions() {
+        // as when typing with carets far apart, each making and breaking matches
+        let mut text = Rope::from("foo bar\n".repeat(2000));
+        let query = || Query::new("o b", false, false, false).unwrap();
+        let mut search = Search::new(query(), &text);
+        let carets = [(2, 3, ""), (5, 5, "x"), (8003, 8005, " b"), (15998, 16000, "ob\n")];
+        for _ in 0..2 {
+            let mut builder = DeltaBuilder::new(text.len());
+            for &(start, end, new) in &carets {
+                builder.replace(Interval::new_closed_open(start, end), Rope::from(new));
+            }
+            let delta = builder.build();
+            text = delta.apply(&text);
+            search.after_edit(&text, &delta);
+            let fresh = Search::new(query(), &text);
+            assert_eq!(fresh.matches(0, text.len()), search.matches(0, text.len()));
+            assert_eq!(fresh.count(), search.count());
+        }
+    }
+
+    #[test]
+    fn incremental_long_line() {
+        // a single line, such as minified JSON, with matches that overlap
+        // the edits, start before or end by them, and are only whole words or not
+        let mut text = Rope::from("{\"aa\":\"é\",\"aaa\":[1,2]}".repeat(500));
+        let queries = [("aa", false, false), ("aaa\"", false, false), ("AA", true, true),
+            ("a+\"", false, true)];
+        let mut searches: Vec<_> = queries.iter()
+            .map(|&(pattern, ignore_case, is_regex)| {
+                let query = Query::new(pattern, ignore_case, ignore_case, is_regex).unwrap();
+                Search::new(query, &text)
+            })
+            .collect();
+        let edits = [(4, 4, "a"), (5006, 5006, "a"), (5007, 5007, "a"), (4990, 5003, ""),
+            (7, 7, "aaaaa"), (0, 0, "a"), (100, 200, "é\"A"), (8000, 8030, "")];
+        for &(start, end, new) in &edits {
+            let delta = Delta::simple_edit(Interval::new_closed_open(start, end),
+                Rope::from(new), text.len());
+            text = delta.apply(&text);
+            for (search, &(pattern, ignore_case, is_regex)) in searches.iter_mut().zip(&queries) {
+                search.after_edit(&text, &delta);
+                let query = Query::new(pattern, ignore_case, ignore_case, is_regex).unwrap();
+                let fresh = Search::new(query, &text);
+                assert_eq!(fresh.matches(0, text.len()), search.matches(0, text.len()));
+                assert_eq!(fresh.count(), search.count());
+            }
+        }
+    }
+
+    #[test]
+    fn replacements() {
+        let text = Rope::from("let a = f(b);\nlet c = f(d);\n");
+        let query = Query::new(r"f\((\w)\)", false, false, true).unwrap();
+        assert_eq!(Some("g($1)".to_string()),
+            Query::new("f(b)", false, false, false).unwrap().replacement(&text, 8, 12, "g($1)"));
+        assert_eq!(Some("g(b)".to_string()), query.replacement(&text, 8, 12, "g($1)"));
+        assert_eq!(None, query.replacement(&text, 8, 11, "g($1)"));
+        let edits = query.replace_all(&text, "$1.f()");
+        let replaced: Vec<_> = edits.iter()
+            .map(|&(iv, ref rope)| (iv.start(), iv.end(), String::from(rope.clone())))
+            .collect();
+        assert_eq!(vec![(8, 12, "b.f()".to_string()), (22, 26, "d.f()".to_string())], replaced);
+    }
+}
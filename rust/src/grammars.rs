@@ -0,0 +1,430 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The built-in grammars: hand-written tokenizers for Rust, JSON and
+//! Markdown. They pick out enough to color text usefully, not to parse it;
+//! anything they don't recognize is left plain.
+
+use std::cmp::min;
+
+use xi_rope::interval::Interval;
+
+use highlight::{StyleId, Tokenizer};
+use highlight::{CODE, COMMENT, CONSTANT, EMPHASIS, FUNCTION, HEADING, KEYWORD, LINK, MACRO,
+    NUMBER, PROPERTY, QUOTE, STRING, STRONG, TYPE};
+
+const RUST_KEYWORDS: &[&str] = &["as", "async", "await", "break", "const", "continue", "crate",
+    "dyn", "else", "enum", "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match",
+    "mod", "move", "mut", "pub", "ref", "return", "self", "static", "struct", "super", "trait",
+    "type", "unsafe", "use", "where", "while"];
+
+const RUST_TYPES: &[&str] = &["bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize",
+    "i8", "i16", "i32", "i64", "i128", "isize", "f32", "f64"];
+
+// The length of the line without its newline.
+fn content_len(line: &str) -> usize {
+    line.trim_end_matches(&['\n', '\r'][..]).len()
+}
+
+// Non-ASCII bytes count as identifier characters, so that runs of text
+// always start and end at character boundaries.
+fn is_ident_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80
+}
+
+fn skip_while<F: Fn(u8) -> bool>(bytes: &[u8], mut i: usize, end: usize, f: F) -> usize {
+    while i < end && f(bytes[i]) {
+        i += 1;
+    }
+    i
+}
+
+fn push(runs: &mut Vec<(Interval, StyleId)>, start: usize, end: usize, style: StyleId) {
+    if start < end {
+        runs.push((Interval::new_closed_open(start, end), style));
+    }
+}
+
+// The end of a string whose contents start at `i`, just after its closing
+// quote, or None if it runs past the end of the line.
+fn string_end(bytes: &[u8], mut i: usize, end: usize, quote: u8) -> Option<usize> {
+    while i < end {
+        if bytes[i] == b'\\' {
+            i += 2;
+        } else if bytes[i] == quote {
+            return Some(i + 1);
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RustState {
+    Normal,
+    BlockComment(usize),  // nesting depth
+    Str,
+    RawStr(usize),  // number of `#`s
+}
+
+impl Default for RustState {
+    fn default() -> RustState {
+        RustState::Normal
+    }
+}
+
+pub struct RustTokenizer;
+
+impl RustTokenizer {
+    // The end of a block comment whose contents start at `i`, and the depth
+    // of nesting still open there.
+    fn block_comment_end(bytes: &[u8], mut i: usize, end: usize, mut depth: usize)
+            -> (usize, usize) {
+        while i < end {
+            if bytes[i..end].starts_with(b"/*") {
+                depth += 1;
+                i += 2;
+            } else if bytes[i..end].starts_with(b"*/") {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    break;
+                }
+            } else {
+                i += 1;
+            }
+        }
+        (i, depth)
+    }
+
+    fn raw_string_end(bytes: &[u8], mut i: usize, end: usize, hashes: usize) -> Option<usize> {
+        while i < end {
+            if bytes[i] == b'"' && bytes[i + 1..end].iter().take_while(|&&b| b == b'#').count() >= hashes {
+                return Some(i + 1 + hashes);
+            }
+            i += 1;
+        }
+        None
+    }
+
+    // A string literal starting at `i`, with any `b` and `r` prefix: its
+    // end, or the state it leaves open at the end of the line.
+    fn string_literal(bytes: &[u8], i: usize, end: usize) -> Option<Result<usize, RustState>> {
+        let mut j = i;
+        if bytes[j] == b'b' {
+            j += 1;
+        }
+        let raw = j < end && bytes[j] == b'r';
+        if raw {
+            j += 1;
+        }
+        let hashes = skip_while(bytes, j, end, |b| b == b'#') - j;
+        j += hashes;
+        if j >= end || bytes[j] != b'"' || (hashes > 0 && !raw) {
+            return None;
+        }
+        Some(if raw {
+            RustTokenizer::raw_string_end(bytes, j + 1, end, hashes).ok_or(RustState::RawStr(hashes))
+        } else {
+            string_end(bytes, j + 1, end, b'"').ok_or(RustState::Str)
+        })
+    }
+
+    // A character literal starting at `i`, which is either a quote or a `b`
+    // followed by one, or None for a lifetime.
+    fn char_literal(line: &str, i: usize, end: usize) -> Option<usize> {
+        let bytes = line.as_bytes();
+        let quote = if bytes[i] == b'b' { i + 1 } else { i };
+        if quote + 1 >= end {
+            return None;
+        }
+        if bytes[quote + 1] == b'\\' {
+            return Some(string_end(bytes, quote + 1, end, b'\'').unwrap_or(end));
+        }
+        let c_len = line[quote + 1..].chars().next().map_or(1, |c| c.len_utf8());
+        if quote + 1 + c_len < end && bytes[quote + 1 + c_len] == b'\'' {
+            Some(quote + 2 + c_len)
+        } else {
+            None
+        }
+    }
+}
+
+impl Tokenizer for RustTokenizer {
+    type State = RustState;
+
+    fn tokenize_line(&self, state: &RustState, line: &str, runs: &mut Vec<(Interval, StyleId)>)
+            -> RustState {
+        let bytes = line.as_bytes();
+        let end = content_len(line);
+        // finish whatever the previous line left open
+        let (mut state, mut i) = match *state {
+            RustState::Normal => (RustState::Normal, 0),
+            RustState::BlockComment(depth) => {
+                let (i, depth) = RustTokenizer::block_comment_end(bytes, 0, end, depth);
+                push(runs, 0, i, COMMENT);
+                (if depth > 0 { RustState::BlockComment(depth) } else { RustState::Normal }, i)
+            }
+            RustState::Str => {
+                let i = string_end(bytes, 0, end, b'"');
+                push(runs, 0, i.unwrap_or(end), STRING);
+                i.map_or((RustState::Str, end), |i| (RustState::Normal, i))
+            }
+            RustState::RawStr(hashes) => {
+                let i = RustTokenizer::raw_string_end(bytes, 0, end, hashes);
+                push(runs, 0, i.unwrap_or(end), STRING);
+                i.map_or((RustState::RawStr(hashes), end), |i| (RustState::Normal, i))
+            }
+        };
+        let mut after_fn = false;
+        while i < end && state == RustState::Normal {
+            let start = i;
+            let b = bytes[i];
+            let string = if b == b'"' || b == b'b' || b == b'r' {
+                RustTokenizer::string_literal(bytes, i, end)
+            } else {
+                None
+            };
+            let character = if b == b'\'' || bytes[i..end].starts_with(b"b'") {
+                RustTokenizer::char_literal(line, i, end)
+            } else {
+                None
+            };
+            if bytes[i..end].starts_with(b"//") {
+                push(runs, i, end, COMMENT);
+                i = end;
+            } else if bytes[i..end].starts_with(b"/*") {
+                let (j, depth) = RustTokenizer::block_comment_end(bytes, i + 2, end, 1);
+                push(runs, i, j, COMMENT);
+                if depth > 0 {
+                    state = RustState::BlockComment(depth);
+                }
+                i = j;
+            } else if let Some(string) = string {
+                i = match string {
+                    Ok(j) => j,
+                    Err(open) => {
+                        state = open;
+                        end
+                    }
+                };
+                push(runs, start, i, STRING);
+            } else if let Some(j) = character {
+                push(runs, i, j, STRING);
+                i = j;
+            } else if b == b'\'' {
+                // a lifetime
+                i = skip_while(bytes, i + 1, end, is_ident_byte);
+            } else if b.is_ascii_digit() {
+                i += 1;
+                while i < end && (is_ident_byte(bytes[i]) ||
+                        (bytes[i] == b'.' && i + 1 < end && bytes[i + 1].is_ascii_digit())) {
+                    i += 1;
+                }
+                push(runs, start, i, NUMBER);
+            } else if is_ident_byte(b) {
+                i = skip_while(bytes, i, end, is_ident_byte);
+                let word = &line[start..i];
+                if after_fn {
+                    push(runs, start, i, FUNCTION);
+                } else if i < end && bytes[i] == b'!' && !bytes[i..end].starts_with(b"!=") {
+                    i += 1;
+                    push(runs, start, i, MACRO);
+                } else if RUST_KEYWORDS.contains(&word) {
+                    push(runs, start, i, KEYWORD);
+                } else if word == "true" || word == "false" {
+                    push(runs, start, i, CONSTANT);
+                } else if RUST_TYPES.contains(&word) || b.is_ascii_uppercase() {
+                    push(runs, start, i, TYPE);
+                }
+                after_fn = word == "fn";
+            } else {
+                i += 1;
+            }
+        }
+        state
+    }
+}
+
+pub struct JsonTokenizer;
+
+impl Tokenizer for JsonTokenizer {
+    // nothing in JSON spans lines
+    type State = ();
+
+    fn tokenize_line(&self, _state: &(), line: &str, runs: &mut Vec<(Interval, StyleId)>) {
+        let bytes = line.as_bytes();
+        let end = content_len(line);
+        let mut i = 0;
+        while i < end {
+            let start = i;
+            let b = bytes[i];
+            if b == b'"' {
+                i = string_end(bytes, i + 1, end, b'"').unwrap_or(end);
+                // a string followed by a colon is an object's key
+                let next = skip_while(bytes, i, end, |b| b == b' ' || b == b'\t');
+                push(runs, start, i, if next < end && bytes[next] == b':' { PROPERTY } else { STRING });
+            } else if b == b'-' || b.is_ascii_digit() {
+                i = skip_while(bytes, i + 1, end, |b| b.is_ascii_digit() || b == b'.' ||
+                    b == b'e' || b == b'E' || b == b'+' || b == b'-');
+                push(runs, start, i, NUMBER);
+            } else if b.is_ascii_alphabetic() {
+                i = skip_while(bytes, i, end, |b| b.is_ascii_alphabetic());
+                if let "true" | "false" | "null" = &line[start..i] {
+                    push(runs, start, i, CONSTANT);
+                }
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+pub struct MarkdownTokenizer;
+
+impl MarkdownTokenizer {
+    // The end of an inline element starting at `i` and closed by `close`, or
+    // None if it isn't closed on this line.
+    fn closed_by(bytes: &[u8], i: usize, end: usize, close: &[u8]) -> Option<usize> {
+        (i..end).find(|&j| bytes[j..end].starts_with(close)).map(|j| j + close.len())
+    }
+
+    fn tokenize_inline(bytes: &[u8], mut i: usize, end: usize, runs: &mut Vec<(Interval, StyleId)>) {
+        while i < end {
+            let b = bytes[i];
+            let run = skip_while(bytes, i, end, |c| c == b) - i;
+            let element = match b {
+                b'\\' => None,
+                b'`' => MarkdownTokenizer::closed_by(bytes, i + run, end, &bytes[i..i + run])
+                    .map(|j| (j, CODE)),
+                // `_` only counts at the start of a word, so snake_case isn't emphasis
+                b'*' | b'_' if b == b'*' || i == 0 || !is_ident_byte(bytes[i - 1]) => {
+                    let delimiter = &bytes[i..i + min(run, 2)];
+                    MarkdownTokenizer::closed_by(bytes, i + delimiter.len(), end, delimiter)
+                        .map(|j| (j, if delimiter.len() == 2 { STRONG } else { EMPHASIS }))
+                }
+                b'[' => MarkdownTokenizer::closed_by(bytes, i + 1, end, b"](")
+                    .and_then(|j| MarkdownTokenizer::closed_by(bytes, j, end, b")"))
+                    .map(|j| (j, LINK)),
+                _ => None,
+            };
+            match element {
+                Some((j, style)) => {
+                    push(runs, i, j, style);
+                    i = j;
+                }
+                None if b == b'\\' => i += 2,
+                None => i += run,
+            }
+        }
+    }
+}
+
+impl Tokenizer for MarkdownTokenizer {
+    // the character and length of the fence of the code block we're in
+    type State = Option<(u8, usize)>;
+
+    fn tokenize_line(&self, state: &Option<(u8, usize)>, line: &str,
+            runs: &mut Vec<(Interval, StyleId)>) -> Option<(u8, usize)> {
+        let bytes = line.as_bytes();
+        let end = content_len(line);
+        let indent = skip_while(bytes, 0, min(end, 3), |b| b == b' ');
+        let fence_char = if indent < end { bytes[indent] } else { b' ' };
+        let fence_len = skip_while(bytes, indent, end, |b| b == fence_char) - indent;
+        if let Some((c, n)) = *state {
+            push(runs, 0, end, CODE);
+            let closes = fence_char == c && fence_len >= n &&
+                bytes[indent + fence_len..end].iter().all(|&b| b == b' ' || b == b'\t');
+            return if closes { None } else { *state };
+        }
+        if (fence_char == b'`' || fence_char == b'~') && fence_len >= 3 {
+            push(runs, 0, end, CODE);
+            Some((fence_char, fence_len))
+        } else if fence_char == b'#' && fence_len <= 6 &&
+                (indent + fence_len == end || bytes[indent + fence_len] == b' ') {
+            push(runs, 0, end, HEADING);
+            None
+        } else if fence_char == b'>' {
+            push(runs, 0, end, QUOTE);
+            None
+        } else {
+            MarkdownTokenizer::tokenize_inline(bytes, 0, end, runs);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xi_rope::rope::Rope;
+    use highlight::{Highlight, Highlighter, StyleId, Tokenizer};
+    use highlight::{CODE, COMMENT, CONSTANT, EMPHASIS, FUNCTION, HEADING, KEYWORD, LINK, MACRO,
+        NUMBER, PROPERTY, QUOTE, STRING, STRONG, TYPE};
+    use super::{JsonTokenizer, MarkdownTokenizer, RustTokenizer};
+
+    // The styled pieces of `text`.
+    fn styled<T: Tokenizer>(tokenizer: T, text: &str) -> Vec<(String, StyleId)> {
+        let rope = Rope::from(text);
+        let highlighter = Highlighter::new(tokenizer, &rope);
+        highlighter.spans().iter()
+            .map(|(iv, &style)| (text[iv.start()..iv.end()].to_string(), style))
+            .collect()
+    }
+
+    fn expected(pieces: &[(&str, StyleId)]) -> Vec<(String, StyleId)> {
+        pieces.iter().map(|&(s, style)| (s.to_string(), style)).collect()
+    }
+
+    #[test]
+    fn rust() {
+        assert_eq!(expected(&[("pub", KEYWORD), ("fn", KEYWORD), ("f", FUNCTION),
+                ("u32", TYPE), ("Option", TYPE), ("char", TYPE), ("// done", COMMENT)]),
+            styled(RustTokenizer, "pub fn f(x: u32) -> Option<char> {} // done"));
+        assert_eq!(expected(&[("let", KEYWORD), ("\"a \\\" b\"", STRING), ("'c'", STRING),
+                ("'\\n'", STRING), ("b'\u{e9}'", STRING), ("0x1f", NUMBER), ("1.5", NUMBER),
+                ("true", CONSTANT), ("println!", MACRO)]),
+            styled(RustTokenizer,
+                "let s = (\"a \\\" b\", 'c', '\\n', b'\u{e9}', 0x1f, 1.5, true); println!(s);"));
+        // lifetimes aren't character literals, and ranges aren't decimals
+        assert_eq!(expected(&[("str", TYPE), ("0", NUMBER), ("2", NUMBER)]),
+            styled(RustTokenizer, "x: &'a str = 0..2"));
+    }
+
+    #[test]
+    fn rust_multiline() {
+        let text = "a /* b /* c */\nd */ e\n\"f\ng\" h\nr#\"i\"\nj\"# /*";
+        assert_eq!(expected(&[("/* b /* c */", COMMENT), ("d */", COMMENT), ("\"f", STRING),
+                ("g\"", STRING), ("r#\"i\"", STRING), ("j\"#", STRING), ("/*", COMMENT)]),
+            styled(RustTokenizer, text));
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(expected(&[("\"a\"", PROPERTY), ("\"x\\\"y\"", STRING), ("\"b\"", PROPERTY),
+                ("-1.5e3", NUMBER), ("true", CONSTANT), ("null", CONSTANT)]),
+            styled(JsonTokenizer, "{\"a\": \"x\\\"y\",\n \"b\" : [-1.5e3, true, null]}"));
+    }
+
+    #[test]
+    fn markdown() {
+        let text = "# Title\n> quoted\nSome *em* and **strong**, `code`, [a link](url) and \
+            snake_case.\n```rust\nlet *x* = 1;\n```\n~~~~\n~~~\n~~~~\nafter";
+        assert_eq!(expected(&[("# Title", HEADING), ("> quoted", QUOTE), ("*em*", EMPHASIS),
+                ("**strong**", STRONG), ("`code`", CODE), ("[a link](url)", LINK),
+                ("```rust", CODE), ("let *x* = 1;", CODE), ("```", CODE), ("~~~~", CODE),
+                ("~~~", CODE), ("~~~~", CODE)]),
+            styled(MarkdownTokenizer, text));
+    }
+}
@@ -0,0 +1,270 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Syntax highlighting. A tokenizer for each language styles text a line at
+//! a time, and a highlighter keeps the styles of a whole text up to date as
+//! it's edited, redoing only the lines whose styles may have changed.
+
+use std::path::Path;
+
+use xi_rope::interval::Interval;
+use xi_rope::rope::Rope;
+use xi_rope::spans::{Spans, SpansBuilder};
+
+use grammars::{JsonTokenizer, MarkdownTokenizer, RustTokenizer};
+
+/// Identifies the style of a run of text: what kind of thing it is, such as
+/// a comment or a keyword, which the theme decides the look of.
+pub type StyleId = u32;
+
+// 0 is plain text, which has no span
+pub const COMMENT: StyleId = 1;
+pub const STRING: StyleId = 2;
+pub const NUMBER: StyleId = 3;
+pub const CONSTANT: StyleId = 4;
+pub const KEYWORD: StyleId = 5;
+pub const TYPE: StyleId = 6;
+pub const FUNCTION: StyleId = 7;
+pub const MACRO: StyleId = 8;
+pub const PROPERTY: StyleId = 9;
+pub const HEADING: StyleId = 10;
+pub const EMPHASIS: StyleId = 11;
+pub const STRONG: StyleId = 12;
+pub const CODE: StyleId = 13;
+pub const LINK: StyleId = 14;
+pub const QUOTE: StyleId = 15;
+
+// The TextMate scope of each style, by id, which themes give it a look by.
+const SCOPES: &[&str] = &["", "comment", "string.quoted", "constant.numeric", "constant.language",
+    "keyword", "entity.name.type", "entity.name.function", "entity.name.function.macro",
+    "support.type.property-name", "markup.heading", "markup.italic", "markup.bold",
+    "markup.raw", "markup.underline.link", "markup.quote"];
+
+pub fn scope(style: StyleId) -> &'static str {
+    SCOPES[style as usize]
+}
+
+/// Styles a language a line at a time. Whatever the tokenizer needs to carry
+/// from one line to the next, such as being inside a block comment, goes in
+/// its `State`; a line is styled the same whenever it starts in the same
+/// state, which is what lets highlighting stop once the states after an edit
+/// match the ones from before it.
+pub trait Tokenizer {
+    type State: Clone + Eq + Default;
+
+    /// Style `line`, which includes its newline (if it has one), starting in
+    /// `state`. Runs of styled text are added to `runs`, in order and with
+    /// offsets from the start of the line, and the state at the end of the
+    /// line is returned.
+    fn tokenize_line(&self, state: &Self::State, line: &str, runs: &mut Vec<(Interval, StyleId)>)
+        -> Self::State;
+}
+
+/// What a view needs of a highlighter, whatever its tokenizer.
+pub trait Highlight {
+    /// The styles of the text last highlighted.
+    fn spans(&self) -> &Spans<StyleId>;
+
+    /// Update the styles for an edit to the text, which replaced the interval
+    /// `iv` with `new_len` bytes. Returns the interval of the new text whose
+    /// styles were redone, which may run past the edit.
+    fn after_edit(&mut self, text: &Rope, iv: Interval, new_len: usize) -> Interval;
+}
+
+pub struct Highlighter<T: Tokenizer> {
+    tokenizer: T,
+    states: Vec<T::State>,  // the state at the start of each line
+    spans: Spans<StyleId>,
+}
+
+impl<T: Tokenizer> Highlighter<T> {
+    pub fn new(tokenizer: T, text: &Rope) -> Highlighter<T> {
+        let n_lines = text.line_of_offset(text.len()) + 1;
+        let mut highlighter = Highlighter {
+            tokenizer: tokenizer,
+            states: vec![T::State::default(); n_lines],
+            spans: SpansBuilder::new(text.len()).build(),
+        };
+        highlighter.highlight(text, 0, n_lines);
+        highlighter
+    }
+
+    // Style the text from the start of `first_line`, carrying on past
+    // `end_line` until a line starts in the same state as it did before.
+    fn highlight(&mut self, text: &Rope, first_line: usize, end_line: usize) -> Interval {
+        let start = text.offset_of_line(first_line);
+        let mut state = self.states[first_line].clone();
+        let mut line_num = first_line;
+        let mut offset = start;
+        let mut runs = Vec::new();
+        let mut line_runs = Vec::new();
+        for line in text.lines_raw(start, text.len()) {
+            line_runs.clear();
+            state = self.tokenizer.tokenize_line(&state, &line, &mut line_runs);
+            runs.extend(line_runs.iter().map(|&(iv, style)| (iv.translate(offset - start), style)));
+            offset += line.len();
+            line_num += 1;
+            if line_num == self.states.len() {
+                break;
+            }
+            let converged = line_num >= end_line && self.states[line_num] == state;
+            self.states[line_num] = state.clone();
+            if converged {
+                break;
+            }
+        }
+        let mut builder = SpansBuilder::new(offset - start);
+        for (iv, style) in runs {
+            builder.add_span(iv, style);
+        }
+        let iv = Interval::new_closed_open(start, offset);
+        self.spans.edit(iv, builder.build());
+        iv
+    }
+}
+
+impl<T: Tokenizer> Highlight for Highlighter<T> {
+    fn spans(&self) -> &Spans<StyleId> {
+        &self.spans
+    }
+
+    fn after_edit(&mut self, text: &Rope, iv: Interval, new_len: usize) -> Interval {
+        self.spans.apply_edit(iv, new_len);
+        // The states of the lines after the first that the edit touched are
+        // unknown, and the lines after those move.
+        let first_line = text.line_of_offset(iv.start());
+        let last_line = text.line_of_offset(iv.start() + new_len);
+        let n_lines = text.line_of_offset(text.len()) + 1;
+        let old_last_line = last_line + self.states.len() - n_lines;
+        self.states.splice(first_line + 1..old_last_line + 1,
+            vec![T::State::default(); last_line - first_line]);
+        self.highlight(text, first_line, last_line + 1)
+    }
+}
+
+/// The languages there are grammars for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Syntax {
+    Rust,
+    Json,
+    Markdown,
+}
+
+impl Syntax {
+    /// The language of a file, going by its extension.
+    pub fn from_path(path: &Path) -> Option<Syntax> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("rs") => Some(Syntax::Rust),
+            Some("json") => Some(Syntax::Json),
+            Some("md") | Some("markdown") => Some(Syntax::Markdown),
+            _ => None,
+        }
+    }
+
+    /// The scope of all the text in the language, outside the scopes of the
+    /// styles within it.
+    pub fn scope(&self) -> &'static str {
+        match *self {
+            Syntax::Rust => "source.rust",
+            Syntax::Json => "source.json",
+            Syntax::Markdown => "text.html.markdown",
+        }
+    }
+
+    pub fn highlighter(&self, text: &Rope) -> Box<dyn Highlight> {
+        match *self {
+            Syntax::Rust => Box::new(Highlighter::new(RustTokenizer, text)),
+            Syntax::Json => Box::new(Highlighter::new(JsonTokenizer, text)),
+            Syntax::Markdown => Box::new(Highlighter::new(MarkdownTokenizer, text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xi_rope::interval::Interval;
+    use xi_rope::rope::Rope;
+    use super::{Highlight, Highlighter, StyleId, Tokenizer};
+
+    // Styles words starting with `#` as comments, with `{` and `}` nesting
+    // blocks whose text is all strings.
+    struct ToyTokenizer;
+
+    impl Tokenizer for ToyTokenizer {
+        type State = usize;
+
+        fn tokenize_line(&self, state: &usize, line: &str, runs: &mut Vec<(Interval, StyleId)>)
+                -> usize {
+            let mut depth = *state;
+            let mut offset = 0;
+            for word in line.split(' ') {
+                let end = offset + word.trim_end().len();
+                if word.starts_with('{') {
+                    depth += 1;
+                } else if word.starts_with('}') {
+                    depth = depth.saturating_sub(1);
+                } else if depth > 0 {
+                    runs.push((Interval::new_closed_open(offset, end), super::STRING));
+                } else if word.starts_with('#') {
+                    runs.push((Interval::new_closed_open(offset, end), super::COMMENT));
+                }
+                offset += word.len() + 1;
+            }
+            depth
+        }
+    }
+
+    fn styles<H: Highlight>(highlighter: &H) -> Vec<(usize, usize, StyleId)> {
+        highlighter.spans().iter().map(|(iv, &style)| (iv.start(), iv.end(), style)).collect()
+    }
+
+    // Edit `text`, checking that incremental highlighting gives the same
+    // styles as starting afresh. Returns the interval redone.
+    fn edit(highlighter: &mut Highlighter<ToyTokenizer>, text: &mut Rope, start: usize,
+            end: usize, new: &str) -> (usize, usize) {
+        text.edit_str(start, end, new);
+        let iv = highlighter.after_edit(text, Interval::new_closed_open(start, end), new.len());
+        let fresh = Highlighter::new(ToyTokenizer, text);
+        assert_eq!(styles(&fresh), styles(highlighter));
+        assert_eq!(fresh.states, highlighter.states);
+        (iv.start(), iv.end())
+    }
+
+    #[test]
+    fn highlight() {
+        let text = Rope::from("a #b\n{ c\nd }\n#e");
+        let highlighter = Highlighter::new(ToyTokenizer, &text);
+        assert_eq!(vec![(2, 4, 1), (7, 8, 2), (9, 10, 2), (13, 15, 1)], styles(&highlighter));
+    }
+
+    #[test]
+    fn incremental() {
+        let mut text = Rope::from("a #b\nc\nd\n#e\nf\n");
+        let mut highlighter = Highlighter::new(ToyTokenizer, &text);
+        // an edit that doesn't change the state at the end of its line stops there
+        assert_eq!((0, 6), edit(&mut highlighter, &mut text, 1, 1, "x"));
+        // opening or closing a block restyles the rest of the text
+        assert_eq!((0, 17), edit(&mut highlighter, &mut text, 0, 0, "{ "));
+        assert_eq!((10, 19), edit(&mut highlighter, &mut text, 10, 10, "} "));
+        assert_eq!((10, 17), edit(&mut highlighter, &mut text, 10, 12, ""));
+        assert_eq!((8, 19), edit(&mut highlighter, &mut text, 8, 8, "} "));
+        // inserting and deleting lines
+        assert_eq!((8, 14), edit(&mut highlighter, &mut text, 10, 11, "q\nr"));
+        assert_eq!((0, 4), edit(&mut highlighter, &mut text, 0, 8, ""));
+        let len = text.len();
+        edit(&mut highlighter, &mut text, 0, len, "");
+        edit(&mut highlighter, &mut text, 0, 0, "{\n\n}\n#x");
+        edit(&mut highlighter, &mut text, 1, 2, "");
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Line endings. Text is kept with `\n` line endings, whatever the file it
+//! came from used; the buffer remembers which, to use it again when saving.
+//! A `\r` that isn't part of a `\r\n` only ends a line in a file where most
+//! lines end that way, and is otherwise kept, so saving leaves it alone.
+
+use std::borrow::Cow;
+use std::mem;
+
+use xi_rope::rope::{Rope, RopeInfo};
+use xi_rope::tree::TreeBuilder;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl Default for LineEnding {
+    fn default() -> LineEnding {
+        LineEnding::Lf
+    }
+}
+
+impl LineEnding {
+    pub fn from_name(name: &str) -> Option<LineEnding> {
+        match name {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::CrLf),
+            "cr" => Some(LineEnding::Cr),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LineEnding::Lf => "lf",
+            LineEnding::CrLf => "crlf",
+            LineEnding::Cr => "cr",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    /// `s`, which has `\n` line endings, with this line ending instead.
+    pub fn apply<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        if *self == LineEnding::Lf || !s.contains('\n') {
+            Cow::Borrowed(s)
+        } else {
+            Cow::Owned(s.replace('\n', self.as_str()))
+        }
+    }
+}
+
+/// Builds a rope from text pushed a piece at a time, converting its line
+/// endings to `\n` and counting which kind it used.
+pub struct Normalizer {
+    builder: TreeBuilder<RopeInfo>,  // with each lone `\r` kept until `finish`
+    pending_cr: bool,  // the last piece ended with `\r`, which may start a `\r\n`
+    n_lf: usize,
+    n_crlf: usize,
+    n_cr: usize,
+}
+
+impl Normalizer {
+    pub fn new() -> Normalizer {
+        Normalizer {
+            builder: TreeBuilder::new(),
+            pending_cr: false,
+            n_lf: 0,
+            n_crlf: 0,
+            n_cr: 0,
+        }
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        let mut out = String::with_capacity(s.len() + 1);
+        let mut rest = s;
+        if self.pending_cr && !rest.is_empty() {
+            self.pending_cr = false;
+            if rest.starts_with('\n') {
+                self.n_crlf += 1;
+                rest = &rest[1..];
+                out.push('\n');
+            } else {
+                self.n_cr += 1;
+                out.push('\r');
+            }
+        }
+        while let Some(i) = rest.find(&['\r', '\n'][..]) {
+            out.push_str(&rest[..i]);
+            let after = &rest.as_bytes()[i + 1..];
+            if rest.as_bytes()[i] == b'\n' {
+                self.n_lf += 1;
+                out.push('\n');
+                rest = &rest[i + 1..];
+            } else if after.is_empty() {
+                self.pending_cr = true;
+                rest = "";
+            } else if after[0] == b'\n' {
+                self.n_crlf += 1;
+                out.push('\n');
+                rest = &rest[i + 2..];
+            } else {
+                self.n_cr += 1;
+                out.push('\r');
+                rest = &rest[i + 1..];
+            }
+        }
+        out.push_str(rest);
+        self.builder.push_str(&out);
+    }
+
+    /// Decode the text pushed so far again, a chunk at a time, with `decode`,
+    /// which mustn't add or remove line breaks. This is for text that turns
+    /// out part way through not to be in the encoding it was decoded from.
+    pub fn redecode<F: FnMut(&str) -> String>(&mut self, mut decode: F) {
+        let text = mem::replace(&mut self.builder, TreeBuilder::new()).build();
+        for chunk in text.iter_chunks(0, text.len()) {
+            self.builder.push_str(&decode(chunk));
+        }
+    }
+
+    /// The text, and the line ending most of its lines had. Ties, and text
+    /// without line breaks, count as `\n`. Only if it's `\r` are the lone ones
+    /// turned into `\n`.
+    pub fn finish(mut self) -> (Rope, LineEnding) {
+        if self.pending_cr {
+            self.n_cr += 1;
+            self.builder.push_str("\r");
+        }
+        let line_ending = if self.n_crlf > self.n_lf && self.n_crlf >= self.n_cr {
+            LineEnding::CrLf
+        } else if self.n_cr > self.n_lf && self.n_cr > self.n_crlf {
+            LineEnding::Cr
+        } else {
+            LineEnding::Lf
+        };
+        let text = self.builder.build();
+        if line_ending != LineEnding::Cr {
+            return (text, line_ending);
+        }
+        let mut builder = TreeBuilder::new();
+        for chunk in text.iter_chunks(0, text.len()) {
+            builder.push_str(&chunk.replace('\r', "\n"));
+        }
+        (builder.build(), line_ending)
+    }
+}
+
+/// `s` as a rope with `\n` line endings, for inserting into the text.
+pub fn normalize(s: &str) -> Rope {
+    let mut normalizer = Normalizer::new();
+    normalizer.push_str(s);
+    normalizer.finish().0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, LineEnding, Normalizer};
+
+    fn normalize_pieces(pieces: &[&str]) -> (String, LineEnding) {
+        let mut normalizer = Normalizer::new();
+        for piece in pieces {
+            normalizer.push_str(piece);
+        }
+        let (text, line_ending) = normalizer.finish();
+        (String::from(text), line_ending)
+    }
+
+    #[test]
+    fn detect() {
+        assert_eq!(("a\nb\n".to_string(), LineEnding::Lf), normalize_pieces(&["a\nb\n"]));
+        assert_eq!(("a\nb\nc".to_string(), LineEnding::CrLf), normalize_pieces(&["a\r\nb\r\nc"]));
+        assert_eq!(("a\nb\n".to_string(), LineEnding::Cr), normalize_pieces(&["a\rb\r"]));
+        assert_eq!(("a\nb\nc\n".to_string(), LineEnding::CrLf), normalize_pieces(&["a\r\nb\nc\r\n"]));
+        assert_eq!(("ab".to_string(), LineEnding::Lf), normalize_pieces(&["ab"]));
+        // a `\r\n` split between pieces
+        assert_eq!(("a\nb\n".to_string(), LineEnding::CrLf),
+            normalize_pieces(&["a\r", "\nb\r", "", "\n"]));
+        assert_eq!(("a\n\nb".to_string(), LineEnding::Cr), normalize_pieces(&["a\r", "\rb"]));
+        assert_eq!("one\ntwo\n", String::from(normalize("one\r\ntwo\r\n")));
+        // lone `\r`s only end lines where most lines end that way
+        assert_eq!(("a\nb\rc\n".to_string(), LineEnding::Lf),
+            normalize_pieces(&["a\nb\rc\n"]));
+        assert_eq!(("a\nb\rc\n".to_string(), LineEnding::CrLf),
+            normalize_pieces(&["a\r\nb\r", "c\r\n"]));
+        assert_eq!(("a\nb\nc\n".to_string(), LineEnding::Cr), normalize_pieces(&["a\rb\r\nc\r"]));
+        assert_eq!("one\ntwo\n", String::from(normalize("one\rtwo\r")));
+    }
+
+    #[test]
+    fn round_trip() {
+        for &s in &["a\nb\rc\n", "a\r\nb\rc\r\n", "a\rb\rc\r", "a\r"] {
+            let (text, line_ending) = normalize_pieces(&[s]);
+            assert_eq!(s, line_ending.apply(&text));
+        }
+    }
+
+    #[test]
+    fn apply() {
+        assert_eq!("a\r\nb\r\n", LineEnding::CrLf.apply("a\nb\n"));
+        assert_eq!("a\rb", LineEnding::Cr.apply("a\nb"));
+        assert_eq!("a\nb", LineEnding::Lf.apply("a\nb"));
+        assert_eq!(Some(LineEnding::CrLf), LineEnding::from_name("crlf"));
+        assert_eq!("crlf", LineEnding::CrLf.name());
+        assert_eq!(None, LineEnding::from_name("lfcr"));
+    }
+}
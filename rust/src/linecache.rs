@@ -0,0 +1,225 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The core's model of the front-end's line cache, used to send updates that
+//! only contain the lines that changed.
+
+use std::cmp::{min, max};
+
+/// An operation in an update, transforming the front-end's old cache of lines
+/// into the new one.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LineOp {
+    /// Copy the next `n` lines of the old cache.
+    Copy(usize),
+    /// Skip over the next `n` lines of the old cache.
+    Skip(usize),
+    /// Add `n` lines that the front-end doesn't have.
+    Invalidate(usize),
+    /// Add the lines `start..end` of the current text, rendered.
+    Ins(usize, usize),
+}
+
+// A run of `n` lines. If valid, the front-end has them, at `old_ix` onwards
+// in its cache.
+#[derive(Clone, Copy)]
+struct Span {
+    n: usize,
+    valid: bool,
+    old_ix: usize,
+}
+
+/// Tracks, for each line of the current text, whether the front-end has an
+/// up-to-date copy of it, and where.
+#[derive(Default)]
+pub struct LineCacheShadow {
+    spans: Vec<Span>,
+}
+
+impl LineCacheShadow {
+    pub fn new() -> LineCacheShadow {
+        LineCacheShadow::default()
+    }
+
+    /// The number of lines in the text, as of the last edit.
+    pub fn n_lines(&self) -> usize {
+        self.spans.iter().fold(0, |acc, span| acc + span.n)
+    }
+
+    /// Forget everything the front-end has; the text now has `n_lines` lines.
+    pub fn reset(&mut self, n_lines: usize) {
+        self.spans.clear();
+        self.push(Span { n: n_lines, valid: false, old_ix: 0 });
+    }
+
+    /// Record an edit replacing lines `start..end` with `new_n` lines.
+    pub fn edit(&mut self, start: usize, end: usize, new_n: usize) {
+        let mut result = LineCacheShadow::new();
+        let mut line = 0;
+        let mut inserted = false;
+        for span in &self.spans {
+            let span_end = line + span.n;
+            if line < start {
+                let n = min(span_end, start) - line;
+                result.push(Span { n: n, ..*span });
+            }
+            if !inserted && start <= span_end {
+                result.push(Span { n: new_n, valid: false, old_ix: 0 });
+                inserted = true;
+            }
+            if span_end > end {
+                let skip = end.saturating_sub(line);
+                result.push(Span { n: span.n - skip, valid: span.valid, old_ix: span.old_ix + skip });
+            }
+            line = span_end;
+        }
+        if !inserted {
+            result.push(Span { n: new_n, valid: false, old_ix: 0 });
+        }
+        *self = result;
+    }
+
+    /// Mark lines `start..end` as needing to be sent again, for example because
+    /// the selection in them changed.
+    pub fn invalidate(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.edit(start, end, end - start);
+        }
+    }
+
+    /// Compute the operations for an update that makes the front-end's cache
+    /// valid for lines `first..last`, and record that it has been sent.
+    pub fn render(&mut self, first: usize, last: usize) -> Vec<LineOp> {
+        let mut ops = Vec::new();
+        let mut result = LineCacheShadow::new();
+        let mut old_ix = 0;
+        let mut line = 0;
+        for span in &self.spans {
+            let span_end = line + span.n;
+            if span.valid {
+                if span.old_ix > old_ix {
+                    push_op(&mut ops, LineOp::Skip(span.old_ix - old_ix));
+                }
+                push_op(&mut ops, LineOp::Copy(span.n));
+                old_ix = span.old_ix + span.n;
+                result.push(Span { n: span.n, valid: true, old_ix: line });
+            } else {
+                let ins_start = max(line, min(first, span_end));
+                let ins_end = max(ins_start, min(last, span_end));
+                if ins_start > line {
+                    push_op(&mut ops, LineOp::Invalidate(ins_start - line));
+                    result.push(Span { n: ins_start - line, valid: false, old_ix: 0 });
+                }
+                if ins_end > ins_start {
+                    push_op(&mut ops, LineOp::Ins(ins_start, ins_end));
+                    result.push(Span { n: ins_end - ins_start, valid: true, old_ix: ins_start });
+                }
+                if span_end > ins_end {
+                    push_op(&mut ops, LineOp::Invalidate(span_end - ins_end));
+                    result.push(Span { n: span_end - ins_end, valid: false, old_ix: 0 });
+                }
+            }
+            line = span_end;
+        }
+        *self = result;
+        ops
+    }
+
+    // Append a span, merging it with the last one when possible.
+    fn push(&mut self, span: Span) {
+        if span.n == 0 {
+            return;
+        }
+        if let Some(last) = self.spans.last_mut() {
+            if last.valid == span.valid && (!span.valid || last.old_ix + last.n == span.old_ix) {
+                last.n += span.n;
+                return;
+            }
+        }
+        self.spans.push(span);
+    }
+}
+
+fn push_op(ops: &mut Vec<LineOp>, op: LineOp) {
+    match (ops.last_mut(), &op) {
+        (Some(&mut LineOp::Copy(ref mut n)), &LineOp::Copy(n2)) |
+        (Some(&mut LineOp::Skip(ref mut n)), &LineOp::Skip(n2)) |
+        (Some(&mut LineOp::Invalidate(ref mut n)), &LineOp::Invalidate(n2)) => {
+            *n += n2;
+            return;
+        }
+        (Some(&mut LineOp::Ins(_, ref mut end)), &LineOp::Ins(start2, end2)) if *end == start2 => {
+            *end = end2;
+            return;
+        }
+        _ => ()
+    }
+    ops.push(op);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LineCacheShadow, LineOp};
+
+    #[test]
+    fn initial_render() {
+        let mut shadow = LineCacheShadow::new();
+        shadow.reset(100);
+        assert_eq!(vec![LineOp::Invalidate(10), LineOp::Ins(10, 20), LineOp::Invalidate(80)],
+            shadow.render(10, 20));
+        // nothing changed, so the lines already sent are copied
+        assert_eq!(vec![LineOp::Invalidate(10), LineOp::Skip(10), LineOp::Copy(10),
+            LineOp::Invalidate(80)], shadow.render(10, 20));
+        // scrolling sends only the new lines
+        assert_eq!(vec![LineOp::Invalidate(10), LineOp::Skip(10), LineOp::Copy(10),
+            LineOp::Ins(20, 25), LineOp::Invalidate(75)], shadow.render(15, 25));
+    }
+
+    #[test]
+    fn edit_lines() {
+        let mut shadow = LineCacheShadow::new();
+        shadow.reset(10);
+        shadow.render(0, 10);
+        // replace line 3 with two lines
+        shadow.edit(3, 4, 2);
+        assert_eq!(11, shadow.n_lines());
+        assert_eq!(vec![LineOp::Copy(3), LineOp::Ins(3, 5), LineOp::Skip(1), LineOp::Copy(6)],
+            shadow.render(0, 11));
+        // delete lines 0 and 1, and change the new line 0
+        shadow.edit(0, 3, 1);
+        assert_eq!(vec![LineOp::Ins(0, 1), LineOp::Skip(3), LineOp::Copy(8)],
+            shadow.render(0, 9));
+    }
+
+    #[test]
+    fn edit_outside_window() {
+        let mut shadow = LineCacheShadow::new();
+        shadow.reset(10);
+        shadow.render(0, 5);
+        shadow.edit(7, 7, 3);
+        shadow.invalidate(2, 3);
+        assert_eq!(vec![LineOp::Copy(2), LineOp::Ins(2, 3), LineOp::Skip(1), LineOp::Copy(2),
+            LineOp::Invalidate(8)], shadow.render(0, 5));
+    }
+
+    #[test]
+    fn edit_at_end() {
+        let mut shadow = LineCacheShadow::new();
+        shadow.reset(2);
+        shadow.render(0, 2);
+        shadow.edit(1, 2, 3);
+        assert_eq!(4, shadow.n_lines());
+        assert_eq!(vec![LineOp::Copy(1), LineOp::Ins(1, 4)], shadow.render(0, 4));
+    }
+}
@@ -20,7 +20,10 @@ use xi_rope::rope::{Rope, RopeInfo};
 use xi_rope::tree::Cursor;
 use xi_rope::interval::Interval;
 use xi_rope::breaks::{Breaks, BreakBuilder, BreaksBaseMetric};
-use xi_unicode::LineBreakLeafIter;
+use xi_unicode::{LineBreakLeafIter, str_width};
+
+/// The distance between tab stops, in columns.
+pub const TAB_WIDTH: usize = 4;
 
 struct LineBreakCursor<'a> {
     inner: Cursor<'a, RopeInfo>,
@@ -65,6 +68,18 @@ impl<'a> LineBreakCursor<'a> {
     }
 }
 
+/// The number of columns taken by the text from `start` to `end`, when it
+/// starts at column `col`.
+pub fn text_width(text: &Rope, start: usize, end: usize, col: usize) -> usize {
+    let mut width = 0;
+    for chunk in text.iter_chunks(start, end) {
+        width += str_width(chunk, col + width, TAB_WIDTH);
+    }
+    width
+}
+
+// Widths are in columns of display width (so CJK characters count as 2), but
+// the breaks record lengths of lines in bytes.
 pub fn linewrap(text: &Rope, cols: usize) -> Breaks {
     let start_time = time::now();
     let mut lb_cursor = LineBreakCursor::new(text, 0);
@@ -74,18 +89,19 @@ pub fn linewrap(text: &Rope, cols: usize) -> Breaks {
     let mut width = 0;
     loop {
         let (pos, hard) = lb_cursor.next();
-        let word_width = pos - last_pos;
-        if width > 0 && width + word_width > cols {
-            builder.add_break(width);
-            //print_err!("soft break {}", width);
-            last_break_pos += width;
+        let mut word_width = text_width(text, last_pos, pos, width);
+        if last_pos > last_break_pos && width + word_width > cols {
+            builder.add_break(last_pos - last_break_pos);
+            //print_err!("soft break {}", last_pos - last_break_pos);
+            last_break_pos = last_pos;
             width = 0;
+            word_width = text_width(text, last_pos, pos, 0);
         }
         width += word_width;
         if hard {
-            builder.add_break(width);
-            //print_err!("hard break {}", width);
-            last_break_pos += width;
+            builder.add_break(pos - last_break_pos);
+            //print_err!("hard break {}", pos - last_break_pos);
+            last_break_pos = pos;
             width = 0;
         }
         last_pos = pos;
@@ -121,11 +137,12 @@ pub fn rewrap(breaks: &mut Breaks, text: &Rope, iv: Interval, newsize: usize, co
         let mut width = 0;
         loop {
             let (pos, hard) = lb_cursor.next();
-            let word_width = pos - last_pos;
-            if width > 0 && width + word_width > cols {
-                builder.add_break(width);
-                last_break_pos += width;
+            let mut word_width = text_width(text, last_pos, pos, width);
+            if last_pos > last_break_pos && width + word_width > cols {
+                builder.add_break(last_pos - last_break_pos);
+                last_break_pos = last_pos;
                 width = 0;
+                word_width = text_width(text, last_pos, pos, 0);
                 while last_break_pos > inval_end {
                     inval_end = bk_cursor.next::<BreaksBaseMetric>().map_or(text.len(), |pos|
                         pos - (end - start) + newsize);
@@ -137,8 +154,8 @@ pub fn rewrap(breaks: &mut Breaks, text: &Rope, iv: Interval, newsize: usize, co
             width += word_width;
             if hard {
                 // TODO: DRY
-                builder.add_break(width);
-                last_break_pos += width;
+                builder.add_break(pos - last_break_pos);
+                last_break_pos = pos;
                 width = 0;
                 while last_break_pos > inval_end {
                     inval_end = bk_cursor.next::<BreaksBaseMetric>().map_or(text.len(), |pos|
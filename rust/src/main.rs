@@ -15,9 +15,14 @@
 extern crate serde;
 extern crate serde_json;
 extern crate time;
+extern crate regex;
+extern crate libc;
 
 use std::io;
 use std::io::{BufRead, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 use serde_json::Value;
 
 #[macro_use]
@@ -27,6 +32,20 @@ mod tabs;
 mod editor;
 mod view;
 mod linewrap;
+mod linecache;
+mod selection;
+mod columns;
+mod word_boundaries;
+mod find;
+mod encoding;
+mod line_ending;
+mod files;
+mod highlight;
+mod grammars;
+mod styles;
+mod theme;
+mod plist;
+mod plugins;
 
 use tabs::Tabs;
 use std::io::Error;
@@ -34,6 +53,21 @@ use std::io::Error;
 extern crate xi_rope;
 extern crate xi_unicode;
 
+// How often open files are checked for changes made by other programs.
+const FILE_CHECK_INTERVAL_MS: u64 = 1000;
+
+/// Messages handled by the main loop, which owns all of the editing state.
+pub enum MainMsg {
+    /// A request from the front-end.
+    Rpc(Value),
+    /// A request from a plugin attached to the buffer with the given id.
+    PluginRpc(usize, Value),
+    /// Time to check open files for changes.
+    CheckFiles,
+    /// The front-end closed stdin.
+    Eof,
+}
+
 pub fn send(v: &Value) -> Result<(), Error> {
     let mut s = serde_json::to_string(v).unwrap();
     s.push('\n');
@@ -42,24 +76,53 @@ pub fn send(v: &Value) -> Result<(), Error> {
 }
 
 fn main() {
-    let stdin = io::stdin();
-    let mut stdin_handle = stdin.lock();
-    let mut buf = String::new();
-    let mut tabs = Tabs::new();
-    while stdin_handle.read_line(&mut buf).is_ok() {
-        if buf.is_empty() {
-            break;
+    let (tx, rx) = mpsc::channel();
+    let mut tabs = Tabs::new(tx.clone(), theme::themes_dir());
+    let check_tx = tx.clone();
+    thread::spawn(move || {
+        while check_tx.send(MainMsg::CheckFiles).is_ok() {
+            thread::sleep(Duration::from_millis(FILE_CHECK_INTERVAL_MS));
         }
-        if let Ok(data) = serde_json::from_slice::<Value>(buf.as_bytes()) {
-            print_err!("to core: {:?}", data);
-            if let Some(req) = data.as_object() {
-                if let (Some(method), Some(params)) =
-                        (req.get("method").and_then(|v| v.as_string()), req.get("params")) {
-                    let id = req.get("id");
-                    tabs.handle_rpc(method, params, id);
+    });
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut stdin_handle = stdin.lock();
+        let mut buf = String::new();
+        while stdin_handle.read_line(&mut buf).is_ok() {
+            if buf.is_empty() {
+                break;
+            }
+            if let Ok(data) = serde_json::from_slice::<Value>(buf.as_bytes()) {
+                if tx.send(MainMsg::Rpc(data)).is_err() {
+                    return;
+                }
+            }
+            buf.clear();
+        }
+        let _ = tx.send(MainMsg::Eof);
+    });
+    for msg in rx.iter() {
+        match msg {
+            MainMsg::Rpc(data) => {
+                print_err!("to core: {:?}", data);
+                if let Some(req) = data.as_object() {
+                    if let (Some(method), Some(params)) =
+                            (req.get("method").and_then(|v| v.as_string()), req.get("params")) {
+                        let id = req.get("id");
+                        tabs.handle_rpc(method, params, id);
+                    }
+                }
+            }
+            MainMsg::PluginRpc(buffer_id, data) => {
+                if let Some(req) = data.as_object() {
+                    if let (Some(method), Some(params)) =
+                            (req.get("method").and_then(|v| v.as_string()), req.get("params")) {
+                        tabs.handle_plugin_rpc(buffer_id, method, params);
+                    }
                 }
             }
+            MainMsg::CheckFiles => tabs.check_files(),
+            MainMsg::Eof => break,
         }
-        buf.clear();
     }
 }
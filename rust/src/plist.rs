@@ -0,0 +1,268 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A reader for XML property lists, as TextMate themes are written in.
+//! Dates and data are kept as the text they're written as.
+
+use std::collections::BTreeMap;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Plist {
+    Dict(BTreeMap<String, Plist>),
+    Array(Vec<Plist>),
+    String(String),
+    Integer(i64),
+    Real(f64),
+    Boolean(bool),
+}
+
+impl Plist {
+    pub fn as_dict(&self) -> Option<&BTreeMap<String, Plist>> {
+        match *self {
+            Plist::Dict(ref dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Plist]> {
+        match *self {
+            Plist::Array(ref array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Plist::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Parse the XML of a property list, giving the value at its root or a
+/// message saying where it went wrong.
+pub fn parse(s: &str) -> Result<Plist, String> {
+    let mut parser = Parser { s: s, pos: 0 };
+    let root = match try!(parser.next_tag()) {
+        Tag::Open("plist") => {
+            let root = try!(parser.value());
+            try!(parser.expect(Tag::Close("plist")));
+            root
+        }
+        tag => try!(parser.value_of(tag)),
+    };
+    if parser.pos < s.len() && try!(parser.skip_misc()) {
+        return Err(parser.error("text after the root value"));
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+#[derive(PartialEq, Debug)]
+enum Tag<'a> {
+    Open(&'a str),
+    Close(&'a str),
+    Empty(&'a str),  // such as `<true/>`
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, what: &str) -> String {
+        format!("malformed plist at byte {}: {}", self.pos, what)
+    }
+
+    // Skip whitespace, comments and the XML declaration and doctype, returning
+    // whether there's anything left.
+    fn skip_misc(&mut self) -> Result<bool, String> {
+        loop {
+            let rest = &self.s[self.pos..];
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            let end = if trimmed.starts_with("<!--") {
+                trimmed.find("-->").map(|i| i + 3)
+            } else if trimmed.starts_with("<?") || trimmed.starts_with("<!") {
+                trimmed.find('>').map(|i| i + 1)
+            } else {
+                return Ok(!trimmed.is_empty());
+            };
+            match end {
+                Some(end) => self.pos += end,
+                None => return Err(self.error("unterminated markup")),
+            }
+        }
+    }
+
+    fn next_tag(&mut self) -> Result<Tag<'a>, String> {
+        if !try!(self.skip_misc()) {
+            return Err(self.error("unexpected end"));
+        }
+        let s = self.s;
+        let rest = &s[self.pos..];
+        if !rest.starts_with('<') {
+            return Err(self.error("expected a tag"));
+        }
+        let end = try!(rest.find('>').ok_or_else(|| self.error("unterminated tag")));
+        self.pos += end + 1;
+        let inner = &rest[1..end];
+        // attributes, such as the plist's version, are ignored
+        let name = |s: &'a str| s.split_whitespace().next().unwrap_or("");
+        Ok(if let Some(inner) = inner.strip_prefix('/') {
+            Tag::Close(name(inner))
+        } else if let Some(inner) = inner.strip_suffix('/') {
+            Tag::Empty(name(inner))
+        } else {
+            Tag::Open(name(inner))
+        })
+    }
+
+    fn expect(&mut self, tag: Tag) -> Result<(), String> {
+        if try!(self.next_tag()) != tag {
+            return Err(self.error(&format!("expected {:?}", tag)));
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Plist, String> {
+        let tag = try!(self.next_tag());
+        self.value_of(tag)
+    }
+
+    // The value starting with `tag`, which has just been read.
+    fn value_of(&mut self, tag: Tag) -> Result<Plist, String> {
+        match tag {
+            Tag::Open("dict") => {
+                let mut dict = BTreeMap::new();
+                loop {
+                    match try!(self.next_tag()) {
+                        Tag::Close("dict") => return Ok(Plist::Dict(dict)),
+                        Tag::Open("key") => {
+                            let key = try!(self.text("key"));
+                            dict.insert(key, try!(self.value()));
+                        }
+                        _ => return Err(self.error("expected a key")),
+                    }
+                }
+            }
+            Tag::Open("array") => {
+                let mut array = Vec::new();
+                loop {
+                    match try!(self.next_tag()) {
+                        Tag::Close("array") => return Ok(Plist::Array(array)),
+                        tag => array.push(try!(self.value_of(tag))),
+                    }
+                }
+            }
+            Tag::Empty("dict") => Ok(Plist::Dict(BTreeMap::new())),
+            Tag::Empty("array") => Ok(Plist::Array(Vec::new())),
+            Tag::Empty("string") => Ok(Plist::String(String::new())),
+            Tag::Empty("true") => Ok(Plist::Boolean(true)),
+            Tag::Empty("false") => Ok(Plist::Boolean(false)),
+            Tag::Open(name @ "string") | Tag::Open(name @ "date") | Tag::Open(name @ "data") =>
+                Ok(Plist::String(try!(self.text(name)))),
+            Tag::Open("integer") => {
+                let text = try!(self.text("integer"));
+                text.trim().parse().map(Plist::Integer).map_err(|_| self.error("bad integer"))
+            }
+            Tag::Open("real") => {
+                let text = try!(self.text("real"));
+                text.trim().parse().map(Plist::Real).map_err(|_| self.error("bad real"))
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    // The text up to the closing tag of the element `name`, with entities
+    // and character references replaced.
+    fn text(&mut self, name: &str) -> Result<String, String> {
+        let s = self.s;
+        let rest = &s[self.pos..];
+        let end = try!(rest.find('<').ok_or_else(|| self.error("unterminated text")));
+        let mut text = String::new();
+        let mut raw = &rest[..end];
+        while let Some(amp) = raw.find('&') {
+            text.push_str(&raw[..amp]);
+            let semi = try!(raw[amp..].find(';').ok_or_else(|| self.error("bad entity")));
+            let entity = &raw[amp + 1..amp + semi];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok()
+                    .and_then(::std::char::from_u32),
+                _ if entity.starts_with('#') => entity[1..].parse().ok()
+                    .and_then(::std::char::from_u32),
+                _ => None,
+            };
+            text.push(try!(c.ok_or_else(|| self.error("bad entity"))));
+            raw = &raw[amp + semi + 1..];
+        }
+        text.push_str(raw);
+        self.pos += end;
+        try!(self.expect(Tag::Close(name)));
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::{parse, Plist};
+
+    #[test]
+    fn values() {
+        let plist = parse(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <!-- a <comment> -->
+    <key>name</key>
+    <string>Fish &amp; Chips &#x263A;</string>
+    <key>list</key>
+    <array>
+        <integer>-12</integer>
+        <real>1.5</real>
+        <true/>
+        <false/>
+        <string></string>
+        <string/>
+        <dict/>
+    </array>
+</dict>
+</plist>
+"#).unwrap();
+        let mut expected = BTreeMap::new();
+        expected.insert("name".to_string(), Plist::String("Fish & Chips \u{263A}".to_string()));
+        expected.insert("list".to_string(), Plist::Array(vec![Plist::Integer(-12),
+            Plist::Real(1.5), Plist::Boolean(true), Plist::Boolean(false),
+            Plist::String(String::new()), Plist::String(String::new()),
+            Plist::Dict(BTreeMap::new())]));
+        assert_eq!(Plist::Dict(expected), plist);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse("").is_err());
+        assert!(parse("<plist><dict><string>x</string></dict></plist>").is_err());
+        assert!(parse("<plist><array><integer>x</integer></array></plist>").is_err());
+        assert!(parse("<plist><string>a &bogus; b</string></plist>").is_err());
+        assert!(parse("<plist><array></plist>").is_err());
+        assert!(parse("<plist><true/></plist><true/>").is_err());
+        assert_eq!(Ok(Plist::Boolean(true)), parse("<plist><true/></plist>\n"));
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugins are subprocesses that talk to the core using the same newline-delimited
+//! JSON RPC framing as the front-end. They run asynchronously: the core sends them
+//! the document along with its revision id, and their edits are merged into the
+//! current state by the engine, however stale the revision they were based on.
+
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{ChildStdin, Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::thread;
+use serde_json;
+use serde_json::Value;
+use serde_json::builder::ObjectBuilder;
+
+use ::MainMsg;
+
+/// The core's end of the connection to a running plugin.
+pub struct PluginPeer {
+    path: String,
+    stdin: ChildStdin,
+}
+
+impl PluginPeer {
+    /// Spawn the plugin executable at `path`. Messages from the plugin are delivered
+    /// to the main loop through `tx`, tagged with `buffer_id`. The plugin is expected
+    /// to exit when its stdin is closed, which happens when the `PluginPeer` is dropped.
+    pub fn start(path: &str, buffer_id: usize, tx: Sender<MainMsg>) -> io::Result<PluginPeer> {
+        let mut child = try!(Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn());
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let name = path.to_string();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => match serde_json::from_str::<Value>(&line) {
+                        Ok(data) => {
+                            if tx.send(MainMsg::PluginRpc(buffer_id, data)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => print_err!("plugin {}: invalid json {}", name, e),
+                    },
+                    Err(e) => {
+                        print_err!("plugin {}: read error {}", name, e);
+                        break;
+                    }
+                }
+            }
+            if let Err(e) = child.wait() {
+                print_err!("plugin {}: error waiting for exit {}", name, e);
+            }
+        });
+        Ok(PluginPeer {
+            path: path.to_string(),
+            stdin: stdin,
+        })
+    }
+
+    /// Send an asynchronous RPC to the plugin.
+    pub fn send_rpc(&mut self, method: &str, params: &Value) {
+        let mut s = serde_json::to_string(&ObjectBuilder::new()
+            .insert("method", method)
+            .insert("params", params)
+            .unwrap()
+        ).unwrap();
+        s.push('\n');
+        if let Err(e) = self.stdin.write_all(s.as_bytes()) {
+            print_err!("plugin {}: send error {}", self.path, e);
+        }
+    }
+}
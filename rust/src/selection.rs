@@ -0,0 +1,234 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Selections, consisting of any number of carets and selected regions.
+
+use std::cmp::{min, max};
+use std::ops::Deref;
+
+use xi_rope::rope::RopeInfo;
+use xi_rope::delta::Delta;
+
+/// Where a caret at a soft line break is drawn: at the end of the line before
+/// the break (upstream), or at the start of the line after it (downstream).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Affinity {
+    Downstream,
+    Upstream,
+}
+
+impl Default for Affinity {
+    fn default() -> Affinity {
+        Affinity::Downstream
+    }
+}
+
+/// A single selected region, or a caret if it's empty.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SelRegion {
+    /// The anchor, which stays put when the selection is extended.
+    pub start: usize,
+    /// The active end, where the caret is drawn.
+    pub end: usize,
+    /// The column to aim for in vertical motion, if it differs from the
+    /// column of `end` (because earlier motion passed through shorter lines).
+    pub horiz: Option<usize>,
+    pub affinity: Affinity,
+}
+
+impl SelRegion {
+    /// A region selecting from `start` to `end`.
+    pub fn new(start: usize, end: usize) -> SelRegion {
+        SelRegion {
+            start: start,
+            end: end,
+            horiz: None,
+            affinity: Affinity::default(),
+        }
+    }
+
+    /// A caret at `offset`.
+    pub fn caret(offset: usize) -> SelRegion {
+        SelRegion::new(offset, offset)
+    }
+
+    pub fn min(&self) -> usize {
+        min(self.start, self.end)
+    }
+
+    pub fn max(&self) -> usize {
+        max(self.start, self.end)
+    }
+
+    pub fn is_caret(&self) -> bool {
+        self.start == self.end
+    }
+
+    // Regions merge if they overlap, or if they touch and one of them is a caret.
+    fn should_merge(&self, other: &SelRegion) -> bool {
+        (other.min() < self.max() && self.min() < other.max()) ||
+            ((self.is_caret() || other.is_caret()) &&
+            other.min() <= self.max() && self.min() <= other.max())
+    }
+
+    // The union of the two regions. If one contains the other, it's kept as it
+    // is; otherwise the union has the direction of `self`.
+    fn merge_with(&self, other: &SelRegion) -> SelRegion {
+        let lo = min(self.min(), other.min());
+        let hi = max(self.max(), other.max());
+        if (lo, hi) == (self.min(), self.max()) {
+            *self
+        } else if (lo, hi) == (other.min(), other.max()) {
+            *other
+        } else if self.end < self.start {
+            SelRegion::new(hi, lo)
+        } else {
+            SelRegion::new(lo, hi)
+        }
+    }
+}
+
+/// A set of regions, sorted by position and non-overlapping. A selection in a
+/// view is never empty.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Selection {
+    regions: Vec<SelRegion>,
+}
+
+impl Selection {
+    pub fn new() -> Selection {
+        Selection::default()
+    }
+
+    /// A selection consisting of a single region.
+    pub fn new_simple(region: SelRegion) -> Selection {
+        Selection { regions: vec![region] }
+    }
+
+    /// Add a region, merging it with any regions it overlaps.
+    pub fn add_region(&mut self, region: SelRegion) {
+        let mut ix = self.search(region.min());
+        // skip a region that only touches this one from before
+        if ix < self.regions.len() && self.regions[ix].max() == region.min() &&
+                !region.should_merge(&self.regions[ix]) {
+            ix += 1;
+        }
+        let mut region = region;
+        let mut end_ix = ix;
+        while end_ix < self.regions.len() && region.should_merge(&self.regions[end_ix]) {
+            region = region.merge_with(&self.regions[end_ix]);
+            end_ix += 1;
+        }
+        self.regions.drain(ix..end_ix);
+        self.regions.insert(ix, region);
+    }
+
+    /// The regions that intersect or touch the range `start..end`.
+    pub fn regions_in_range(&self, start: usize, end: usize) -> &[SelRegion] {
+        let first = self.search(start);
+        let mut last = first;
+        while last < self.regions.len() && self.regions[last].min() <= end {
+            last += 1;
+        }
+        &self.regions[first..last]
+    }
+
+    /// The selection after an edit: each region keeps selecting the same text,
+    /// with carets staying before text inserted at their position.
+    pub fn apply_delta(&self, delta: &Delta<RopeInfo>) -> Selection {
+        let mut result = Selection::new();
+        for region in &self.regions {
+            result.add_region(SelRegion {
+                start: delta.transform_offset(region.start, false),
+                end: delta.transform_offset(region.end, false),
+                ..*region
+            });
+        }
+        result
+    }
+
+    // The index of the first region that ends at or after `offset`.
+    fn search(&self, offset: usize) -> usize {
+        match self.regions.binary_search_by(|r| r.max().cmp(&offset)) {
+            Ok(mut ix) => {
+                while ix > 0 && self.regions[ix - 1].max() == offset {
+                    ix -= 1;
+                }
+                ix
+            }
+            Err(ix) => ix,
+        }
+    }
+}
+
+impl Deref for Selection {
+    type Target = [SelRegion];
+
+    fn deref(&self) -> &[SelRegion] {
+        &self.regions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Selection, SelRegion};
+    use xi_rope::rope::Rope;
+    use xi_rope::delta::Delta;
+    use xi_rope::interval::Interval;
+
+    fn ranges(sel: &Selection) -> Vec<(usize, usize)> {
+        sel.iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn add_region() {
+        let mut sel = Selection::new();
+        sel.add_region(SelRegion::new(10, 12));
+        sel.add_region(SelRegion::caret(3));
+        sel.add_region(SelRegion::new(7, 5));
+        assert_eq!(vec![(3, 3), (7, 5), (10, 12)], ranges(&sel));
+        // overlapping regions merge, keeping the direction of the new one
+        sel.add_region(SelRegion::new(6, 11));
+        assert_eq!(vec![(3, 3), (5, 12)], ranges(&sel));
+        // a caret touching a region merges with it
+        sel.add_region(SelRegion::caret(12));
+        assert_eq!(vec![(3, 3), (5, 12)], ranges(&sel));
+        // but touching regions are kept separate
+        sel.add_region(SelRegion::new(12, 14));
+        assert_eq!(vec![(3, 3), (5, 12), (12, 14)], ranges(&sel));
+    }
+
+    #[test]
+    fn regions_in_range() {
+        let mut sel = Selection::new();
+        for &(start, end) in &[(0, 2), (4, 4), (6, 9), (12, 15)] {
+            sel.add_region(SelRegion::new(start, end));
+        }
+        assert_eq!(vec![(4, 4), (6, 9)], ranges(&Selection { regions:
+            sel.regions_in_range(3, 6).to_vec() }));
+        assert_eq!(vec![(6, 9)], ranges(&Selection { regions:
+            sel.regions_in_range(7, 8).to_vec() }));
+        assert!(sel.regions_in_range(16, 20).is_empty());
+    }
+
+    #[test]
+    fn apply_delta() {
+        let mut sel = Selection::new();
+        sel.add_region(SelRegion::caret(2));
+        sel.add_region(SelRegion::new(5, 8));
+        let delta = Delta::simple_edit(Interval::new_closed_open(2, 6), Rope::from("xy"), 10);
+        // the caret stays before the insertion, the region loses its deleted part
+        assert_eq!(vec![(2, 2), (4, 6)], ranges(&sel.apply_delta(&delta)));
+    }
+}
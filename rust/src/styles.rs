@@ -0,0 +1,235 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Styles of text, and the table of them shared with the front-end. Each
+//! style is defined once, with a `def_style` message, and lines refer to it
+//! by id.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde_json::builder::ObjectBuilder;
+
+use xi_rope::interval::Interval;
+
+use theme::Theme;
+
+/// The background of selected text.
+pub const SELECTION_BG: u32 = 0xffb3d9fc;
+/// The background of matches of the search.
+pub const FIND_BG: u32 = 0xfffff099;
+
+/// How a run of text is drawn. Anything that isn't set is left as the
+/// front-end's default, or to the style underneath when styles are layered.
+#[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Style {
+    pub fg: Option<u32>,  // ARGB
+    pub bg: Option<u32>,  // likewise
+    pub weight: Option<u16>,  // as in CSS, 400 being normal and 700 bold
+    pub italic: Option<bool>,
+    pub underline: Option<bool>,
+}
+
+impl Style {
+    pub fn fg(fg: u32) -> Style {
+        Style { fg: Some(fg), ..Style::default() }
+    }
+
+    pub fn bg(bg: u32) -> Style {
+        Style { bg: Some(bg), ..Style::default() }
+    }
+
+    /// This style layered over `other`, taking anything this one doesn't set
+    /// from it.
+    pub fn over(&self, other: &Style) -> Style {
+        Style {
+            fg: self.fg.or(other.fg),
+            bg: self.bg.or(other.bg),
+            weight: self.weight.or(other.weight),
+            italic: self.italic.or(other.italic),
+            underline: self.underline.or(other.underline),
+        }
+    }
+
+    fn insert_fields(&self, mut builder: ObjectBuilder) -> ObjectBuilder {
+        if let Some(fg) = self.fg {
+            builder = builder.insert("fg", fg);
+        }
+        if let Some(bg) = self.bg {
+            builder = builder.insert("bg", bg);
+        }
+        if let Some(weight) = self.weight {
+            builder = builder.insert("weight", weight);
+        }
+        if let Some(italic) = self.italic {
+            builder = builder.insert("italic", italic);
+        }
+        if let Some(underline) = self.underline {
+            builder = builder.insert("underline", underline);
+        }
+        builder
+    }
+}
+
+/// The styles that have been given ids, which are shared by all the tabs,
+/// and the theme that decides the styles of highlighted text. Ids are never
+/// reused, so the front-end can keep its table for as long as it's connected.
+#[derive(Default)]
+pub struct StyleMap {
+    styles: Vec<Style>,  // indexed by id
+    ids: HashMap<Style, usize>,
+    n_defined: usize,  // the styles before this have been sent to the front-end
+    theme: Theme,
+    scope_styles: HashMap<String, Style>,  // the theme's styles, by space-separated scopes
+}
+
+impl StyleMap {
+    pub fn new() -> StyleMap {
+        StyleMap::default()
+    }
+
+    /// The id of `style`, giving it one if it doesn't have one yet.
+    pub fn add(&mut self, style: &Style) -> usize {
+        if let Some(&id) = self.ids.get(style) {
+            return id;
+        }
+        let id = self.styles.len();
+        self.styles.push(style.clone());
+        self.ids.insert(style.clone(), id);
+        id
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// Change the theme. Text that has been sent keeps its old styles until
+    /// it's sent again.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        self.scope_styles.clear();
+    }
+
+    /// The style the theme gives text with the stack of scopes `scopes`.
+    pub fn scope_style(&mut self, scopes: &[&str]) -> Style {
+        let theme = &self.theme;
+        self.scope_styles.entry(scopes.join(" "))
+            .or_insert_with(|| theme.style(scopes))
+            .clone()
+    }
+
+    /// The params of a `def_style` message for each style given an id since
+    /// the last call, to be sent before anything that refers to them.
+    pub fn take_definitions(&mut self) -> Vec<ObjectBuilder> {
+        let defs = self.styles[self.n_defined..].iter().enumerate()
+            .map(|(i, style)| style.insert_fields(ObjectBuilder::new()
+                .insert("id", self.n_defined + i)))
+            .collect();
+        self.n_defined = self.styles.len();
+        defs
+    }
+}
+
+/// Flatten layers of styled runs, which may overlap, into runs that don't,
+/// in order. Where runs overlap, later ones are layered over earlier ones,
+/// so `runs` should go from the bottom layer to the top. Adjacent runs that
+/// come out the same are joined, and text with no style is left out. This
+/// sorts the starts and ends of the runs, so it takes O(n log n) time for n
+/// runs, besides layering the styles over each piece of text.
+pub fn merge_layers(runs: &[(Interval, Style)]) -> Vec<(Interval, Style)> {
+    // Sweep along the text, through the starts and ends of the runs in order,
+    // keeping the layers of the ones over the text since the last of them.
+    let mut events = Vec::with_capacity(runs.len() * 2);
+    for (layer, &(iv, _)) in runs.iter().enumerate() {
+        if !iv.is_empty() {
+            events.push((iv.start(), true, layer));
+            events.push((iv.end(), false, layer));
+        }
+    }
+    events.sort_by_key(|&(offset, _, _)| offset);
+    let mut active: BTreeSet<usize> = BTreeSet::new();
+    let mut result: Vec<(Interval, Style)> = Vec::new();
+    let mut start = 0;
+    for (end, is_start, layer) in events {
+        if start < end && !active.is_empty() {
+            let style = active.iter()
+                .fold(Style::default(), |below, &layer| runs[layer].1.over(&below));
+            let joined = match result.last_mut() {
+                Some(last) if last.0.end() == start && last.1 == style => {
+                    last.0 = Interval::new_closed_open(last.0.start(), end);
+                    true
+                }
+                _ => false,
+            };
+            if !joined && style != Style::default() {
+                result.push((Interval::new_closed_open(start, end), style));
+            }
+        }
+        start = end;
+        if is_start {
+            active.insert(layer);
+        } else {
+            active.remove(&layer);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use xi_rope::interval::Interval;
+    use super::{merge_layers, Style, StyleMap};
+
+    fn runs(runs: &[(usize, usize, Style)]) -> Vec<(Interval, Style)> {
+        runs.iter().map(|&(start, end, ref style)|
+            (Interval::new_closed_open(start, end), style.clone())).collect()
+    }
+
+    #[test]
+    fn merge() {
+        let bold = Style { weight: Some(700), ..Style::default() };
+        let merged = merge_layers(&runs(&[
+            (0, 4, Style::fg(1)), (6, 8, Style::fg(2)), (2, 7, Style::bg(3)),
+            (8, 10, Style::fg(2)), (3, 5, Style::fg(4)), (12, 14, bold.clone()),
+        ]));
+        assert_eq!(runs(&[
+            (0, 2, Style::fg(1)),
+            (2, 3, Style { fg: Some(1), bg: Some(3), ..Style::default() }),
+            (3, 5, Style { fg: Some(4), bg: Some(3), ..Style::default() }),
+            (5, 6, Style::bg(3)),
+            (6, 7, Style { fg: Some(2), bg: Some(3), ..Style::default() }),
+            (7, 10, Style::fg(2)),
+            (12, 14, bold),
+        ]), merged);
+        assert_eq!(Vec::<(Interval, Style)>::new(), merge_layers(&[]));
+    }
+
+    #[test]
+    fn style_map() {
+        let mut styles = StyleMap::new();
+        assert_eq!(0, styles.add(&Style::fg(0xffc00000)));
+        assert_eq!(1, styles.add(&Style { bg: Some(1), italic: Some(true), ..Style::default() }));
+        assert_eq!(0, styles.add(&Style::fg(0xffc00000)));
+        let defs = styles.take_definitions().into_iter()
+            .map(|def| serde_json::to_string(&def.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![r#"{"fg":4290772992,"id":0}"#, r#"{"bg":1,"id":1,"italic":true}"#],
+            defs);
+        // each style is defined once
+        styles.add(&Style::bg(1));
+        assert_eq!(1, styles.take_definitions().len());
+        styles.add(&Style::fg(0xffc00000));
+        assert!(styles.take_definitions().is_empty());
+    }
+}
@@ -13,29 +13,51 @@
 // limitations under the License.
 
 //! A container for all the tabs being edited. Also functions as main dispatch for RPC.
+//!
+//! Each tab shows a view onto a buffer, and several tabs may show the same buffer.
 
+use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::sync::Mutex;
+use std::sync::mpsc::Sender;
 use serde_json::Value;
 use serde_json::builder::ObjectBuilder;
 use serde::ser::Serialize;
 
 use xi_rope::rope::Rope;
 use editor::Editor;
+use columns::ColumnUnit;
+use styles::StyleMap;
+use theme;
 use ::send;
+use ::MainMsg;
 
 pub struct Tabs {
-    tabs: BTreeMap<String, Editor>,
+    buffers: BTreeMap<usize, Editor>,
+    tabs: BTreeMap<String, usize>,  // the id of the buffer shown in each tab
     id_counter: usize,
+    buffer_id_counter: usize,
     kill_ring: Mutex<Rope>,
+    plugin_tx: Sender<MainMsg>,  // handed to plugins so they can reach the main loop
+    column_unit: ColumnUnit,  // negotiated with the front-end
+    styles: Rc<RefCell<StyleMap>>,  // defined to the front-end as they're used
+    themes_dir: PathBuf,
 }
 
 impl Tabs {
-    pub fn new() -> Tabs {
+    pub fn new(plugin_tx: Sender<MainMsg>, themes_dir: PathBuf) -> Tabs {
         Tabs {
+            buffers: BTreeMap::new(),
             tabs: BTreeMap::new(),
             id_counter: 0,
+            buffer_id_counter: 0,
             kill_ring: Mutex::new(Rope::from("")),
+            plugin_tx: plugin_tx,
+            column_unit: ColumnUnit::default(),
+            styles: Rc::new(RefCell::new(StyleMap::new())),
+            themes_dir: themes_dir,
         }
     }
 
@@ -43,12 +65,30 @@ impl Tabs {
     pub fn handle_rpc(&mut self, method: &str, params: &Value, id: Option<&Value>) {
         match method {
             "new_tab" => self.do_new_tab(id),
+            "new_view" => self.do_new_view(params, id),
             "delete_tab" => self.do_delete_tab(params),
             "edit" => self.do_edit(params, id),
+            "negotiate_column_unit" => self.do_negotiate_column_unit(params, id),
+            "available_themes" => self.do_available_themes(id),
+            "set_theme" => self.do_set_theme(params, id),
             _ => print_err!("unknown method {}", method),
         }
     }
 
+    pub fn handle_plugin_rpc(&mut self, buffer_id: usize, method: &str, params: &Value) {
+        // the buffer may have been closed while the plugin was working
+        if let Some(editor) = self.buffers.get_mut(&buffer_id) {
+            editor.do_plugin_rpc(method, params);
+        }
+    }
+
+    /// Reload, or warn about, files changed by something else.
+    pub fn check_files(&mut self) {
+        for editor in self.buffers.values_mut() {
+            editor.check_file();
+        }
+    }
+
     pub fn respond<V>(&self, result: V, id: Option<&Value>)
             where V: Serialize {
         if let Some(id) = id {
@@ -69,6 +109,19 @@ impl Tabs {
         self.respond(&tabname, id);
     }
 
+    fn do_new_view(&mut self, params: &Value, id: Option<&Value>) {
+        if let Some(tab) = params.as_object()
+                .and_then(|v| v.get("tab")).and_then(|v| v.as_string()) {
+            match self.new_view(tab) {
+                Some(tabname) => self.respond(&tabname, id),
+                None => {
+                    print_err!("tab not found: {}", tab);
+                    self.respond(Value::Null, id);
+                }
+            }
+        }
+    }
+
     fn do_delete_tab(&mut self, params: &Value) {
         if let Some(params) = params.as_object() {
             let tab = params.get("tab").unwrap().as_string().unwrap();
@@ -76,14 +129,53 @@ impl Tabs {
         }
     }
 
+    // Use the first of the units the front-end lists that we support, keeping
+    // the current one if there is none, and respond with the one in effect.
+    fn do_negotiate_column_unit(&mut self, params: &Value, id: Option<&Value>) {
+        let unit = params.as_object()
+            .and_then(|v| v.get("units")).and_then(|v| v.as_array())
+            .and_then(|units| units.iter()
+                .filter_map(|unit| unit.as_string().and_then(ColumnUnit::from_name))
+                .next());
+        if let Some(unit) = unit {
+            self.column_unit = unit;
+            for editor in self.buffers.values_mut() {
+                editor.set_column_unit(unit);
+            }
+        }
+        let name = self.column_unit.name();
+        self.respond(name, id);
+    }
+
+    fn do_available_themes(&mut self, id: Option<&Value>) {
+        let names = theme::available_themes(&self.themes_dir);
+        self.respond(names, id);
+    }
+
+    fn do_set_theme(&mut self, params: &Value, id: Option<&Value>) {
+        let result = match params.as_object()
+                .and_then(|v| v.get("name")).and_then(|v| v.as_string()) {
+            Some(name) => theme::load_theme(&self.themes_dir, name),
+            None => Err("no theme name given".to_string()),
+        };
+        let result = result.map(|theme| {
+            self.styles.borrow_mut().set_theme(theme);
+            for editor in self.buffers.values_mut() {
+                editor.restyle();
+            }
+        });
+        self.respond(ok_response(result), id);
+    }
+
     fn do_edit(&mut self, params: &Value, id: Option<&Value>) {
         if let Some(params) = params.as_object() {
             let tab = params.get("tab").unwrap().as_string().unwrap();
             let response = {
-                if let Some(editor) = self.tabs.get_mut(tab) {
+                let buffers = &mut self.buffers;
+                if let Some(editor) = self.tabs.get(tab).and_then(|id| buffers.get_mut(id)) {
                     let method = params.get("method").unwrap().as_string().unwrap();
                     let params = params.get("params").unwrap();
-                    editor.do_rpc(method, params, &self.kill_ring)
+                    editor.do_rpc(tab, method, params, &self.kill_ring)
                 } else {
                     print_err!("tab not found: {}", tab);
                     None
@@ -97,16 +189,46 @@ impl Tabs {
         }
     }
 
-    fn new_tab(&mut self) -> String {
+    fn next_tabname(&mut self) -> String {
         let tabname = self.id_counter.to_string();
         self.id_counter += 1;
-        let editor = Editor::new(&tabname);
-        self.tabs.insert(tabname.clone(), editor);
         tabname
     }
 
+    // Create a tab showing a new, empty buffer.
+    fn new_tab(&mut self) -> String {
+        let tabname = self.next_tabname();
+        let buffer_id = self.buffer_id_counter;
+        self.buffer_id_counter += 1;
+        let mut editor = Editor::new(buffer_id, &tabname, self.plugin_tx.clone(),
+            self.styles.clone());
+        editor.set_column_unit(self.column_unit);
+        self.buffers.insert(buffer_id, editor);
+        self.tabs.insert(tabname.clone(), buffer_id);
+        tabname
+    }
+
+    // Create a tab showing a new view onto the buffer shown in `tab`.
+    fn new_view(&mut self, tab: &str) -> Option<String> {
+        let buffer_id = match self.tabs.get(tab) {
+            Some(&buffer_id) => buffer_id,
+            None => return None,
+        };
+        let tabname = self.next_tabname();
+        self.buffers.get_mut(&buffer_id).unwrap().add_view(&tabname);
+        self.tabs.insert(tabname.clone(), buffer_id);
+        Some(tabname)
+    }
+
+    // Delete a tab, and its buffer if no other tab shows it.
     fn delete_tab(&mut self, tabname: &str) {
-        self.tabs.remove(tabname);
+        if let Some(buffer_id) = self.tabs.remove(tabname) {
+            if self.tabs.values().any(|&id| id == buffer_id) {
+                self.buffers.get_mut(&buffer_id).unwrap().remove_view(tabname);
+            } else {
+                self.buffers.remove(&buffer_id);
+            }
+        }
     }
 }
 
@@ -122,3 +244,43 @@ pub fn update_tab(update: &Value, tab: &str) {
         print_err!("send error on update_tab: {}", e);
     }
 }
+
+/// Send a `def_style` for each style that has been given an id since the
+/// last call, before anything that uses them.
+pub fn def_styles(styles: &mut StyleMap) {
+    for def in styles.take_definitions() {
+        if let Err(e) = send(&ObjectBuilder::new()
+            .insert("method", "def_style")
+            .insert("params", def.unwrap())
+            .unwrap()
+        ) {
+            print_err!("send error on def_style: {}", e);
+        }
+    }
+}
+
+/// The response to a request that can fail, such as `save`: `{"ok": true}`,
+/// or `ok` false with an `error` message meant for the user.
+pub fn ok_response(result: Result<(), String>) -> Value {
+    match result {
+        Ok(()) => ObjectBuilder::new().insert("ok", true).unwrap(),
+        Err(msg) => {
+            print_err!("{}", msg);
+            ObjectBuilder::new().insert("ok", false).insert("error", msg).unwrap()
+        }
+    }
+}
+
+/// Tell the front-end about a problem with a request made in the tab `tab`,
+/// such as a file that couldn't be opened.
+pub fn alert(tab: &str, msg: &str) {
+    if let Err(e) = send(&ObjectBuilder::new()
+        .insert("method", "alert")
+        .insert_object("params", |builder|
+            builder.insert("tab", tab)
+                .insert("msg", msg))
+        .unwrap()
+    ) {
+        print_err!("send error on alert: {}", e);
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Themes, which decide how syntax highlighting looks. They're read from
+//! TextMate (or Sublime Text) `.tmTheme` files, whose rules give styles to
+//! the text matching scope selectors such as `comment` or
+//! `source.json string - string.unquoted`.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use plist;
+use plist::Plist;
+use styles::Style;
+
+/// The name of the theme built into the editor, which it starts with.
+pub const DEFAULT_THEME: &str = "default";
+
+const DEFAULT_THEME_PLIST: &str = include_str!("../themes/default.tmTheme");
+
+const THEME_EXTENSION: &str = "tmTheme";
+
+pub struct Theme {
+    rules: Vec<Rule>,  // in the order they're in the file
+    pub selection: Option<u32>,  // the background of the selection
+    pub find_highlight: Option<u32>,  // the background of search matches
+}
+
+struct Rule {
+    selectors: Vec<Selector>,  // the rule applies where any of these match
+    style: Style,
+}
+
+// Matches a stack of scopes, outermost first, containing `path` (in order,
+// but not necessarily next to each other) but none of the `excluded` paths.
+struct Selector {
+    path: Vec<String>,
+    excluded: Vec<Vec<String>>,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::from_plist(&plist::parse(DEFAULT_THEME_PLIST).unwrap()).unwrap()
+    }
+}
+
+impl Theme {
+    /// Read a theme from the contents of a `.tmTheme` file.
+    pub fn from_plist(plist: &Plist) -> Result<Theme, String> {
+        let items = try!(plist.as_dict().and_then(|dict| dict.get("settings"))
+            .and_then(Plist::as_array)
+            .ok_or_else(|| "theme has no settings".to_string()));
+        let mut theme = Theme { rules: Vec::new(), selection: None, find_highlight: None };
+        for item in items {
+            let item = try!(item.as_dict().ok_or_else(|| "theme setting isn't a dict".to_string()));
+            let settings = match item.get("settings").and_then(Plist::as_dict) {
+                Some(settings) => settings,
+                None => continue,
+            };
+            let color = |key: &str| settings.get(key).and_then(Plist::as_str).and_then(parse_color);
+            match item.get("scope").and_then(Plist::as_str) {
+                // the settings without a scope apply to the editor as a whole
+                None => {
+                    theme.selection = color("selection");
+                    theme.find_highlight = color("findHighlight");
+                }
+                Some(scope) => {
+                    let mut style = Style { fg: color("foreground"), bg: color("background"),
+                        ..Style::default() };
+                    if let Some(font_style) = settings.get("fontStyle").and_then(Plist::as_str) {
+                        let has = |word| font_style.split_whitespace().any(|w| w == word);
+                        style.weight = Some(if has("bold") { 700 } else { 400 });
+                        style.italic = Some(has("italic"));
+                        style.underline = Some(has("underline"));
+                    }
+                    theme.rules.push(Rule { selectors: parse_selectors(scope), style: style });
+                }
+            }
+        }
+        Ok(theme)
+    }
+
+    /// The style of text with the stack of scopes `scopes`, outermost first,
+    /// such as `["source.rust", "comment.line"]`. Where several rules match,
+    /// each sets what it has to over the rules that match less closely: those
+    /// matching deeper scopes, and then more of a scope's name, win, with
+    /// later rules winning ties.
+    pub fn style(&self, scopes: &[&str]) -> Style {
+        let mut matches = self.rules.iter().enumerate()
+            .filter_map(|(i, rule)| rule.selectors.iter()
+                .filter_map(|selector| selector.score(scopes))
+                .max()
+                .map(|score| (score, i)))
+            .collect::<Vec<_>>();
+        matches.sort();
+        matches.iter().fold(Style::default(), |below, &(_, i)| self.rules[i].style.over(&below))
+    }
+}
+
+impl Selector {
+    // How closely the selector matches `scopes`, if it does. Each scope in
+    // the path counts for the number of parts of its name, weighted by how
+    // deep in the stack it matches.
+    fn score(&self, scopes: &[&str]) -> Option<u64> {
+        if self.excluded.iter().any(|path| path_score(path, scopes).is_some()) {
+            return None;
+        }
+        path_score(&self.path, scopes)
+    }
+}
+
+fn path_score(path: &[String], scopes: &[&str]) -> Option<u64> {
+    let mut score = 0;
+    let mut depth = 0;
+    for prefix in path {
+        while depth < scopes.len() && !has_prefix(scopes[depth], prefix) {
+            depth += 1;
+        }
+        if depth == scopes.len() {
+            return None;
+        }
+        let parts = prefix.split('.').count() as u64;
+        score += parts << (8 * depth);
+        depth += 1;
+    }
+    Some(score)
+}
+
+// Whether the scope `scope` is `prefix` or within it, as `comment.line` is
+// within `comment`.
+fn has_prefix(scope: &str, prefix: &str) -> bool {
+    scope.starts_with(prefix) &&
+        (scope.len() == prefix.len() || scope.as_bytes()[prefix.len()] == b'.')
+}
+
+// Parse a comma-separated list of selectors, each a space-separated path of
+// scopes, optionally followed by `-` and paths to exclude. Grouping and the
+// other operators of TextMate selectors aren't supported.
+fn parse_selectors(s: &str) -> Vec<Selector> {
+    s.split(',').filter_map(|s| {
+        let mut paths = s.split(" -").map(|path|
+            path.split_whitespace().map(str::to_string).collect::<Vec<_>>());
+        let path = paths.next().unwrap();
+        if path.is_empty() {
+            return None;
+        }
+        Some(Selector { path: path, excluded: paths.filter(|path| !path.is_empty()).collect() })
+    }).collect()
+}
+
+// A color written as `#RRGGBB` or `#RRGGBBAA`, as ARGB.
+fn parse_color(s: &str) -> Option<u32> {
+    if !s.starts_with('#') {
+        return None;
+    }
+    let value = match u32::from_str_radix(&s[1..], 16) {
+        Ok(value) => value,
+        Err(_) => return None,
+    };
+    match s.len() {
+        7 => Some(0xff000000 | value),
+        9 => Some(value.rotate_right(8)),
+        _ => None,
+    }
+}
+
+/// The directory themes are loaded from: `$XI_THEMES_DIR`, or `.xi/themes`
+/// in the user's home directory.
+pub fn themes_dir() -> PathBuf {
+    match env::var_os("XI_THEMES_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".xi/themes"),
+    }
+}
+
+/// The names of the themes there are: the default one, and then the
+/// `.tmTheme` files in `dir`, by name without the extension.
+pub fn available_themes(dir: &Path) -> Vec<String> {
+    let mut names = fs::read_dir(dir).into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == THEME_EXTENSION))
+        .filter_map(|path| path.file_stem().and_then(|name| name.to_str()).map(str::to_string))
+        .filter(|name| name != DEFAULT_THEME)
+        .collect::<Vec<_>>();
+    names.sort();
+    names.insert(0, DEFAULT_THEME.to_string());
+    names
+}
+
+/// Load the theme called `name`, which is one of the `available_themes`.
+pub fn load_theme(dir: &Path, name: &str) -> Result<Theme, String> {
+    if name == DEFAULT_THEME {
+        return Ok(Theme::default());
+    }
+    if !available_themes(dir).iter().any(|available| available == name) {
+        return Err(format!("there's no theme called {}", name));
+    }
+    let path = dir.join(name).with_extension(THEME_EXTENSION);
+    let mut s = String::new();
+    try!(File::open(&path).and_then(|mut f| f.read_to_string(&mut s))
+        .map_err(|e| format!("couldn't read theme {}: {}", path.display(), e)));
+    plist::parse(&s).and_then(|plist| Theme::from_plist(&plist))
+        .map_err(|e| format!("couldn't load theme {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use styles::Style;
+    use super::{available_themes, load_theme, parse_color, Theme, DEFAULT_THEME};
+
+    fn fixtures() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/themes")
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(Some(0xff8e908c), parse_color("#8E908C"));
+        assert_eq!(Some(0x80ff0000), parse_color("#ff000080"));
+        assert_eq!(None, parse_color("8e908c"));
+        assert_eq!(None, parse_color("#8e908"));
+        assert_eq!(None, parse_color("#8e908g"));
+    }
+
+    #[test]
+    fn available() {
+        assert_eq!(vec![DEFAULT_THEME, "Test"], available_themes(&fixtures()));
+        assert_eq!(vec![DEFAULT_THEME], available_themes(&fixtures().join("missing")));
+        assert!(load_theme(&fixtures(), "missing").is_err());
+        assert!(load_theme(&fixtures(), "../themes/Test").is_err());
+        assert_eq!(Some(0xff718c00), Theme::default().style(&["source.rust", "string"]).fg);
+    }
+
+    #[test]
+    fn resolve() {
+        let theme = load_theme(&fixtures(), "Test").unwrap();
+        assert_eq!(Some(0xffd6d6d6), theme.selection);
+        assert_eq!(None, theme.find_highlight);
+        let style = |scopes: &[&str]| theme.style(scopes);
+        // the font style sets all of weight, italic and underline
+        assert_eq!(Style { fg: Some(0xff8e908c), weight: Some(400), italic: Some(true),
+            underline: Some(false), ..Style::default() }, style(&["source.rust", "comment.line"]));
+        assert_eq!(Style::default(), style(&["source.rust", "commentary"]));
+        // either of a list of selectors
+        assert_eq!(Style::fg(0xfff5871f), style(&["source.rust", "constant.numeric"]));
+        assert_eq!(Style::fg(0xfff5871f), style(&["source.rust", "constant.language"]));
+        // a rule for more of the name wins, taking what it doesn't set from the other
+        assert_eq!(Style::fg(0xff718c00), style(&["source.rust", "string.quoted"]));
+        assert_eq!(Style { fg: Some(0xff3e999f), bg: Some(0xffeeeeee), ..Style::default() },
+            style(&["source.rust", "string.quoted.double"]));
+        // and one for an outer scope too, all else being equal
+        assert_eq!(Style::fg(0xffc82829), style(&["source.json", "string.quoted"]));
+        assert_eq!(Style { fg: Some(0xff3e999f), bg: Some(0xffeeeeee), ..Style::default() },
+            style(&["source.json", "string.quoted.double"]));
+        // exclusions
+        assert_eq!(Style::fg(0xff8959a8), style(&["source.rust", "keyword.control"]));
+        assert_eq!(Style::default(), style(&["source.rust", "keyword.operator"]));
+    }
+}
@@ -18,36 +18,69 @@ use serde_json::Value;
 use serde_json::builder::{ArrayBuilder,ObjectBuilder};
 
 use xi_rope::rope::{Rope, LinesMetric, RopeInfo};
-use xi_rope::delta::{OldDelta};
+use xi_rope::delta::Delta;
 use xi_rope::tree::Cursor;
 use xi_rope::breaks::{Breaks, BreaksMetric, BreaksBaseMetric};
 use xi_rope::interval::Interval;
 use xi_rope::spans::{Spans, SpansBuilder};
+use xi_unicode::str_width;
 
 use linewrap;
+use linewrap::TAB_WIDTH;
+use linecache::{LineCacheShadow, LineOp};
+use columns::ColumnUnit;
+use find::{Query, Search};
+use highlight;
+use highlight::{Highlight, Syntax};
+use selection::{Affinity, Selection, SelRegion};
+use styles::{merge_layers, Style, StyleMap, FIND_BG, SELECTION_BG};
 
 const SCROLL_SLOP: usize = 2;
 
+/// The state of one view onto a buffer: the selection, scroll position and
+/// wrapping, and what has been sent to the front-end.
 pub struct View {
-    pub sel_start: usize,
-    pub sel_end: usize,
+    pub selection: Selection,
+    pub drag_state: Option<(Selection, usize)>,  // selection and anchor at the start of a drag
+    pub scroll_to: Option<usize>,  // offset to bring into view in the next update
+    pub dirty: bool,  // whether an update needs to be sent
     first_line: usize,  // vertical scroll position
     height: usize,  // height of visible portion
     breaks: Option<Breaks>,
     fg_spans: Spans<u32>,
+    syntax: Option<Syntax>,
+    highlighter: Option<Box<dyn Highlight>>,  // for `syntax`, coloring the text
+    search: Option<Search>,  // the search whose matches are highlighted
     cols: usize,
+    column_unit: ColumnUnit,  // how columns are counted in annotations and clicks
+    line_cache: LineCacheShadow,  // what the front-end has of the rendered lines
+    sel_lines: Vec<(usize, usize)>,  // line ranges containing the selection when last rendered
+    find_status: Option<(usize, Option<usize>)>,  // match count and index last sent
+    pending_edit: Option<(usize, usize, Option<usize>)>,  // see before_edit
 }
 
 impl Default for View {
     fn default() -> View {
+        let mut line_cache = LineCacheShadow::new();
+        line_cache.reset(1);  // the empty text has one line
         View {
-            sel_start: 0,
-            sel_end: 0,
+            selection: Selection::new_simple(SelRegion::caret(0)),
+            drag_state: None,
+            scroll_to: Some(0),
+            dirty: false,
             first_line: 0,
             height: 10,
             breaks: None,
             fg_spans: Spans::default(),
+            syntax: None,
+            highlighter: None,
+            search: None,
             cols: 0,
+            column_unit: ColumnUnit::default(),
+            line_cache: line_cache,
+            sel_lines: Vec::new(),
+            find_status: None,
+            pending_edit: None,
         }
     }
 }
@@ -66,16 +99,61 @@ impl View {
         self.height
     }
 
-    pub fn sel_min(&self) -> usize {
-        min(self.sel_start, self.sel_end)
+    /// Change the units of the columns sent to and received from the front-end.
+    /// Lines it already has are resent, as their annotations change.
+    pub fn set_column_unit(&mut self, text: &Rope, unit: ColumnUnit) {
+        if unit != self.column_unit {
+            self.column_unit = unit;
+            self.reset_line_cache(text);
+            self.dirty = true;
+        }
+    }
+
+    pub fn search(&self) -> Option<&Search> {
+        self.search.as_ref()
+    }
+
+    pub fn syntax(&self) -> Option<Syntax> {
+        self.syntax
+    }
+
+    /// Color the text as `syntax`, or not at all. The lines the front-end has
+    /// are resent if that changes anything.
+    pub fn set_syntax(&mut self, text: &Rope, syntax: Option<Syntax>) {
+        if syntax != self.syntax {
+            self.syntax = syntax;
+            self.highlighter = syntax.map(|syntax| syntax.highlighter(text));
+            self.restyle();
+        }
+    }
+
+    /// Resend the lines the front-end has with the next update, as their
+    /// styles have changed.
+    pub fn restyle(&mut self) {
+        let height = self.line_cache.n_lines();
+        self.line_cache.invalidate(0, height);
+        self.dirty = true;
     }
 
-    pub fn sel_max(&self) -> usize {
-        max(self.sel_start, self.sel_end)
+    /// Start a search, highlighting its matches, or clear it. The lines the
+    /// front-end has are resent with the new highlights.
+    pub fn set_query(&mut self, text: &Rope, query: Option<Query>) {
+        self.search = query.map(|query| Search::new(query, text));
+        self.restyle();
+    }
+
+    /// Replace the selection, scrolling to keep the caret of its last region visible.
+    pub fn set_selection(&mut self, text: &Rope, selection: Selection) {
+        self.selection = selection;
+        self.scroll_to_cursor(text);
+        self.dirty = true;
     }
 
     pub fn scroll_to_cursor(&mut self, text: &Rope) {
-        let (line, _) = self.offset_to_line_col(text, self.sel_end);
+        let line = match self.selection.last() {
+            Some(region) => self.caret_line(text, region),
+            None => return,
+        };
         if line < self.first_line {
             self.first_line = line;
         } else if self.first_line + self.height <= line {
@@ -83,19 +161,11 @@ impl View {
         }
     }
 
-    pub fn render_lines(&self, text: &Rope, first_line: usize, last_line: usize) -> Value {
+    /// Render lines `first_line..last_line`, giving ids in `styles` to the
+    /// styles they use.
+    pub fn render_lines(&self, text: &Rope, styles: &mut StyleMap, first_line: usize,
+            last_line: usize) -> Value {
         let mut builder = ArrayBuilder::new();
-        let (cursor_line, cursor_col) = self.offset_to_line_col(text, self.sel_end);
-        let sel_min_line = if self.sel_start == self.sel_end {
-            cursor_line
-        } else {
-            self.line_of_offset(text, self.sel_min())
-        };
-        let sel_max_line = if self.sel_start == self.sel_end {
-            cursor_line
-        } else {
-            self.line_of_offset(text, self.sel_max())
-        };
         let first_line_offset = self.offset_of_line(text, first_line);
         let mut cursor = Cursor::new(text, first_line_offset);
         let mut breaks_cursor = self.breaks.as_ref().map(|breaks|
@@ -124,34 +194,26 @@ impl View {
                 }
             };
             let l_str = text.slice_to_string(start_pos, pos);
-            let l = &l_str;
             // TODO: strip trailing line end
-            let l_len = l.len();
-            line_builder = line_builder.push(l);
-            line_builder = self.render_spans(line_builder, start_pos, pos);
-            if line_num >= sel_min_line && line_num <= sel_max_line && self.sel_start != self.sel_end {
-                let sel_start_ix = if line_num == sel_min_line {
-                    self.sel_min() - self.offset_of_line(text, line_num)
-                } else {
-                    0
-                };
-                let sel_end_ix = if line_num == sel_max_line {
-                    self.sel_max() - self.offset_of_line(text, line_num)
-                } else {
-                    l_len
-                };
+            line_builder = line_builder.push(&l_str);
+            let col = |offset| self.column_unit.col_of_offset(text, start_pos, offset);
+            for (iv, style) in self.render_styles(styles, start_pos, pos) {
+                let id = styles.add(&style);
                 line_builder = line_builder.push_array(|builder|
-                    builder.push("sel")
-                        .push(sel_start_ix)
-                        .push(sel_end_ix)
-                );                
+                    builder.push("style")
+                        .push(col(start_pos + iv.start()))
+                        .push(col(start_pos + iv.end()))
+                        .push(id));
             }
-            if line_num == cursor_line {
-                line_builder = line_builder.push_array(|builder|
-                    builder.push("cursor")
-                        .push(cursor_col)
-                );
-            }            builder = builder.push(line_builder.unwrap());
+            for region in self.selection.regions_in_range(start_pos, pos) {
+                if self.caret_line(text, region) == line_num {
+                    line_builder = line_builder.push_array(|builder|
+                        builder.push("cursor")
+                            .push(col(region.end))
+                    );
+                }
+            }
+            builder = builder.push(line_builder.unwrap());
             line_num += 1;
             if is_last_line || line_num == last_line {
                 break;
@@ -160,35 +222,131 @@ impl View {
         builder.unwrap()
     }
 
-    pub fn render_spans(&self, mut builder: ArrayBuilder, start: usize, end: usize) -> ArrayBuilder {
-        let fg_spans = self.fg_spans.subseq(Interval::new_closed_open(start, end));
-        for (iv, fg) in fg_spans.iter() {
-            builder = builder.push_array(|builder|
-                builder.push("fg")
-                    .push(iv.start())
-                    .push(iv.end())
-                    .push(fg));
+    // The styles of the text between `start` and `end`, relative to `start`,
+    // with the layers (syntax, test spans, search matches and the selection,
+    // from the bottom up) merged into runs that don't overlap.
+    fn render_styles(&self, styles: &mut StyleMap, start: usize, end: usize)
+            -> Vec<(Interval, Style)> {
+        let iv = Interval::new_closed_open(start, end);
+        let clip = |span_iv: Interval| span_iv.intersect(iv).translate_neg(start);
+        let mut runs = Vec::new();
+        if let (Some(syntax), Some(highlighter)) = (self.syntax, self.highlighter.as_ref()) {
+            for (span_iv, &style) in highlighter.spans().iter_range(iv) {
+                runs.push((clip(span_iv),
+                    styles.scope_style(&[syntax.scope(), highlight::scope(style)])));
+            }
+        }
+        runs.extend(self.fg_spans.iter_range(iv)
+            .map(|(span_iv, &fg)| (clip(span_iv), Style::fg(fg))));
+        let find_bg = styles.theme().find_highlight.unwrap_or(FIND_BG);
+        let selection_bg = styles.theme().selection.unwrap_or(SELECTION_BG);
+        if let Some(ref search) = self.search {
+            runs.extend(search.matches(start, end).into_iter().map(|(m_start, m_end)|
+                (Interval::new_closed_open(m_start - start, m_end - start), Style::bg(find_bg))));
+        }
+        for region in self.selection.regions_in_range(start, end) {
+            let sel_start = max(region.min(), start);
+            let sel_end = min(region.max(), end);
+            if sel_start < sel_end {
+                runs.push((Interval::new_closed_open(sel_start - start, sel_end - start),
+                    Style::bg(selection_bg)));
+            }
         }
-        builder
+        merge_layers(&runs)
     }
 
-    pub fn render(&self, text: &Rope, scroll_to: Option<usize>) -> Value {
+    /// Render an update for the front-end, containing only the lines in the
+    /// visible region (plus some slop) that it doesn't already have.
+    pub fn render(&mut self, text: &Rope, styles: &mut StyleMap) -> Value {
+        self.dirty = false;
+        let height = self.line_of_offset(text, text.len()) + 1;
+        debug_assert_eq!(height, self.line_cache.n_lines());
+        // the lines with selection annotations, both before and now, need resending
+        for (start, end) in self.sel_lines.drain(..) {
+            self.line_cache.invalidate(start, end);
+        }
+        let mut sel_lines: Vec<(usize, usize)> = Vec::new();
+        for region in self.selection.iter() {
+            let caret_line = self.caret_line(text, region);
+            let start = min(self.line_of_offset(text, region.min()), caret_line);
+            let end = max(self.line_of_offset(text, region.max()), caret_line) + 1;
+            match sel_lines.last_mut() {
+                Some(last) if start <= last.1 => last.1 = max(last.1, end),
+                _ => sel_lines.push((start, end)),
+            }
+        }
+        for &(start, end) in &sel_lines {
+            self.line_cache.invalidate(start, end);
+        }
+        self.sel_lines = sel_lines;
+
         let first_line = max(self.first_line, SCROLL_SLOP) - SCROLL_SLOP;
-        let last_line = self.first_line + self.height + SCROLL_SLOP;
-        let lines = self.render_lines(text, first_line, last_line);
-        let height = self.offset_to_line_col(text, text.len()).0 + 1;
+        let last_line = min(self.first_line + self.height + SCROLL_SLOP, height);
+        let ops = self.render_ops(text, styles, first_line, last_line);
         let mut builder = ObjectBuilder::new()
-            .insert("lines", lines)
-            .insert("first_line", first_line)
-            .insert("height", height);
-        if let Some(scrollto) = scroll_to {
+            .insert("ops", ops);
+        if let Some(scrollto) = self.scroll_to.take() {
             let (line, col) = self.offset_to_line_col(text, scrollto);
             builder = builder.insert_array("scrollto", |builder|
                 builder.push(line).push(col));
         }
+        let find_status = self.find_status();
+        if find_status != self.find_status {
+            builder = match find_status {
+                Some((count, current)) => builder.insert_object("find", |builder|
+                    builder.insert("count", count).insert("current", current)),
+                None => builder.insert("find", Value::Null),
+            };
+            self.find_status = find_status;
+        }
         builder.unwrap()
     }
 
+    // The number of matches of the search, and the index of the one the last
+    // region of the selection is on, if any.
+    fn find_status(&self) -> Option<(usize, Option<usize>)> {
+        self.search.as_ref().map(|search| {
+            let current = self.selection.last().and_then(|region|
+                search.index_of(region.min(), region.max()));
+            (search.count(), current)
+        })
+    }
+
+    /// Build an update sending lines `first..last`, as requested by the front-end
+    /// when it scrolls to lines it doesn't have. The front-end may have dropped
+    /// lines from its cache, so the requested ones are always sent.
+    pub fn render_request(&mut self, text: &Rope, styles: &mut StyleMap, first: usize,
+            last: usize) -> Value {
+        let last = min(last, self.line_cache.n_lines());
+        let first = min(first, last);
+        self.line_cache.invalidate(first, last);
+        let ops = self.render_ops(text, styles, first, last);
+        ObjectBuilder::new()
+            .insert("ops", ops)
+            .unwrap()
+    }
+
+    fn render_ops(&mut self, text: &Rope, styles: &mut StyleMap, first: usize, last: usize)
+            -> Value {
+        let mut ops = ArrayBuilder::new();
+        for op in self.line_cache.render(first, last) {
+            ops = match op {
+                LineOp::Copy(n) => ops.push_object(|builder|
+                    builder.insert("op", "copy").insert("n", n)),
+                LineOp::Skip(n) => ops.push_object(|builder|
+                    builder.insert("op", "skip").insert("n", n)),
+                LineOp::Invalidate(n) => ops.push_object(|builder|
+                    builder.insert("op", "invalidate").insert("n", n)),
+                LineOp::Ins(start, end) => {
+                    let lines = self.render_lines(text, styles, start, end);
+                    ops.push_object(|builder|
+                        builder.insert("op", "ins").insert("n", end - start).insert("lines", lines))
+                }
+            };
+        }
+        ops.unwrap()
+    }
+
     // How should we count "column"? Valid choices include:
     // * Unicode codepoints
     // * grapheme clusters
@@ -196,56 +354,134 @@ impl View {
     // * Actual measurement in text layout
     // * Code units in some encoding
     //
-    // Of course, all these are identical for ASCII. For now we use UTF-8 code units
-    // for simplicity.
+    // Of course, all these are identical for ASCII. Positions sent to the
+    // front-end (`scrollto`) and the column remembered by vertical motion use
+    // Unicode width, as in wrapping. Columns of clicks, drags and annotations
+    // are in the code units negotiated with the front-end (see `ColumnUnit`).
 
     pub fn offset_to_line_col(&self, text: &Rope, offset: usize) -> (usize, usize) {
         let line = self.line_of_offset(text, offset);
-        (line, offset - self.offset_of_line(text, line))
+        (line, self.width_in_line(text, line, offset))
+    }
+
+    /// The line and column (in display width) at which the caret of `region`
+    /// is drawn.
+    pub fn caret_line_col(&self, text: &Rope, region: &SelRegion) -> (usize, usize) {
+        let line = self.caret_line(text, region);
+        (line, self.width_in_line(text, line, region.end))
+    }
+
+    // The display width of the text from the start of `line` to `offset`.
+    fn width_in_line(&self, text: &Rope, line: usize, offset: usize) -> usize {
+        linewrap::text_width(text, self.offset_of_line(text, line), offset, 0)
+    }
+
+    // The line the caret of `region` is drawn on. This is the line containing
+    // its end, unless that's at a soft break and the caret has upstream affinity.
+    fn caret_line(&self, text: &Rope, region: &SelRegion) -> usize {
+        let line = self.line_of_offset(text, region.end);
+        if region.affinity == Affinity::Upstream && self.is_soft_break(text, region.end) {
+            line - 1
+        } else {
+            line
+        }
+    }
+
+    // Whether `offset` is the start of a line created by wrapping, rather than
+    // by a newline.
+    fn is_soft_break(&self, text: &Rope, offset: usize) -> bool {
+        self.breaks.is_some() && offset > 0 && offset < text.len() &&
+            self.offset_of_line(text, self.line_of_offset(text, offset)) == offset &&
+            text.offset_of_line(text.line_of_offset(offset)) != offset
+    }
+
+    /// A caret at the given line and column, in the front-end's column unit,
+    /// clamped to the text. Past the end of a line ending in a soft break, the
+    /// caret goes at the end of that line.
+    pub fn line_col_to_caret(&self, text: &Rope, line: usize, col: usize) -> SelRegion {
+        // past the last line, the breaks can give an offset beyond the text
+        let line_start = min(self.offset_of_line(text, line), text.len());
+        let offset = self.column_unit.offset_of_col(text, line_start, col);
+        self.line_offset_to_caret(text, line, offset)
     }
 
-    pub fn line_col_to_offset(&self, text: &Rope, line: usize, col: usize) -> usize {
-        let mut offset = self.offset_of_line(text, line).saturating_add(col);
+    // A caret at `offset`, which is at or after the start of `line`, snapped to
+    // a grapheme cluster boundary and clamped to the end of the line.
+    fn line_offset_to_caret(&self, text: &Rope, line: usize, mut offset: usize) -> SelRegion {
         if offset >= text.len() {
             offset = text.len();
             if self.line_of_offset(text, offset) <= line {
-                return offset;
+                return SelRegion::caret(offset);
             }
         } else {
-            // Snap to grapheme cluster boundary
-            offset = text.prev_grapheme_offset(offset + 1).unwrap();
+            // Snap to codepoint, then to grapheme cluster boundary
+            offset = text.prev_codepoint_offset(offset + 1).unwrap();
+            let next = text.next_grapheme_offset(offset).unwrap();
+            offset = text.prev_grapheme_offset(next).unwrap();
         }
 
         // clamp to end of line
         let next_line_offset = self.offset_of_line(text, line + 1);
         if offset >= next_line_offset {
+            if self.is_soft_break(text, next_line_offset) {
+                return SelRegion {
+                    affinity: Affinity::Upstream,
+                    ..SelRegion::caret(next_line_offset)
+                };
+            }
             if let Some(prev) = text.prev_grapheme_offset(next_line_offset) {
                 offset = prev;
             }
         }
-        offset
+        SelRegion::caret(offset)
     }
 
-    // Move up or down by `line_delta` lines and return offset where the
-    // cursor lands. The `col` argument should probably move into the View
-    // struct.
-    pub fn vertical_motion(&self, text: &Rope, line_delta: isize, col: usize) -> usize {
+    /// A caret on the given line, at the given column in display width. A
+    /// column in the middle of a wide character goes before it.
+    pub fn line_width_to_caret(&self, text: &Rope, line: usize, width: usize) -> SelRegion {
+        let start = self.offset_of_line(text, line);
+        // past the last line, the breaks can give an offset beyond the text
+        let end = min(self.offset_of_line(text, line + 1), text.len());
+        let line_str = text.slice_to_string(start, end);
+        let mut col = 0;
+        for (ix, c) in line_str.char_indices() {
+            col += str_width(&line_str[ix..ix + c.len_utf8()], col, TAB_WIDTH);
+            if col > width {
+                return self.line_offset_to_caret(text, line, start + ix);
+            }
+        }
+        self.line_offset_to_caret(text, line, end)
+    }
+
+    /// Move the caret of `region` up or down by `line_delta` lines, returning a
+    /// caret where it lands. The column aimed for is remembered in the result,
+    /// so that moving through shorter lines doesn't lose it.
+    pub fn vertical_motion(&self, text: &Rope, region: &SelRegion, line_delta: isize) -> SelRegion {
         // This code is quite careful to avoid integer overflow.
         // TODO: write tests to verify
-        let line = self.line_of_offset(text, self.sel_end);
-        if line_delta < 0 && (-line_delta as usize) > line {
-            return 0;
-        }
-        let line = if line_delta < 0 {
-            line - (-line_delta as usize)
+        let (line, col) = self.caret_line_col(text, region);
+        let col = region.horiz.unwrap_or(col);
+        let new_region = if line_delta < 0 && (-line_delta as usize) > line {
+            SelRegion::caret(0)
         } else {
-            line.saturating_add(line_delta as usize)
+            let line = if line_delta < 0 {
+                line - (-line_delta as usize)
+            } else {
+                line.saturating_add(line_delta as usize)
+            };
+            let n_lines = self.line_of_offset(text, text.len());
+            if line > n_lines {
+                SelRegion::caret(text.len())
+            } else {
+                self.line_width_to_caret(text, line, col)
+            }
         };
-        let n_lines = self.line_of_offset(text, text.len());
-        if line > n_lines {
-            return text.len();
+        // when the caret can't move any further, the column is forgotten
+        if new_region.end == region.end {
+            new_region
+        } else {
+            SelRegion { horiz: Some(col), ..new_region }
         }
-        self.line_col_to_offset(text, line, col)
     }
 
     // use own breaks if present, or text if not (no line wrapping)
@@ -271,31 +507,98 @@ impl View {
     pub fn rewrap(&mut self, text: &Rope, cols: usize) {
         self.breaks = Some(linewrap::linewrap(text, cols));
         self.cols = cols;
+        self.reset_line_cache(text);
     }
 
-    pub fn before_edit(&mut self, _text: &Rope, _delta: &OldDelta<RopeInfo>) {
-
+    pub fn before_edit(&mut self, text: &Rope, delta: &Delta<RopeInfo>) {
+        // Wrapping can change anywhere in a paragraph, so the lines affected are
+        // those of the paragraphs the edit touches. Record them, along with the
+        // offset (in the edited text) of the first paragraph after them, if any.
+        let (iv, new_len) = delta.summary();
+        let start = text.offset_of_line(text.line_of_offset(iv.start()));
+        let next_para = text.line_of_offset(iv.end()) + 1;
+        let (last_line, next_offset) = if next_para <= text.line_of_offset(text.len()) {
+            let offset = text.offset_of_line(next_para);
+            (self.line_of_offset(text, offset), Some(offset + new_len - (iv.end() - iv.start())))
+        } else {
+            (self.line_of_offset(text, text.len()) + 1, None)
+        };
+        self.pending_edit = Some((self.line_of_offset(text, start), last_line, next_offset));
     }
 
-    pub fn after_edit(&mut self, text: &Rope, delta: &OldDelta<RopeInfo>) {
+    /// Update the view for an edit to the text, which may have been made in
+    /// another view. The selection moves along with the text around it.
+    pub fn after_edit(&mut self, text: &Rope, delta: &Delta<RopeInfo>) {
+        self.selection = self.selection.apply_delta(delta);
+        self.dirty = true;
+        let (iv, new_len) = delta.summary();
         let cols = self.cols;
-        if self.breaks.is_some() {
-            if delta.len() == 1 {
-                let item = delta.iter().next().unwrap();
-                linewrap::rewrap(self.breaks.as_mut().unwrap(), text, item.interval, item.rope.len(), cols);
+        if let Some(ref mut breaks) = self.breaks {
+            linewrap::rewrap(breaks, text, iv, new_len, cols);
+        }
+        if let Some(ref mut search) = self.search {
+            search.after_edit(text, delta);
+        }
+        // edit the spans at each place the text changed, from the last, so the
+        // offsets of the ones before stay put
+        for &(iv, new_len) in delta.edits().iter().rev() {
+            self.fg_spans.apply_edit(iv, new_len);
+        }
+        let recolored = self.highlighter.as_mut()
+            .map(|highlighter| highlighter.after_edit(text, iv, new_len));
+        if let Some((first_line, last_line, next_offset)) = self.pending_edit.take() {
+            let new_last_line = match next_offset {
+                Some(offset) => self.line_of_offset(text, offset),
+                None => self.line_of_offset(text, text.len()) + 1,
+            };
+            self.line_cache.edit(first_line, last_line, new_last_line - first_line);
+            // keep tracking the lines of the old selection, for invalidation
+            let shift = |line: usize| if line <= first_line {
+                line
+            } else if line >= last_line {
+                line + new_last_line - last_line
             } else {
-                self.rewrap(text, cols);
+                new_last_line
+            };
+            for lines in &mut self.sel_lines {
+                *lines = (shift(lines.0), shift(lines.1));
             }
         }
+        // recoloring can carry on past the edit, to the end of a block comment say
+        if let Some(iv) = recolored {
+            let start = self.line_of_offset(text, iv.start());
+            let end = self.line_of_offset(text, iv.end()) + 1;
+            self.line_cache.invalidate(start, end);
+        }
     }
 
-    pub fn reset_breaks(&mut self) {
+    /// Reset the view when the text is replaced wholesale, putting the cursor
+    /// at the start. A search carries on in the new text.
+    pub fn reset(&mut self, text: &Rope) {
+        self.selection = Selection::new_simple(SelRegion::caret(0));
+        if let Some(search) = self.search.take() {
+            self.search = Some(Search::new(search.into_query(), text));
+        }
+        self.scroll_to = Some(0);
+        self.dirty = true;
         self.breaks = None;
+        self.fg_spans = SpansBuilder::new(text.len()).build();
+        self.highlighter = self.syntax.map(|syntax| syntax.highlighter(text));
+        self.reset_line_cache(text);
+    }
+
+    // Forget what the front-end has, so the next update resends everything.
+    fn reset_line_cache(&mut self, text: &Rope) {
+        let height = self.line_of_offset(text, text.len()) + 1;
+        self.line_cache.reset(height);
+        self.sel_lines.clear();
     }
 
-    pub fn set_test_fg_spans(&mut self) {
-        let mut sb = SpansBuilder::new(15);
-        sb.add_span(Interval::new_closed_open(5, 10), 0xffc00000);
+    pub fn set_test_fg_spans(&mut self, text: &Rope) {
+        let mut sb = SpansBuilder::new(text.len());
+        sb.add_span(Interval::new_closed_open(min(5, text.len()), min(10, text.len())), 0xffc00000);
         self.fg_spans = sb.build();
+        let height = self.line_cache.n_lines();
+        self.line_cache.invalidate(0, height);
     }
 }
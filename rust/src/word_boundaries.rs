@@ -0,0 +1,145 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Find word boundaries in text, for word-wise motion and selection.
+
+use xi_rope::rope::{LinesMetric, Rope, RopeInfo};
+use xi_rope::tree::Cursor;
+use xi_unicode::WordBreakLeafIter;
+
+struct WordBreakCursor<'a> {
+    inner: Cursor<'a, RopeInfo>,
+    wb_iter: WordBreakLeafIter,
+    len: usize,
+    done: bool,
+}
+
+impl<'a> WordBreakCursor<'a> {
+    // `pos` must be a word boundary, such as the start of a line.
+    fn new(text: &'a Rope, pos: usize) -> WordBreakCursor<'a> {
+        WordBreakCursor {
+            inner: Cursor::new(text, pos),
+            wb_iter: WordBreakLeafIter::new(pos),
+            len: text.len(),
+            done: false,
+        }
+    }
+
+    // The next word boundary; the last one is the end of the text.
+    fn next(&mut self) -> Option<usize> {
+        if self.done {
+            return None;
+        }
+        let mut leaf = self.inner.get_leaf();
+        while let Some((s, offset)) = leaf {
+            if let Some(bk) = self.wb_iter.next(s.as_str(), self.inner.pos() - offset) {
+                return Some(bk);
+            }
+            leaf = self.inner.next_leaf();
+        }
+        if let Some(bk) = self.wb_iter.finish() {
+            return Some(bk);
+        }
+        self.done = true;
+        Some(self.len)
+    }
+}
+
+/// Whether `c` makes the segment it starts a word.
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// A run of text between two adjacent word boundaries.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Segment {
+    pub start: usize,
+    pub end: usize,
+    /// Whether the segment is a word, rather than whitespace or punctuation.
+    pub is_word: bool,
+}
+
+// The segments of a line, including its newline. The start of a line is
+// always a word boundary, and so is the end.
+fn line_segments(text: &Rope, line: usize) -> Vec<Segment> {
+    let line_start = text.offset_of_line(line);
+    let line_end = text.offset_of_line(line + 1);
+    let mut cursor = WordBreakCursor::new(text, line_start);
+    let mut segments = Vec::new();
+    let mut start = line_start;
+    while start < line_end {
+        let end = match cursor.next() {
+            Some(end) => end,
+            None => break,
+        };
+        let s = text.slice_to_string(start, end);
+        segments.push(Segment {
+            start: start,
+            end: end,
+            is_word: s.starts_with(is_word_char),
+        });
+        start = end;
+    }
+    segments
+}
+
+fn last_line(text: &Rope) -> usize {
+    text.measure::<LinesMetric>()
+}
+
+/// The start of the word before `offset`, or the start of the text if there
+/// is none. Whitespace and punctuation, including newlines, are skipped.
+pub fn prev_word_offset(text: &Rope, offset: usize) -> usize {
+    let mut line = text.line_of_offset(offset);
+    loop {
+        let segments = line_segments(text, line);
+        if let Some(seg) = segments.iter().rev().find(|seg| seg.is_word && seg.start < offset) {
+            return seg.start;
+        }
+        if line == 0 {
+            return 0;
+        }
+        line -= 1;
+    }
+}
+
+/// The end of the word after `offset`, or the end of the text if there is
+/// none. Whitespace and punctuation, including newlines, are skipped.
+pub fn next_word_offset(text: &Rope, offset: usize) -> usize {
+    let mut line = text.line_of_offset(offset);
+    while line <= last_line(text) {
+        let segments = line_segments(text, line);
+        if let Some(seg) = segments.iter().find(|seg| seg.is_word && seg.end > offset) {
+            return seg.end;
+        }
+        line += 1;
+    }
+    text.len()
+}
+
+/// The segment containing `offset`, for selecting by double-click. A word
+/// ending at `offset` is preferred to whitespace or punctuation after it, so
+/// that clicking just past the end of a word selects it. At the end of the
+/// text, with no word before it, the segment is empty.
+pub fn segment_around(text: &Rope, offset: usize) -> Segment {
+    let segments = line_segments(text, text.line_of_offset(offset));
+    let containing = segments.iter().find(|seg| seg.start <= offset && offset < seg.end);
+    let before = segments.iter().find(|seg| seg.end == offset && seg.is_word);
+    match (containing, before) {
+        (Some(seg), _) if seg.is_word => *seg,
+        (_, Some(seg)) => *seg,
+        (Some(seg), None) => *seg,
+        (None, None) => Segment { start: offset, end: offset, is_word: false },
+    }
+}
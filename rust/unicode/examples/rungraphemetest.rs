@@ -0,0 +1,138 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Run grapheme break test data.
+
+// Run on:
+// http://www.unicode.org/Public/UCD/latest/ucd/auxiliary/GraphemeBreakTest.txt
+
+extern crate xi_unicode;
+
+use xi_unicode::{GraphemeCursor, GraphemeIncomplete};
+
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::fs::File;
+
+fn quote_str(s: &str) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            result.push('\\');
+        }
+        if ' ' <= c && c <= '~' {
+            result.push(c);
+        } else {
+            result.push_str(&format!("\\u{{{:04x}}}", c as u32));
+        }
+    }
+    result
+}
+
+// Find boundaries going forward (or backward) with the string split into
+// one chunk per codepoint, exercising the chunk protocol.
+fn find_breaks(s: &str, chunked: bool, backward: bool) -> Vec<usize> {
+    let chunks = if chunked {
+        s.char_indices().map(|(i, c)| (i, &s[i..i + c.len_utf8()])).collect::<Vec<_>>()
+    } else {
+        vec![(0, s)]
+    };
+    let mut ix = if backward { chunks.len() - 1 } else { 0 };
+    let mut cursor = GraphemeCursor::new(if backward { s.len() } else { 0 }, s.len());
+    let mut result = Vec::new();
+    loop {
+        let (chunk_start, chunk) = chunks[ix];
+        let next = if backward {
+            cursor.prev_boundary(chunk, chunk_start)
+        } else {
+            cursor.next_boundary(chunk, chunk_start)
+        };
+        match next {
+            Ok(Some(bk)) => result.push(bk),
+            Ok(None) => break,
+            Err(GraphemeIncomplete::PrevChunk) => ix -= 1,
+            Err(GraphemeIncomplete::NextChunk) => ix += 1,
+        }
+    }
+    if backward {
+        // report in the same order, and the same way, as going forward
+        result.reverse();
+        result.remove(0);
+        result.push(s.len());
+    }
+    result
+}
+
+fn check_breaks(s: &str, breaks: &[usize], chunked: bool, backward: bool) -> bool {
+    let my_breaks = find_breaks(s, chunked, backward);
+    if my_breaks != breaks {
+        println!("failed case: \"{}\"", quote_str(s));
+        println!("expected {:?} actual {:?}", breaks, my_breaks);
+        return false;
+    }
+    true
+}
+
+fn run_test(filename: &str, chunked: bool) -> std::io::Result<()> {
+    let f = try!(File::open(filename));
+    let mut reader = BufReader::new(f);
+    let mut pass = 0;
+    let mut total = 0;
+    loop {
+        let mut line = String::new();
+        if try!(reader.read_line(&mut line)) == 0 { break };
+        let mut s = String::new();
+        let mut breaks = Vec::new();
+        for token in line.split_whitespace() {
+            if token == "÷" {
+                // the break at the start of text isn't reported
+                if !s.is_empty() {
+                    breaks.push(s.len());
+                }
+            } else if token == "×" {
+            } else if token == "#" {
+                break;
+            } else if let Ok(cp) = u32::from_str_radix(token, 16) {
+                s.push(std::char::from_u32(cp).unwrap());
+            }
+        }
+        if s.is_empty() {
+            continue;
+        }
+        total += 1;
+        if check_breaks(&s, &breaks, chunked, false) &&
+                check_breaks(&s, &breaks, chunked, true) {
+            pass += 1;
+        }
+    }
+    println!("{}/{} pass", pass, total);
+    Ok(())
+}
+
+fn main() {
+    let mut args = std::env::args();
+    let _ = args.next();
+    let filename = args.next().unwrap();
+    match args.next() {
+        None => {
+            let _ = run_test(&filename, false);
+        }
+        Some(ref s) if s == "--chunked" => {
+            let _ = run_test(&filename, true);
+        }
+        _ => {
+            println!("unknown argument");
+        }
+    }
+}
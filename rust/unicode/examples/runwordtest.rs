@@ -0,0 +1,124 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Run word break test data.
+
+// Run on:
+// http://www.unicode.org/Public/UCD/latest/ucd/auxiliary/WordBreakTest.txt
+
+extern crate xi_unicode;
+
+use xi_unicode::{WordBreakIterator, WordBreakLeafIter};
+
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::fs::File;
+
+fn quote_str(s: &str) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            result.push('\\');
+        }
+        if ' ' <= c && c <= '~' {
+            result.push(c);
+        } else {
+            result.push_str(&format!("\\u{{{:04x}}}", c as u32));
+        }
+    }
+    result
+}
+
+// Find boundaries with the string split into one leaf per codepoint,
+// exercising breaks held back across leaves.
+fn find_breaks_chunked(s: &str) -> Vec<usize> {
+    let mut iter = WordBreakLeafIter::new(0);
+    let mut result = Vec::new();
+    for (i, c) in s.char_indices() {
+        while let Some(bk) = iter.next(&s[i..i + c.len_utf8()], i) {
+            result.push(bk);
+        }
+    }
+    while let Some(bk) = iter.finish() {
+        result.push(bk);
+    }
+    result.push(s.len());
+    result
+}
+
+fn check_breaks(s: &str, breaks: &[usize], chunked: bool) -> bool {
+    let my_breaks = if chunked {
+        find_breaks_chunked(s)
+    } else {
+        WordBreakIterator::new(s).collect::<Vec<_>>()
+    };
+    if my_breaks != breaks {
+        println!("failed case: \"{}\"", quote_str(s));
+        println!("expected {:?} actual {:?}", breaks, my_breaks);
+        return false;
+    }
+    true
+}
+
+fn run_test(filename: &str, chunked: bool) -> std::io::Result<()> {
+    let f = try!(File::open(filename));
+    let mut reader = BufReader::new(f);
+    let mut pass = 0;
+    let mut total = 0;
+    loop {
+        let mut line = String::new();
+        if try!(reader.read_line(&mut line)) == 0 { break };
+        let mut s = String::new();
+        let mut breaks = Vec::new();
+        for token in line.split_whitespace() {
+            if token == "÷" {
+                // the break at the start of text isn't reported
+                if !s.is_empty() {
+                    breaks.push(s.len());
+                }
+            } else if token == "×" {
+            } else if token == "#" {
+                break;
+            } else if let Ok(cp) = u32::from_str_radix(token, 16) {
+                s.push(std::char::from_u32(cp).unwrap());
+            }
+        }
+        if s.is_empty() {
+            continue;
+        }
+        total += 1;
+        if check_breaks(&s, &breaks, chunked) {
+            pass += 1;
+        }
+    }
+    println!("{}/{} pass", pass, total);
+    Ok(())
+}
+
+fn main() {
+    let mut args = std::env::args();
+    let _ = args.next();
+    let filename = args.next().unwrap();
+    match args.next() {
+        None => {
+            let _ = run_test(&filename, false);
+        }
+        Some(ref s) if s == "--chunked" => {
+            let _ = run_test(&filename, true);
+        }
+        _ => {
+            println!("unknown argument");
+        }
+    }
+}
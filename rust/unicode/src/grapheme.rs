@@ -0,0 +1,394 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Extended grapheme cluster boundaries, according to UAX #29.
+
+use tables::*;
+
+// Grapheme break properties used in code, in the order of
+// grapheme_assignments in tools/mk_tables.py.
+const GB_OTHER: u8 = 0;
+pub const GB_CR: u8 = 1;
+pub const GB_LF: u8 = 2;
+pub const GB_CONTROL: u8 = 3;
+pub const GB_EXTEND: u8 = 4;
+pub const GB_ZWJ: u8 = 5;
+const GB_REGIONAL_INDICATOR: u8 = 6;
+pub const GB_V: u8 = 10;
+pub const GB_T: u8 = 11;
+pub const GB_EXTENDED_PICTOGRAPHIC: u8 = 14;
+
+// Entries of GRAPHEME_PAIR_TABLE.
+const PAIR_NO_BREAK: u8 = 0;
+const PAIR_BREAK: u8 = 1;
+const PAIR_REGIONAL_INDICATOR: u8 = 2;
+
+pub fn grapheme_property(cp: char) -> u8 {
+    let cp = cp as usize;
+    if cp < 0x800 {
+        GRAPHEME_1_2[cp]
+    } else if cp < 0x10000 {
+        let child = GRAPHEME_3_ROOT[cp >> 6];
+        GRAPHEME_3_CHILD[(child as usize) * 0x40 + (cp & 0x3f)]
+    } else {
+        let mid = GRAPHEME_4_ROOT[cp >> 12];
+        let leaf = GRAPHEME_4_MID[(mid as usize) * 0x40 + ((cp >> 6) & 0x3f)];
+        GRAPHEME_4_LEAVES[(leaf as usize) * 0x40 + (cp & 0x3f)]
+    }
+}
+
+// Return property, length
+// May panic if ix doesn't point to a valid character in the string
+pub fn grapheme_property_str(s: &str, ix: usize) -> (u8, usize) {
+    let b = s.as_bytes()[ix];
+    if b < 0x80 {
+        (GRAPHEME_1_2[b as usize], 1)
+    } else if b < 0xe0 {
+        // 2 byte UTF-8 sequences
+        let cp = ((b as usize) << 6) + (s.as_bytes()[ix + 1] as usize) - 0x3080;
+        (GRAPHEME_1_2[cp], 2)
+    } else if b < 0xf0 {
+        // 3 byte UTF-8 sequences
+        let mid_ix = ((b as usize) << 6) + (s.as_bytes()[ix + 1] as usize) - 0x3880;
+        let mid = GRAPHEME_3_ROOT[mid_ix];
+        (GRAPHEME_3_CHILD[(mid as usize) * 0x40 + (s.as_bytes()[ix + 2] as usize) - 0x80], 3)
+    } else {
+        // 4 byte UTF-8 sequences
+        let mid_ix = ((b as usize) << 6) + (s.as_bytes()[ix + 1] as usize) - 0x3c80;
+        let mid = GRAPHEME_4_ROOT[mid_ix];
+        let leaf_ix = ((mid as usize) << 6) + (s.as_bytes()[ix + 2] as usize) - 0x80;
+        let leaf = GRAPHEME_4_MID[leaf_ix];
+        (GRAPHEME_4_LEAVES[(leaf as usize) * 0x40 + (s.as_bytes()[ix + 3] as usize) - 0x80], 4)
+    }
+}
+
+// Return property, length of the codepoint ending at ix
+fn grapheme_property_before(s: &str, ix: usize) -> (u8, usize) {
+    let c = s[..ix].chars().next_back().unwrap();
+    (grapheme_property(c), c.len_utf8())
+}
+
+/// The reason a `GraphemeCursor` couldn't give an answer with the chunk it
+/// was given. The caller should supply the requested chunk and call the same
+/// method again.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GraphemeIncomplete {
+    /// The chunk ending where the supplied chunk begins is needed.
+    PrevChunk,
+    /// The chunk beginning where the supplied chunk ends is needed.
+    NextChunk,
+}
+
+// Look-behind in progress, for the rules that depend on more than the
+// codepoints on either side of the boundary.
+#[derive(Copy, Clone)]
+enum LookBehind {
+    // GB12, GB13: counting regional indicators; true if an even number have
+    // been seen so far.
+    RegionalIndicator(bool),
+    // GB11: skipping Extend after a ZWJ, looking for Extended_Pictographic.
+    Emoji,
+}
+
+/// A cursor for finding extended grapheme cluster boundaries in a rope or
+/// other non-contiguous string representation.
+///
+/// The text is supplied in chunks, each of which must start and end on a
+/// codepoint boundary. Each method takes the chunk containing the position
+/// of interest along with the offset of its start; when the cursor needs text
+/// outside that chunk, it returns a `GraphemeIncomplete` saying which one,
+/// and the method should be called again with that chunk. Determining a
+/// boundary may need an unbounded amount of look-behind, in the case of a
+/// long run of regional indicators.
+#[derive(Clone)]
+pub struct GraphemeCursor {
+    // the position being examined
+    offset: usize,
+    // the length of the whole text
+    len: usize,
+    // property of the codepoint after offset, once known
+    after: Option<u8>,
+    // property and length of the codepoint before offset, once known
+    before: Option<(u8, usize)>,
+    // the position look-behind has reached, and its state
+    look_behind: Option<(usize, LookBehind)>,
+    // whether offset is a boundary, once known
+    boundary: Option<bool>,
+    // true when a call to next_boundary or prev_boundary was interrupted
+    // after moving offset
+    resuming: bool,
+}
+
+impl GraphemeCursor {
+    /// Create a new cursor at `offset` in a text of length `len`.
+    /// Precondition: `offset` is on a codepoint boundary.
+    pub fn new(offset: usize, len: usize) -> GraphemeCursor {
+        GraphemeCursor {
+            offset: offset,
+            len: len,
+            after: None,
+            before: None,
+            look_behind: None,
+            boundary: None,
+            resuming: false,
+        }
+    }
+
+    /// Move the cursor to `offset`, which must be on a codepoint boundary.
+    pub fn set_cursor(&mut self, offset: usize) {
+        *self = GraphemeCursor::new(offset, self.len);
+    }
+
+    /// The current position of the cursor.
+    pub fn cur_cursor(&self) -> usize {
+        self.offset
+    }
+
+    /// Determine whether the current position is a grapheme cluster boundary.
+    /// The chunk should contain the codepoint following the position.
+    pub fn is_boundary(&mut self, chunk: &str, chunk_start: usize)
+            -> Result<bool, GraphemeIncomplete> {
+        if let Some(boundary) = self.boundary {
+            return Ok(boundary);
+        }
+        if self.offset == 0 || self.offset == self.len {
+            // GB1, GB2
+            self.boundary = Some(true);
+            return Ok(true);
+        }
+        let chunk_end = chunk_start + chunk.len();
+        if self.after.is_none() {
+            if self.offset < chunk_start {
+                return Err(GraphemeIncomplete::PrevChunk);
+            } else if self.offset >= chunk_end {
+                return Err(GraphemeIncomplete::NextChunk);
+            }
+            self.after = Some(grapheme_property_str(chunk, self.offset - chunk_start).0);
+        }
+        if self.before.is_none() {
+            if self.offset <= chunk_start {
+                return Err(GraphemeIncomplete::PrevChunk);
+            } else if self.offset > chunk_end {
+                return Err(GraphemeIncomplete::NextChunk);
+            }
+            self.before = Some(grapheme_property_before(chunk, self.offset - chunk_start));
+        }
+        if self.look_behind.is_none() {
+            let (before, before_len) = self.before.unwrap();
+            let after = self.after.unwrap();
+            let state = match GRAPHEME_PAIR_TABLE[(before as usize) * N_GRAPHEME_CATEGORIES + (after as usize)] {
+                PAIR_NO_BREAK => {
+                    self.boundary = Some(false);
+                    return Ok(false);
+                }
+                PAIR_BREAK => {
+                    self.boundary = Some(true);
+                    return Ok(true);
+                }
+                PAIR_REGIONAL_INDICATOR => LookBehind::RegionalIndicator(false),
+                _ => LookBehind::Emoji,
+            };
+            self.look_behind = Some((self.offset - before_len, state));
+        }
+        loop {
+            let (pos, state) = self.look_behind.unwrap();
+            let prop = if pos == 0 {
+                // start of text, which neither rule looks past
+                GB_OTHER
+            } else if pos <= chunk_start {
+                return Err(GraphemeIncomplete::PrevChunk);
+            } else if pos > chunk_end {
+                return Err(GraphemeIncomplete::NextChunk);
+            } else {
+                let (prop, len) = grapheme_property_before(chunk, pos - chunk_start);
+                match (state, prop) {
+                    (LookBehind::RegionalIndicator(even), GB_REGIONAL_INDICATOR) => {
+                        self.look_behind = Some((pos - len, LookBehind::RegionalIndicator(!even)));
+                        continue;
+                    }
+                    (LookBehind::Emoji, GB_EXTEND) => {
+                        self.look_behind = Some((pos - len, state));
+                        continue;
+                    }
+                    _ => prop
+                }
+            };
+            let boundary = match state {
+                LookBehind::RegionalIndicator(even) => even,
+                LookBehind::Emoji => prop != GB_EXTENDED_PICTOGRAPHIC,
+            };
+            self.boundary = Some(boundary);
+            return Ok(boundary);
+        }
+    }
+
+    /// Find the next boundary after the current position, moving the cursor
+    /// there. Returns `None` at the end of the text. The chunk should
+    /// contain the codepoint following the position.
+    pub fn next_boundary(&mut self, chunk: &str, chunk_start: usize)
+            -> Result<Option<usize>, GraphemeIncomplete> {
+        loop {
+            if !self.resuming {
+                if self.offset == self.len {
+                    return Ok(None);
+                } else if self.offset < chunk_start {
+                    return Err(GraphemeIncomplete::PrevChunk);
+                } else if self.offset >= chunk_start + chunk.len() {
+                    return Err(GraphemeIncomplete::NextChunk);
+                }
+                let step = grapheme_property_str(chunk, self.offset - chunk_start);
+                self.set_cursor(self.offset + step.1);
+                self.before = Some(step);
+                self.resuming = true;
+            }
+            let boundary = try!(self.is_boundary(chunk, chunk_start));
+            self.resuming = false;
+            if boundary {
+                return Ok(Some(self.offset));
+            }
+        }
+    }
+
+    /// Find the previous boundary before the current position, moving the
+    /// cursor there. Returns `None` at the start of the text. The chunk
+    /// should contain the codepoint preceding the position.
+    pub fn prev_boundary(&mut self, chunk: &str, chunk_start: usize)
+            -> Result<Option<usize>, GraphemeIncomplete> {
+        loop {
+            if !self.resuming {
+                if self.offset == 0 {
+                    return Ok(None);
+                } else if self.offset <= chunk_start {
+                    return Err(GraphemeIncomplete::PrevChunk);
+                } else if self.offset > chunk_start + chunk.len() {
+                    return Err(GraphemeIncomplete::NextChunk);
+                }
+                let (prop, len) = grapheme_property_before(chunk, self.offset - chunk_start);
+                self.set_cursor(self.offset - len);
+                self.after = Some(prop);
+                self.resuming = true;
+            }
+            let boundary = try!(self.is_boundary(chunk, chunk_start));
+            self.resuming = false;
+            if boundary {
+                return Ok(Some(self.offset));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use grapheme_property;
+    use grapheme_property_str;
+    use GraphemeCursor;
+    use GraphemeIncomplete;
+
+    fn boundaries(s: &str) -> Vec<usize> {
+        let mut cursor = GraphemeCursor::new(0, s.len());
+        let mut result = Vec::new();
+        while let Some(bk) = cursor.next_boundary(s, 0).unwrap() {
+            result.push(bk);
+        }
+        result
+    }
+
+    fn prev_boundaries(s: &str) -> Vec<usize> {
+        let mut cursor = GraphemeCursor::new(s.len(), s.len());
+        let mut result = Vec::new();
+        while let Some(bk) = cursor.prev_boundary(s, 0).unwrap() {
+            result.push(bk);
+        }
+        result
+    }
+
+    #[test]
+    fn grapheme_prop() {
+        assert_eq!( 0, grapheme_property('a'));
+        assert_eq!( 1, grapheme_property('\r'));
+        assert_eq!( 2, grapheme_property('\n'));
+        assert_eq!( 3, grapheme_property('\u{0007}'));
+        assert_eq!( 4, grapheme_property('\u{0301}'));
+        assert_eq!( 5, grapheme_property('\u{200D}'));
+        assert_eq!( 6, grapheme_property('\u{1F1FA}'));
+        assert_eq!( 7, grapheme_property('\u{0600}'));
+        assert_eq!( 8, grapheme_property('\u{0903}'));
+        assert_eq!( 9, grapheme_property('\u{1100}'));
+        assert_eq!(10, grapheme_property('\u{1160}'));
+        assert_eq!(11, grapheme_property('\u{11A8}'));
+        assert_eq!(12, grapheme_property('\u{AC00}'));
+        assert_eq!(13, grapheme_property('\u{AC01}'));
+        assert_eq!(14, grapheme_property('\u{1F600}'));
+        assert_eq!(14, grapheme_property('\u{00A9}'));
+        assert_eq!( 0, grapheme_property('\u{10FFFF}'));
+
+        assert_eq!((4, 2), grapheme_property_str(&"\u{0301}", 0));
+        assert_eq!((5, 3), grapheme_property_str(&"\u{200D}", 0));
+        assert_eq!((6, 4), grapheme_property_str(&"\u{1F1FA}", 0));
+        assert_eq!((14, 4), grapheme_property_str(&"\u{1F600}", 0));
+    }
+
+    #[test]
+    fn grapheme_boundaries() {
+        assert_eq!(vec![1, 2, 3], boundaries("abc"));
+        // GB3, GB4
+        assert_eq!(vec![2, 3], boundaries("\r\na"));
+        // GB9
+        assert_eq!(vec![3, 4], boundaries("e\u{0301}f"));
+        // GB6, GB7, GB8: jamo forming one syllable
+        assert_eq!(vec![9], boundaries("\u{1100}\u{1161}\u{11A8}"));
+        // GB11: family emoji
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        assert_eq!(vec![family.len()], boundaries(family));
+        // GB11 doesn't apply without Extended_Pictographic
+        assert_eq!(vec![4, 8], boundaries("a\u{200D}\u{1F467}"));
+        // GB12, GB13: flags pair up
+        let flags = "\u{1F1FA}\u{1F1F8}\u{1F1EB}\u{1F1F7}\u{1F1EC}";
+        assert_eq!(vec![8, 16, 20], boundaries(flags));
+        assert_eq!(vec![16, 8, 0], prev_boundaries(flags));
+        assert_eq!(vec![0], prev_boundaries("e\u{0301}"));
+        assert_eq!(Vec::<usize>::new(), boundaries(""));
+    }
+
+    #[test]
+    fn chunked() {
+        // each codepoint in its own chunk
+        let s = "a\u{1F1FA}\u{1F1F8}\u{1F1EB}\u{1F468}\u{200D}\u{1F469}\u{0301}b";
+        let chunks = s.char_indices().map(|(i, c)| (i, &s[i..i + c.len_utf8()])).collect::<Vec<_>>();
+        let mut cursor = GraphemeCursor::new(0, s.len());
+        let mut ix = 0;
+        let mut result = Vec::new();
+        loop {
+            match cursor.next_boundary(chunks[ix].1, chunks[ix].0) {
+                Ok(Some(bk)) => result.push(bk),
+                Ok(None) => break,
+                Err(GraphemeIncomplete::PrevChunk) => ix -= 1,
+                Err(GraphemeIncomplete::NextChunk) => ix += 1,
+            }
+        }
+        assert_eq!(boundaries(s), result);
+
+        let mut cursor = GraphemeCursor::new(s.len(), s.len());
+        let mut result = Vec::new();
+        loop {
+            match cursor.prev_boundary(chunks[ix].1, chunks[ix].0) {
+                Ok(Some(bk)) => result.push(bk),
+                Ok(None) => break,
+                Err(GraphemeIncomplete::PrevChunk) => ix -= 1,
+                Err(GraphemeIncomplete::NextChunk) => ix += 1,
+            }
+        }
+        assert_eq!(prev_boundaries(s), result);
+    }
+}
@@ -12,12 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Unicode utilities useful for text editing, including a line breaking iterator.
+//! Unicode utilities useful for text editing, including a line breaking iterator,
+//! cursors for finding grapheme cluster and word boundaries, and display width.
 
 mod tables;
+mod grapheme;
+mod word;
+mod width;
 
 use tables::*;
 
+pub use grapheme::{grapheme_property, grapheme_property_str};
+pub use grapheme::{GraphemeCursor, GraphemeIncomplete};
+pub use word::{wordbreak_property, wordbreak_property_str};
+pub use word::{WordBreakIterator, WordBreakLeafIter};
+pub use width::{east_asian_width, char_width, str_width};
+
 pub fn linebreak_property(cp: char) -> u8 {
     let cp = cp as usize;
     if cp < 0x800 {
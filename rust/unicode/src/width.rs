@@ -0,0 +1,110 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Display width of text in columns, based on East Asian Width (UAX #11).
+
+use tables::*;
+use grapheme::grapheme_property;
+use grapheme::{GB_CR, GB_LF, GB_CONTROL, GB_EXTEND, GB_ZWJ, GB_V, GB_T};
+
+// East Asian Width properties used in code, in the order of eaw_assignments
+// in tools/mk_tables.py.
+const EAW_FULLWIDTH: u8 = 3;
+const EAW_WIDE: u8 = 5;
+
+pub fn east_asian_width(cp: char) -> u8 {
+    let cp = cp as usize;
+    if cp < 0x800 {
+        EAW_1_2[cp]
+    } else if cp < 0x10000 {
+        let child = EAW_3_ROOT[cp >> 6];
+        EAW_3_CHILD[(child as usize) * 0x40 + (cp & 0x3f)]
+    } else {
+        let mid = EAW_4_ROOT[cp >> 12];
+        let leaf = EAW_4_MID[(mid as usize) * 0x40 + ((cp >> 6) & 0x3f)];
+        EAW_4_LEAVES[(leaf as usize) * 0x40 + (cp & 0x3f)]
+    }
+}
+
+/// The number of columns taken by a codepoint: 2 for wide and fullwidth
+/// characters, 0 for controls, combining marks and the medial and final
+/// Hangul jamo that join onto a preceding syllable, and 1 for everything
+/// else, including characters of ambiguous width. A tab counts as 0 here, as
+/// its width depends on where it starts; see `str_width`.
+pub fn char_width(c: char) -> usize {
+    if c < '\u{7f}' {
+        return if c < ' ' { 0 } else { 1 };
+    }
+    match grapheme_property(c) {
+        GB_CR | GB_LF | GB_CONTROL | GB_EXTEND | GB_ZWJ | GB_V | GB_T => return 0,
+        _ => (),
+    }
+    match east_asian_width(c) {
+        EAW_FULLWIDTH | EAW_WIDE => 2,
+        _ => 1,
+    }
+}
+
+/// The number of columns taken by `s`, when it starts at column `start_col`.
+/// A tab advances to the next multiple of `tab_width`.
+pub fn str_width(s: &str, start_col: usize, tab_width: usize) -> usize {
+    let mut col = start_col;
+    for c in s.chars() {
+        if c == '\t' {
+            col += tab_width - col % tab_width;
+        } else {
+            col += char_width(c);
+        }
+    }
+    col - start_col
+}
+
+#[cfg(test)]
+mod tests {
+    use east_asian_width;
+    use char_width;
+    use str_width;
+
+    #[test]
+    fn eaw_prop() {
+        assert_eq!(0, east_asian_width('\u{0E01}'));
+        assert_eq!(1, east_asian_width('\u{00A1}'));
+        assert_eq!(2, east_asian_width('\u{FF61}'));
+        assert_eq!(3, east_asian_width('\u{3000}'));
+        assert_eq!(4, east_asian_width('a'));
+        assert_eq!(5, east_asian_width('\u{4E00}'));
+        assert_eq!(5, east_asian_width('\u{1F600}'));
+        assert_eq!(5, east_asian_width('\u{20000}'));
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(1, char_width('a'));
+        assert_eq!(0, char_width('\n'));
+        assert_eq!(0, char_width('\u{0301}'));
+        assert_eq!(0, char_width('\u{200D}'));
+        assert_eq!(2, char_width('\u{4E2D}'));
+        assert_eq!(2, char_width('\u{FF21}'));
+        assert_eq!(1, char_width('\u{FF61}'));
+        assert_eq!(1, char_width('\u{00E9}'));
+        // a conjoining jamo sequence takes the width of its leading consonant
+        assert_eq!(2, str_width("\u{1100}\u{1161}\u{11A8}", 0, 8));
+
+        assert_eq!(5, str_width("he\u{0301}llo", 0, 8));
+        assert_eq!(4, str_width("\u{4E2D}\u{6587}", 0, 8));
+        assert_eq!(9, str_width("a\tb", 0, 8));
+        assert_eq!(2, str_width("\tb", 7, 8));
+        assert_eq!(0, str_width("", 3, 8));
+    }
+}
@@ -0,0 +1,400 @@
+// Copyright 2016 Google Inc. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Word boundaries, according to UAX #29.
+
+use tables::*;
+use grapheme::{grapheme_property_str, GB_EXTENDED_PICTOGRAPHIC};
+
+// Word break properties used in code, in the order of wordbreak_assignments
+// in tools/mk_tables.py.
+const WB_CR: u8 = 1;
+const WB_LF: u8 = 2;
+const WB_NEWLINE: u8 = 3;
+const WB_EXTEND: u8 = 4;
+const WB_ZWJ: u8 = 5;
+const WB_REGIONAL_INDICATOR: u8 = 6;
+const WB_FORMAT: u8 = 7;
+const WB_HEBREW_LETTER: u8 = 9;
+const WB_ALETTER: u8 = 10;
+const WB_NUMERIC: u8 = 16;
+const WB_WSEGSPACE: u8 = 18;
+
+// Not a property; the state before the first codepoint.
+const WB_SOT: u8 = 0xff;
+
+// Entries of WORDBREAK_PAIR_TABLE.
+const PAIR_NO_BREAK: u8 = 0;
+const PAIR_BREAK: u8 = 1;
+const PAIR_REGIONAL_INDICATOR: u8 = 2;
+const PAIR_MID_LETTER: u8 = 3;
+const PAIR_MID_NUM: u8 = 4;
+const PAIR_DOUBLE_QUOTE: u8 = 5;
+const PAIR_HEBREW_SINGLE_QUOTE: u8 = 6;
+
+pub fn wordbreak_property(cp: char) -> u8 {
+    let cp = cp as usize;
+    if cp < 0x800 {
+        WORDBREAK_1_2[cp]
+    } else if cp < 0x10000 {
+        let child = WORDBREAK_3_ROOT[cp >> 6];
+        WORDBREAK_3_CHILD[(child as usize) * 0x40 + (cp & 0x3f)]
+    } else {
+        let mid = WORDBREAK_4_ROOT[cp >> 12];
+        let leaf = WORDBREAK_4_MID[(mid as usize) * 0x40 + ((cp >> 6) & 0x3f)];
+        WORDBREAK_4_LEAVES[(leaf as usize) * 0x40 + (cp & 0x3f)]
+    }
+}
+
+// Return property, length
+// May panic if ix doesn't point to a valid character in the string
+pub fn wordbreak_property_str(s: &str, ix: usize) -> (u8, usize) {
+    let b = s.as_bytes()[ix];
+    if b < 0x80 {
+        (WORDBREAK_1_2[b as usize], 1)
+    } else if b < 0xe0 {
+        // 2 byte UTF-8 sequences
+        let cp = ((b as usize) << 6) + (s.as_bytes()[ix + 1] as usize) - 0x3080;
+        (WORDBREAK_1_2[cp], 2)
+    } else if b < 0xf0 {
+        // 3 byte UTF-8 sequences
+        let mid_ix = ((b as usize) << 6) + (s.as_bytes()[ix + 1] as usize) - 0x3880;
+        let mid = WORDBREAK_3_ROOT[mid_ix];
+        (WORDBREAK_3_CHILD[(mid as usize) * 0x40 + (s.as_bytes()[ix + 2] as usize) - 0x80], 3)
+    } else {
+        // 4 byte UTF-8 sequences
+        let mid_ix = ((b as usize) << 6) + (s.as_bytes()[ix + 1] as usize) - 0x3c80;
+        let mid = WORDBREAK_4_ROOT[mid_ix];
+        let leaf_ix = ((mid as usize) << 6) + (s.as_bytes()[ix + 2] as usize) - 0x80;
+        let leaf = WORDBREAK_4_MID[leaf_ix];
+        (WORDBREAK_4_LEAVES[(leaf as usize) * 0x40 + (s.as_bytes()[ix + 3] as usize) - 0x80], 4)
+    }
+}
+
+fn is_newline(wb: u8) -> bool {
+    wb == WB_CR || wb == WB_LF || wb == WB_NEWLINE
+}
+
+// A break held back until the next codepoint not ignored by WB4 decides it,
+// as in "can't" or "3.14".
+#[derive(Copy, Clone)]
+struct Pending {
+    pos: usize,
+    // the property the next codepoint needs for there to be no break, where
+    // WB_ALETTER stands for either kind of letter
+    needs: u8,
+    // whether pos is a break if the next codepoint doesn't match (false when
+    // WB7a already joined the quote)
+    is_break: bool,
+}
+
+impl Pending {
+    fn matches(&self, wb: u8) -> bool {
+        if self.needs == WB_ALETTER {
+            wb == WB_ALETTER || wb == WB_HEBREW_LETTER
+        } else {
+            wb == self.needs
+        }
+    }
+}
+
+/// An iterator which produces word boundaries according to UAX #29. For each
+/// boundary after the start of the string, return its offset; the end of the
+/// string is always a boundary.
+pub struct WordBreakIterator<'a> {
+    s: &'a str,
+    inner: WordBreakLeafIter,
+    done: bool,
+}
+
+impl<'a> Iterator for WordBreakIterator<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.done {
+            return None;
+        }
+        if let Some(bk) = self.inner.next(self.s, 0) {
+            return Some(bk);
+        }
+        if let Some(bk) = self.inner.finish() {
+            return Some(bk);
+        }
+        // WB2, break at EOT
+        self.done = true;
+        if self.s.is_empty() {
+            None
+        } else {
+            Some(self.s.len())
+        }
+    }
+}
+
+impl<'a> WordBreakIterator<'a> {
+    /// Create a new iterator for the given string slice.
+    pub fn new(s: &str) -> WordBreakIterator {
+        WordBreakIterator {
+            s: s,
+            inner: WordBreakLeafIter::new(0),
+            done: false,
+        }
+    }
+}
+
+/// A word break iterator useful for leaves in a rope or other non-contiguous
+/// string representation. Unlike `LineBreakLeafIter`, offsets are relative to
+/// the whole text rather than the leaf, because whether there's a break
+/// before an apostrophe or full stop depends on the codepoint after it, which
+/// might be in the next leaf.
+///
+/// Iteration must start at a word boundary; the start of the text, and of
+/// every line, is always one.
+#[derive(Copy, Clone)]
+pub struct WordBreakLeafIter {
+    // offset of the next codepoint to examine
+    ix: usize,
+    // property of the previous codepoint, for WB3 to WB3d
+    prev_raw: u8,
+    // property of the previous codepoint not ignored by WB4
+    prev: u8,
+    // whether prev ends an odd-length run of regional indicators
+    ri_odd: bool,
+    pending: Option<Pending>,
+    // breaks found but not yet returned, in order
+    ready: [Option<usize>; 2],
+}
+
+impl WordBreakLeafIter {
+    /// Create a new word break iterator starting at `offset`, which must be a
+    /// word boundary.
+    pub fn new(offset: usize) -> WordBreakLeafIter {
+        WordBreakLeafIter {
+            ix: offset,
+            prev_raw: WB_SOT,
+            prev: WB_SOT,
+            ri_odd: false,
+            pending: None,
+            ready: [None, None],
+        }
+    }
+
+    /// Return the offset in the whole text of the next word boundary, given
+    /// the leaf `s` containing the current position, which starts at offset
+    /// `leaf_start`. If the end of the leaf is reached, return `None`; then
+    /// call again with the next leaf or, at the end of the text, call
+    /// `finish`.
+    pub fn next(&mut self, s: &str, leaf_start: usize) -> Option<usize> {
+        loop {
+            if let Some(bk) = self.pop() {
+                return Some(bk);
+            }
+            let ix = self.ix - leaf_start;
+            if ix >= s.len() {
+                return None;
+            }
+            let (wb, len) = wordbreak_property_str(s, ix);
+            // WB3c only needs this after a ZWJ
+            let is_pictographic = self.prev_raw == WB_ZWJ &&
+                grapheme_property_str(s, ix).0 == GB_EXTENDED_PICTOGRAPHIC;
+            let pos = self.ix;
+            self.ix += len;
+            self.step(pos, wb, is_pictographic);
+        }
+    }
+
+    /// At the end of the text, return a boundary that was held back waiting
+    /// for the next codepoint, if any. The end of the text is always a
+    /// boundary; it's up to the caller to report it.
+    pub fn finish(&mut self) -> Option<usize> {
+        self.fail_pending();
+        self.pop()
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        let bk = self.ready[0].take();
+        self.ready[0] = self.ready[1].take();
+        bk
+    }
+
+    fn push(&mut self, bk: usize) {
+        if self.ready[0].is_none() {
+            self.ready[0] = Some(bk);
+        } else {
+            self.ready[1] = Some(bk);
+        }
+    }
+
+    // The codepoint after the pending break didn't match.
+    fn fail_pending(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            if pending.is_break {
+                self.push(pending.pos);
+            }
+        }
+    }
+
+    // Decide whether there's a break before the codepoint at pos.
+    fn step(&mut self, pos: usize, wb: u8, is_pictographic: bool) {
+        let prev_raw = self.prev_raw;
+        self.prev_raw = wb;
+        if prev_raw == WB_SOT {
+            // WB1, no break at the start
+            self.prev = wb;
+            self.ri_odd = wb == WB_REGIONAL_INDICATOR;
+            return;
+        }
+        if is_newline(prev_raw) || is_newline(wb) {
+            // WB3, WB3a, WB3b
+            self.fail_pending();
+            if !(prev_raw == WB_CR && wb == WB_LF) {
+                self.push(pos);
+            }
+            self.prev = wb;
+            self.ri_odd = false;
+            return;
+        }
+        if is_pictographic || (prev_raw == WB_WSEGSPACE && wb == WB_WSEGSPACE) {
+            // WB3c, WB3d
+            self.fail_pending();
+            self.prev = wb;
+            self.ri_odd = false;
+            return;
+        }
+        if wb == WB_EXTEND || wb == WB_FORMAT || wb == WB_ZWJ {
+            // WB4
+            return;
+        }
+        let prev = self.prev;
+        let ri_odd = self.ri_odd;
+        self.prev = wb;
+        self.ri_odd = wb == WB_REGIONAL_INDICATOR && !(prev == WB_REGIONAL_INDICATOR && ri_odd);
+        if let Some(pending) = self.pending {
+            if pending.matches(wb) {
+                // WB6, WB7, WB7b, WB7c, WB11, WB12
+                self.pending = None;
+                return;
+            }
+            self.fail_pending();
+        }
+        let pair = WORDBREAK_PAIR_TABLE[(prev as usize) * N_WORDBREAK_CATEGORIES + (wb as usize)];
+        match pair {
+            PAIR_NO_BREAK => (),
+            PAIR_BREAK => self.push(pos),
+            PAIR_REGIONAL_INDICATOR => if !ri_odd {
+                self.push(pos);
+            },
+            PAIR_MID_LETTER | PAIR_MID_NUM | PAIR_DOUBLE_QUOTE | PAIR_HEBREW_SINGLE_QUOTE => {
+                let needs = match pair {
+                    PAIR_MID_NUM => WB_NUMERIC,
+                    PAIR_DOUBLE_QUOTE => WB_HEBREW_LETTER,
+                    _ => WB_ALETTER,
+                };
+                self.pending = Some(Pending {
+                    pos: pos,
+                    needs: needs,
+                    is_break: pair != PAIR_HEBREW_SINGLE_QUOTE,
+                });
+            }
+            _ => panic!("unexpected word break pair table entry {}", pair),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wordbreak_property;
+    use wordbreak_property_str;
+    use WordBreakIterator;
+    use WordBreakLeafIter;
+
+    fn breaks(s: &str) -> Vec<usize> {
+        WordBreakIterator::new(s).collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn wordbreak_prop() {
+        assert_eq!( 0, wordbreak_property('!'));
+        assert_eq!( 1, wordbreak_property('\r'));
+        assert_eq!( 2, wordbreak_property('\n'));
+        assert_eq!( 3, wordbreak_property('\u{2028}'));
+        assert_eq!( 4, wordbreak_property('\u{0301}'));
+        assert_eq!( 5, wordbreak_property('\u{200D}'));
+        assert_eq!( 6, wordbreak_property('\u{1F1FA}'));
+        assert_eq!( 7, wordbreak_property('\u{00AD}'));
+        assert_eq!( 8, wordbreak_property('\u{30A2}'));
+        assert_eq!( 9, wordbreak_property('\u{05D0}'));
+        assert_eq!(10, wordbreak_property('a'));
+        assert_eq!(11, wordbreak_property('\''));
+        assert_eq!(12, wordbreak_property('"'));
+        assert_eq!(13, wordbreak_property('.'));
+        assert_eq!(14, wordbreak_property(':'));
+        assert_eq!(15, wordbreak_property(','));
+        assert_eq!(16, wordbreak_property('7'));
+        assert_eq!(17, wordbreak_property('_'));
+        assert_eq!(18, wordbreak_property(' '));
+        assert_eq!( 0, wordbreak_property('\u{4E00}'));
+
+        assert_eq!((4, 2), wordbreak_property_str(&"\u{0301}", 0));
+        assert_eq!((8, 3), wordbreak_property_str(&"\u{30A2}", 0));
+        assert_eq!((6, 4), wordbreak_property_str(&"\u{1F1FA}", 0));
+    }
+
+    #[test]
+    fn wb_iter_simple() {
+        assert_eq!(vec![5, 6, 11], breaks("hello world"));
+        // WB3d
+        assert_eq!(vec![1, 4, 5], breaks("a   b"));
+        // WB3, WB3a, WB3b
+        assert_eq!(vec![1, 3, 4], breaks("a\r\nb"));
+        // WB4
+        assert_eq!(vec![3, 4, 5], breaks("e\u{0301} f"));
+        // WB6, WB7
+        assert_eq!(vec![5, 6, 8], breaks("can't go"));
+        assert_eq!(vec![3, 4], breaks("can'"));
+        assert_eq!(vec![3, 4, 5, 6], breaks("can' t"));
+        // WB11, WB12
+        assert_eq!(vec![4, 5, 6], breaks("3.14 x"));
+        assert_eq!(vec![1, 2, 3], breaks("3.a"));
+        // WB13a
+        assert_eq!(vec![10], breaks("snake_case"));
+        // WB3c
+        assert_eq!(vec![11], breaks("\u{1F468}\u{200D}\u{1F469}"));
+        // WB15, WB16
+        assert_eq!(vec![8, 16, 20], breaks("\u{1F1FA}\u{1F1F8}\u{1F1EB}\u{1F1F7}\u{1F1EC}"));
+        assert_eq!(Vec::<usize>::new(), breaks(""));
+    }
+
+    #[test]
+    fn wb_leaf_iter() {
+        // breaks held back across leaves come out in order
+        let s = "can't 3.14 e\u{0301}x";
+        for split in (1..s.len()).filter(|&i| s.is_char_boundary(i)) {
+            let leaves = [&s[..split], &s[split..]];
+            let mut iter = WordBreakLeafIter::new(0);
+            let mut result = Vec::new();
+            let mut leaf_start = 0;
+            for leaf in &leaves {
+                while let Some(bk) = iter.next(leaf, leaf_start) {
+                    result.push(bk);
+                }
+                leaf_start += leaf.len();
+            }
+            while let Some(bk) = iter.finish() {
+                result.push(bk);
+            }
+            result.push(s.len());
+            assert_eq!(breaks(s), result);
+        }
+    }
+}
@@ -17,7 +17,9 @@
 # efficiently queried.
 
 # Usage: python tools/mk_tables.py datadir > src/tables.rs
-# datadir should point to Unicode data, including LineBreak.txt
+# datadir should point to Unicode data, including LineBreak.txt,
+# EastAsianWidth.txt, GraphemeBreakProperty.txt and WordBreakProperty.txt
+# (from auxiliary/) and emoji-data.txt
 
 import os
 import sys
@@ -30,6 +32,23 @@ linebreak_assignments = ['XX', 'AI', 'AL', 'B2', 'BA', 'BB', 'BK', 'CB', 'CL',
 
 inv_lb_assigments = dict((val, i) for (i, val) in enumerate(linebreak_assignments))
 
+grapheme_assignments = ['Other', 'CR', 'LF', 'Control', 'Extend', 'ZWJ',
+'Regional_Indicator', 'Prepend', 'SpacingMark', 'L', 'V', 'T', 'LV', 'LVT',
+'Extended_Pictographic']
+
+inv_gb_assignments = dict((val, i) for (i, val) in enumerate(grapheme_assignments))
+
+wordbreak_assignments = ['Other', 'CR', 'LF', 'Newline', 'Extend', 'ZWJ',
+'Regional_Indicator', 'Format', 'Katakana', 'Hebrew_Letter', 'ALetter',
+'Single_Quote', 'Double_Quote', 'MidNumLet', 'MidLetter', 'MidNum', 'Numeric',
+'ExtendNumLet', 'WSegSpace']
+
+inv_wb_assignments = dict((val, i) for (i, val) in enumerate(wordbreak_assignments))
+
+eaw_assignments = ['N', 'A', 'H', 'F', 'Na', 'W']
+
+inv_eaw_assignments = dict((val, i) for (i, val) in enumerate(eaw_assignments))
+
 def gen_data(data, width=80):
     line = ''
     for val in data:
@@ -64,46 +83,89 @@ def compute_trie2(rawdata, midsize, leafsize):
     (root, midnodes) = compute_trie(mid, midsize)
     return (root, midnodes, leaves)
 
-def load_unicode_props(datadir, fn):
+# Read property values from a Unicode data file into props, returning the
+# name of the file as given in its first line. Only the values in `values`
+# are recorded, if given.
+def read_unicode_file(datadir, fn, props, values = None):
     f = open(os.path.join(datadir, fn))
 
-    lb = ['XX'] * 0x110000;
+    orig = fn
     did_notice = False
 
     for line in f:
         if line.startswith('#'):
             if not did_notice:
                 orig = line.split('#', 1)[1].strip()
-                print "// This file autogenerated from %s by mk_tables.py" % orig
-                print
                 did_notice = True
-        s = line.split(' ')[0].split(';')
-        if len(s) == 2:
+        s = [field.strip() for field in line.split('#')[0].split(';')]
+        if len(s) == 2 and (values is None or s[1] in values):
             t = s[0].split('..')
             lo = int(t[0], 16)
             hi = int(t[-1], 16) + 1
             for cp in range(lo, hi):
-                lb[cp] = s[1]
+                props[cp] = s[1]
+    return orig
+
+def load_unicode_props(datadir, fn):
+    lb = ['XX'] * 0x110000;
+    orig = read_unicode_file(datadir, fn, lb)
 
     numeric_lb = [inv_lb_assigments[lb[cp]] for cp in range(0x110000)]
-    return numeric_lb
+    return (numeric_lb, orig)
+
+def load_grapheme_props(datadir):
+    gb = ['Other'] * 0x110000;
+    orig = read_unicode_file(datadir, 'GraphemeBreakProperty.txt', gb)
+    orig_emoji = read_unicode_file(datadir, 'emoji-data.txt', gb,
+        ['Extended_Pictographic'])
+
+    numeric_gb = [inv_gb_assignments[gb[cp]] for cp in range(0x110000)]
+    return (numeric_gb, [orig, orig_emoji])
+
+def load_wordbreak_props(datadir):
+    wb = ['Other'] * 0x110000;
+    orig = read_unicode_file(datadir, 'WordBreakProperty.txt', wb)
+
+    numeric_wb = [inv_wb_assignments[wb[cp]] for cp in range(0x110000)]
+    return (numeric_wb, orig)
 
-def mk_linebreak_props(datadir):
-    numeric_lb = load_unicode_props(datadir, 'LineBreak.txt')
+def load_eaw_props(datadir):
+    eaw = ['N'] * 0x110000;
+    orig = read_unicode_file(datadir, 'EastAsianWidth.txt', eaw)
 
+    numeric_eaw = [inv_eaw_assignments[eaw[cp]] for cp in range(0x110000)]
+    return (numeric_eaw, orig)
+
+def mk_trie_tables(prefix, numeric):
     # generate table for 1 and 2 byte utf-8, direct lookup
-    gen_table('LINEBREAK_1_2', 'u8', numeric_lb[0:0x800])
+    gen_table(prefix + '_1_2', 'u8', numeric[0:0x800])
+
+    (root3, child3) = compute_trie(numeric[0x800:0x10000], 0x40)
+    gen_table(prefix + '_3_ROOT', 'u8', [255] * 32 + root3);
+    gen_table(prefix + '_3_CHILD', 'u8', child3);
+
+    (root4, mid4, leaves4) = compute_trie2(numeric[0x10000:], 0x40, 0x40)
+    gen_table(prefix + '_4_ROOT', 'u8', [255] * 16 + root4);
+    gen_table(prefix + '_4_MID', 'u8', mid4);
+    gen_table(prefix + '_4_LEAVES', 'u8', leaves4);
+
+def mk_linebreak_props(numeric_lb):
+    mk_trie_tables('LINEBREAK', numeric_lb)
 
-    (root3, child3) = compute_trie(numeric_lb[0x800:0x10000], 0x40)
-    gen_table('LINEBREAK_3_ROOT', 'u8', [255] * 32 + root3);
-    gen_table('LINEBREAK_3_CHILD', 'u8', child3);
+def mk_grapheme_props(numeric_gb):
+    mk_trie_tables('GRAPHEME', numeric_gb)
 
-    (root4, mid4, leaves4) = compute_trie2(numeric_lb[0x10000:], 0x40, 0x40)
-    gen_table('LINEBREAK_4_ROOT', 'u8', [255] * 16 + root4);
-    gen_table('LINEBREAK_4_MID', 'u8', mid4);
-    gen_table('LINEBREAK_4_LEAVES', 'u8', leaves4);
+def mk_wordbreak_props(numeric_wb):
+    mk_trie_tables('WORDBREAK', numeric_wb)
+
+def mk_eaw_props(numeric_eaw):
+    mk_trie_tables('EAW', numeric_eaw)
 
 def mk_tables(datadir):
+    (numeric_lb, orig_lb) = load_unicode_props(datadir, 'LineBreak.txt')
+    (numeric_gb, orig_gb) = load_grapheme_props(datadir)
+    (numeric_wb, orig_wb) = load_wordbreak_props(datadir)
+    (numeric_eaw, orig_eaw) = load_eaw_props(datadir)
     print """// Copyright 2016 Google Inc. All rights reserved.
 //
 // Licensed under the Apache License, Version 2.0 (the "License");
@@ -118,13 +180,24 @@ def mk_tables(datadir):
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Raw trie data for linebreak property lookup.
+//! Raw trie data for linebreak, grapheme break, word break and East Asian
+//! Width property lookup.
 """
-    mk_linebreak_props(datadir)
+    origs = [orig_lb] + orig_gb + [orig_wb, orig_eaw]
+    print "// This file autogenerated by mk_tables.py from:"
+    for orig in origs:
+        print "//     %s" % orig
+    print
+    mk_linebreak_props(numeric_lb)
     mk_lb_rules()
+    mk_grapheme_props(numeric_gb)
+    mk_gb_rules()
+    mk_wordbreak_props(numeric_wb)
+    mk_wb_rules()
+    mk_eaw_props(numeric_eaw)
 
 def mk_tests(datadir, do_str = False):
-    numeric_lb = load_unicode_props(datadir, 'LineBreak.txt')
+    (numeric_lb, _) = load_unicode_props(datadir, 'LineBreak.txt')
     ranges = (range(0x80), range(0x80, 0x800), range(0x800, 0x10000), range(0x10000, 0x110000))
     for r in ranges:
         for cp in sorted(random.sample(r, 32)):
@@ -384,6 +457,129 @@ def mk_lb_rules():
         gen_data(sm[state])
     print '];'
 
+def mk_gb_rules():
+    # Rules derived from UAX #29, for extended grapheme clusters
+
+    t = {}
+    Any = grapheme_assignments
+
+    # GB1, GB2: handled in code
+
+    # GB3
+    update(t, 'CR', 'LF', 'x')
+
+    # GB4
+    update(t, ['Control', 'CR', 'LF'], Any, '_')
+
+    # GB5
+    update(t, Any, ['Control', 'CR', 'LF'], '_')
+
+    # GB6
+    update(t, 'L', ['L', 'V', 'LV', 'LVT'], 'x')
+
+    # GB7
+    update(t, ['LV', 'V'], ['V', 'T'], 'x')
+
+    # GB8
+    update(t, ['LVT', 'T'], 'T', 'x')
+
+    # GB9
+    update(t, Any, ['Extend', 'ZWJ'], 'x')
+
+    # GB9a
+    update(t, Any, 'SpacingMark', 'x')
+
+    # GB9b
+    update(t, 'Prepend', Any, 'x')
+
+    # GB11: needs look-behind for Extended_Pictographic Extend*, handled in code
+    update(t, 'ZWJ', 'Extended_Pictographic', 'EP')
+
+    # GB12, GB13: needs look-behind to count RI, handled in code
+    update(t, 'Regional_Indicator', 'Regional_Indicator', 'RI')
+
+    # GB999
+    update(t, Any, Any, '_')
+
+    # table is indexed by left * n + right
+    # 0: no break, 1: break, 2: RI pair rules, 3: emoji ZWJ sequence rule
+    n = len(grapheme_assignments)
+    bk_to_code = {'x': 0, '_': 1, 'RI': 2, 'EP': 3}
+    print 'pub const N_GRAPHEME_CATEGORIES: usize = %d;' % n
+    print 'pub const GRAPHEME_PAIR_TABLE: [u8; %d] = [' % (n * n)
+    for left in range(n):
+        L = grapheme_assignments[left]
+        print '    // left: %s' % L
+        gen_data([bk_to_code[t[L + '|' + R]] for R in grapheme_assignments])
+    print '];'
+
+def mk_wb_rules():
+    # Rules derived from UAX #29, for word boundaries
+
+    t = {}
+    Any = wordbreak_assignments
+    AHLetter = ['ALetter', 'Hebrew_Letter']
+    MidNumLetQ = ['MidNumLet', 'Single_Quote']
+
+    # WB1, WB2: handled in code
+    # WB3, WB3a, WB3b, WB3c, WB3d: handled in code, as they look at the
+    # codepoint before any ignored by WB4
+    # WB4: handled in code
+
+    # WB5
+    update(t, AHLetter, AHLetter, 'x')
+
+    # WB7a: takes priority over WB6, as it doesn't depend on what follows
+    update(t, 'Hebrew_Letter', 'Single_Quote', 'HLSQ')
+
+    # WB6, WB7: needs look-ahead for AHLetter, handled in code
+    update(t, AHLetter, ['MidLetter'] + MidNumLetQ, 'ML')
+
+    # WB7b, WB7c: needs look-ahead for Hebrew_Letter, handled in code
+    update(t, 'Hebrew_Letter', 'Double_Quote', 'DQ')
+
+    # WB8
+    update(t, 'Numeric', 'Numeric', 'x')
+
+    # WB9
+    update(t, AHLetter, 'Numeric', 'x')
+
+    # WB10
+    update(t, 'Numeric', AHLetter, 'x')
+
+    # WB11, WB12: needs look-ahead for Numeric, handled in code
+    update(t, 'Numeric', ['MidNum'] + MidNumLetQ, 'MN')
+
+    # WB13
+    update(t, 'Katakana', 'Katakana', 'x')
+
+    # WB13a
+    update(t, AHLetter + ['Numeric', 'Katakana', 'ExtendNumLet'], 'ExtendNumLet', 'x')
+
+    # WB13b
+    update(t, 'ExtendNumLet', AHLetter + ['Numeric', 'Katakana'], 'x')
+
+    # WB15, WB16: needs look-behind to count RI, handled in code
+    update(t, 'Regional_Indicator', 'Regional_Indicator', 'RI')
+
+    # WB999
+    update(t, Any, Any, '_')
+
+    # table is indexed by left * n + right, where left ignores codepoints
+    # skipped by WB4
+    # 0: no break, 1: break, 2: RI pair rules, 3: break unless AHLetter
+    # follows, 4: break unless Numeric follows, 5: break unless Hebrew_Letter
+    # follows, 6: no break, and the next break is as for 3
+    n = len(wordbreak_assignments)
+    bk_to_code = {'x': 0, '_': 1, 'RI': 2, 'ML': 3, 'MN': 4, 'DQ': 5, 'HLSQ': 6}
+    print 'pub const N_WORDBREAK_CATEGORIES: usize = %d;' % n
+    print 'pub const WORDBREAK_PAIR_TABLE: [u8; %d] = [' % (n * n)
+    for left in range(n):
+        L = wordbreak_assignments[left]
+        print '    // left: %s' % L
+        gen_data([bk_to_code[t[L + '|' + R]] for R in wordbreak_assignments])
+    print '];'
+
 def main():
     datadir = sys.argv[1]
     if len(sys.argv) == 3 and sys.argv[2] == '--tests':